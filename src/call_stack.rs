@@ -0,0 +1,260 @@
+use crate::hack_cpu::CPUState;
+use crate::instructions::{Instruction, Jump};
+use crate::memory_map::{ARGUMENT_POINTER, LOCAL_POINTER, STACK_BASE, STACK_END};
+use crate::MAX_INSTRUCTIONS;
+
+/// The words the calling convention of the VM pushes below the locals of a function: the return
+/// address, and the saved LCL, ARG, THIS and THAT.
+const SAVED_FRAME_LENGTH: usize = 5;
+/// The most frames that are reconstructed, which stops runaway recursion from making the call
+/// stack slow to build.
+const MAX_FRAMES: usize = 1024;
+/// Separates a function from the labels within it, such as `Main.main$LOOP`, or the return
+/// addresses of its calls, such as `Main.main$ret.0`, in the labels of VM translators.
+const LABEL_SEPARATOR: char = '$';
+/// Separates the class of a function from its name, such as `Main.main`.
+const CLASS_SEPARATOR: char = '.';
+
+/// An active function call.
+#[derive(Debug)]
+pub struct Frame {
+    /// The name of the function, from the label that starts it, if there is one.
+    pub function: String,
+    /// The ROM address being run in the frame, which is the return address of the call it made
+    /// for all but the innermost frame.
+    pub address: u16,
+    /// The values of LCL and ARG within the frame.
+    pub local: u16,
+    pub argument: u16,
+}
+
+/// Reconstructs the call stack of a program translated from VM code, from the innermost frame to
+/// the outermost. Each call following the standard calling convention saves the return address
+/// and the pointers of the caller right below the locals of the callee, so the frames are found
+/// by following the saved LCL values down the stack. Frames that cannot have been saved by a call
+/// end the search, so programs not using the convention only have the innermost frame.
+pub fn reconstruct(cpu: &CPUState, instructions: &[Instruction; MAX_INSTRUCTIONS]) -> Vec<Frame> {
+    let functions = functions(cpu);
+    let mut frames = vec![Frame {
+        function: function_at(&functions, cpu.pc),
+        address: cpu.pc,
+        local: cpu.ram[LOCAL_POINTER].0 as u16,
+        argument: cpu.ram[ARGUMENT_POINTER].0 as u16,
+    }];
+    walk_saved_frames(cpu, instructions, |return_address, local, argument| {
+        frames.push(Frame {
+            function: function_at(&functions, return_address),
+            address: return_address,
+            local,
+            argument,
+        })
+    });
+    frames
+}
+
+/// The number of frames [reconstruct] would find, without naming their functions, which is cheap
+/// enough to check after every instruction.
+pub fn depth(cpu: &CPUState, instructions: &[Instruction; MAX_INSTRUCTIONS]) -> usize {
+    let mut depth = 1;
+    walk_saved_frames(cpu, instructions, |_, _, _| depth += 1);
+    depth
+}
+
+/// The address the function being run returns to, if it was called following the convention.
+pub fn return_address(
+    cpu: &CPUState,
+    instructions: &[Instruction; MAX_INSTRUCTIONS],
+) -> Option<u16> {
+    let mut address = None;
+    walk_saved_frames(cpu, instructions, |return_address, _, _| {
+        address.get_or_insert(return_address);
+    });
+    address
+}
+
+/// Follows the saved LCL values down the stack, calling `visit` with the return address, LCL and
+/// ARG saved by each call, from the innermost call to the outermost.
+fn walk_saved_frames(
+    cpu: &CPUState,
+    instructions: &[Instruction; MAX_INSTRUCTIONS],
+    mut visit: impl FnMut(u16, u16, u16),
+) {
+    let word = |address: usize| cpu.ram[address].0 as u16;
+    let mut local = word(LOCAL_POINTER) as usize;
+    let mut frames = 1;
+    while frames < MAX_FRAMES && (STACK_BASE + SAVED_FRAME_LENGTH..=STACK_END + 1).contains(&local)
+    {
+        let saved = local - SAVED_FRAME_LENGTH;
+        let return_address = word(saved);
+        let caller_local = word(saved + 1);
+        if !is_return_address(instructions, return_address) || caller_local as usize >= local {
+            break;
+        }
+        visit(return_address, caller_local, word(saved + 2));
+        frames += 1;
+        local = caller_local as usize;
+    }
+}
+
+/// Whether the address follows an unconditional jump, as a return address follows the jump into
+/// the function being called.
+fn is_return_address(instructions: &[Instruction; MAX_INSTRUCTIONS], address: u16) -> bool {
+    let Some(call) = (address as usize)
+        .checked_sub(1)
+        .and_then(|call| instructions.get(call))
+    else {
+        return false;
+    };
+    matches!(call, Instruction::C(c) if c.jump == Jump::JMP)
+}
+
+/// The labels that start functions, sorted by address. Labels in the `Class.function` form of VM
+/// translators are preferred, but any label that is not within a function is used otherwise.
+fn functions(cpu: &CPUState) -> Vec<(u16, &str)> {
+    let labels: Vec<(u16, &str)> = cpu
+        .address_table
+        .labels_by_address()
+        .into_iter()
+        .filter(|(_, label)| !label.contains(LABEL_SEPARATOR))
+        .collect();
+    let functions: Vec<(u16, &str)> = labels
+        .iter()
+        .filter(|(_, label)| label.contains(CLASS_SEPARATOR))
+        .copied()
+        .collect();
+    if functions.is_empty() {
+        labels
+    } else {
+        functions
+    }
+}
+
+/// Names the function holding the address, which is the last to start at or before it.
+fn function_at(functions: &[(u16, &str)], address: u16) -> String {
+    functions
+        .iter()
+        .take_while(|(start, _)| *start <= address)
+        .last()
+        .map_or(format!("ROM[{address}]"), |(_, name)| name.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instructions::C;
+    use crate::test::on_large_stack;
+    use std::num::Wrapping;
+
+    /// Where each function of the hand-built program starts. `Sys.init` calls `Main.main` with a
+    /// jump at 4, and `Main.main` calls `Main.f` with a jump at 14.
+    const FUNCTIONS: [(&'static str, u16); 3] =
+        [("Sys.init", 0), ("Main.main", 10), ("Main.f", 20)];
+    const CALLS: [usize; 2] = [4, 14];
+
+    fn program(labels: &[(&str, u16)]) -> (CPUState, [Instruction; MAX_INSTRUCTIONS]) {
+        let mut cpu = CPUState::new();
+        for (label, address) in labels {
+            cpu.address_table.table.insert(label.to_string(), *address);
+            cpu.address_table.labels.insert(label.to_string());
+        }
+        let mut instructions = [const { Instruction::None }; MAX_INSTRUCTIONS];
+        for call in CALLS {
+            instructions[call] = Instruction::C(C::new("", "0", "JMP").unwrap());
+        }
+        (cpu, instructions)
+    }
+
+    /// Writes the words a call saves below the locals of the function called.
+    fn save_frame(cpu: &mut CPUState, at: usize, words: [i16; SAVED_FRAME_LENGTH]) {
+        for (i, word) in words.into_iter().enumerate() {
+            cpu.ram[at + i] = Wrapping(word);
+        }
+    }
+
+    fn summary(frames: &[Frame]) -> Vec<(&str, u16, u16, u16)> {
+        frames
+            .iter()
+            .map(|f| (f.function.as_str(), f.address, f.local, f.argument))
+            .collect()
+    }
+
+    #[test]
+    fn nested_calls() {
+        on_large_stack(|| {
+            let (mut cpu, instructions) = program(&FUNCTIONS);
+            // Sys.init has its locals at 256, and calls Main.main, which has its own at 261
+            save_frame(&mut cpu, 256, [5, 256, 250, 3000, 4000]);
+            // Main.main pushes two locals, and an argument, before it calls Main.f
+            save_frame(&mut cpu, 264, [15, 261, 256, 3001, 4001]);
+            cpu.ram[LOCAL_POINTER] = Wrapping(269);
+            cpu.ram[ARGUMENT_POINTER] = Wrapping(263);
+            cpu.pc = 22;
+
+            assert_eq!(
+                summary(&reconstruct(&cpu, &instructions)),
+                [
+                    ("Main.f", 22, 269, 263),
+                    ("Main.main", 15, 261, 256),
+                    ("Sys.init", 5, 256, 250),
+                ]
+            );
+            assert_eq!(depth(&cpu, &instructions), 3);
+            assert_eq!(return_address(&cpu, &instructions), Some(15));
+        });
+    }
+
+    #[test]
+    fn labels_within_functions_do_not_name_frames() {
+        on_large_stack(|| {
+            let mut labels = FUNCTIONS.to_vec();
+            labels.push(("Main.main$ret.0", 15));
+            labels.push(("Main.main$LOOP", 12));
+            let (mut cpu, instructions) = program(&labels);
+            save_frame(&mut cpu, 256, [15, 256, 256, 0, 0]);
+            cpu.ram[LOCAL_POINTER] = Wrapping(261);
+            cpu.pc = 21;
+
+            let frames = reconstruct(&cpu, &instructions);
+            assert_eq!(frames[0].function, "Main.f");
+            assert_eq!(frames[1].function, "Main.main");
+        });
+    }
+
+    #[test]
+    fn programs_without_the_convention_have_one_frame() {
+        on_large_stack(|| {
+            let (mut cpu, instructions) = program(&[("LOOP", 3), ("END", 8)]);
+            cpu.pc = 5;
+            let frames = reconstruct(&cpu, &instructions);
+            assert_eq!(summary(&frames), [("LOOP", 5, 0, 0)]);
+            assert_eq!(depth(&cpu, &instructions), 1);
+            assert_eq!(return_address(&cpu, &instructions), None);
+
+            // A frame is only followed if its return address comes after a jump
+            save_frame(&mut cpu, 256, [6, 256, 256, 0, 0]);
+            cpu.ram[LOCAL_POINTER] = Wrapping(261);
+            assert_eq!(depth(&cpu, &instructions), 1);
+
+            cpu.pc = 1;
+            assert_eq!(reconstruct(&cpu, &instructions)[0].function, "ROM[1]");
+        });
+    }
+
+    #[test]
+    fn saved_frames_must_be_further_down_the_stack() {
+        on_large_stack(|| {
+            let (mut cpu, instructions) = program(&FUNCTIONS);
+            // A frame that saved its own LCL would otherwise be followed forever
+            save_frame(&mut cpu, 256, [5, 261, 256, 0, 0]);
+            cpu.ram[LOCAL_POINTER] = Wrapping(261);
+            assert_eq!(depth(&cpu, &instructions), 1);
+
+            // Two frames that saved each other's LCL end at the one that points up the stack
+            save_frame(&mut cpu, 256, [5, 269, 256, 0, 0]);
+            save_frame(&mut cpu, 264, [15, 261, 256, 0, 0]);
+            cpu.ram[LOCAL_POINTER] = Wrapping(269);
+            assert_eq!(depth(&cpu, &instructions), 2);
+            assert_eq!(return_address(&cpu, &instructions), Some(15));
+        });
+    }
+}
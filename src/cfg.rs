@@ -0,0 +1,282 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::instructions::{Instruction, Jump};
+use crate::symbol_table::SymbolTable;
+use crate::MAX_INSTRUCTIONS;
+
+pub const DOT_FILE_EXTENSION: &'static str = "dot";
+
+/// A run of instructions that is only entered at its first instruction, and only left after its
+/// last. The blocks are identified by the ROM address of their first instruction.
+#[derive(Debug)]
+pub struct BasicBlock {
+    pub start: u16,
+    /// The address after the last instruction of the block.
+    pub end: u16,
+    /// The blocks that may run after this one. The end of the program stands for leaving the ROM.
+    pub successors: Vec<u16>,
+    pub predecessors: Vec<u16>,
+    /// Whether the block ends with a jump to an address that is only known at run time, such as
+    /// after `A=M`.
+    pub indirect: bool,
+}
+
+/// The basic blocks of a program, and the jumps between them. Jump targets are resolved from the
+/// A instruction right before each jumping C instruction.
+#[derive(Debug)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    /// The address after the last instruction of the program.
+    pub end: u16,
+}
+
+impl ControlFlowGraph {
+    /// Splits a program into basic blocks, starting new blocks at every label and jump target,
+    /// and after every jump.
+    pub fn new(
+        instructions: &[Instruction; MAX_INSTRUCTIONS],
+        address_table: &SymbolTable,
+    ) -> Self {
+        let end = instructions
+            .iter()
+            .rposition(|instruction| *instruction != Instruction::None)
+            .map_or(0, |last| last as u16 + 1);
+        if end == 0 {
+            return Self {
+                blocks: vec![],
+                end,
+            };
+        }
+
+        let mut starts = BTreeSet::from([0]);
+        for (address, _) in address_table.labels_by_address() {
+            starts.insert(address.min(end));
+        }
+        for address in 0..end {
+            if jump(instructions, address).is_some() {
+                starts.insert(address + 1);
+                if let Some(target) = target(instructions, address) {
+                    starts.insert(target.min(end));
+                }
+            }
+        }
+        starts.remove(&end);
+
+        let starts: Vec<u16> = starts.into_iter().collect();
+        let mut blocks: Vec<BasicBlock> = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| BasicBlock {
+                start,
+                end: starts.get(i + 1).copied().unwrap_or(end),
+                successors: vec![],
+                predecessors: vec![],
+                indirect: false,
+            })
+            .collect();
+
+        for block in blocks.iter_mut() {
+            let last = block.end - 1;
+            let mut successors = vec![];
+            match jump(instructions, last) {
+                Some(jump) => {
+                    // A target set before the block starts may not be the one that is jumped to
+                    match target(instructions, last).filter(|_| last > block.start) {
+                        Some(target) => successors.push(target.min(end)),
+                        None => block.indirect = true,
+                    }
+                    if *jump != Jump::JMP {
+                        successors.push(block.end);
+                    }
+                }
+                None => successors.push(block.end),
+            }
+            successors.dedup();
+            block.successors = successors;
+        }
+
+        let index: HashMap<u16, usize> = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (block.start, i))
+            .collect();
+        for i in 0..blocks.len() {
+            for successor in blocks[i].successors.clone() {
+                if let Some(&j) = index.get(&successor) {
+                    let start = blocks[i].start;
+                    blocks[j].predecessors.push(start);
+                }
+            }
+        }
+        Self { blocks, end }
+    }
+
+    /// Names a block by its labels, or by its address if it has none.
+    pub fn name(self: &Self, start: u16, address_table: &SymbolTable) -> String {
+        if start == self.end {
+            return String::from("end");
+        }
+        let labels: Vec<&str> = address_table
+            .labels_by_address()
+            .into_iter()
+            .filter(|(address, _)| *address == start)
+            .map(|(_, label)| label)
+            .collect();
+        if labels.is_empty() {
+            format!("ROM[{start}]")
+        } else {
+            labels.join(", ")
+        }
+    }
+
+    /// Exports the graph in the Graphviz DOT format, listing the instructions of every block, with
+    /// A instructions written using the `symbols` they were written with, by ROM address.
+    pub fn to_dot(
+        self: &Self,
+        instructions: &[Instruction; MAX_INSTRUCTIONS],
+        symbols: &HashMap<u16, String>,
+        address_table: &SymbolTable,
+    ) -> String {
+        let mut dot = String::new();
+        let _ = writeln!(dot, "digraph cfg {{");
+        let _ = writeln!(dot, "    node [shape=box, fontname=\"monospace\"];");
+        if self
+            .blocks
+            .iter()
+            .any(|block| block.successors.contains(&self.end))
+        {
+            let _ = writeln!(dot, "    end [shape=oval, label=\"end of ROM\"];");
+        }
+        if self.blocks.iter().any(|block| block.indirect) {
+            let _ = writeln!(dot, "    indirect [shape=oval, label=\"address in A\"];");
+        }
+        for block in &self.blocks {
+            let mut label = format!("{}\\l", self.name(block.start, address_table));
+            for address in block.start..block.end {
+                let instruction = match symbols.get(&address) {
+                    Some(symbol) => format!("@{symbol}"),
+                    None => instructions[address as usize].to_string(),
+                };
+                let _ = write!(label, "{address:>5}: {instruction}\\l");
+            }
+            let _ = writeln!(dot, "    b{} [label=\"{label}\"];", block.start);
+        }
+        for block in &self.blocks {
+            let last = block.end - 1;
+            // The taken branch of a conditional jump is labelled with its condition
+            let condition = jump(instructions, last).filter(|jump| **jump != Jump::JMP);
+            let taken = target(instructions, last).filter(|_| !block.indirect);
+            for successor in &block.successors {
+                let node = if *successor == self.end {
+                    String::from("end")
+                } else {
+                    format!("b{successor}")
+                };
+                match condition.filter(|_| taken.map(|t| t.min(self.end)) == Some(*successor)) {
+                    Some(condition) => {
+                        let _ = writeln!(
+                            dot,
+                            "    b{} -> {node} [label=\"{condition}\"];",
+                            block.start
+                        );
+                    }
+                    None => {
+                        let _ = writeln!(dot, "    b{} -> {node};", block.start);
+                    }
+                }
+            }
+            if block.indirect {
+                let _ = writeln!(dot, "    b{} -> indirect [style=dashed];", block.start);
+            }
+        }
+        let _ = writeln!(dot, "}}");
+        dot
+    }
+}
+
+/// The jump of the instruction at the address, if it is a C instruction that jumps.
+fn jump(instructions: &[Instruction; MAX_INSTRUCTIONS], address: u16) -> Option<&Jump> {
+    match &instructions[address as usize] {
+        Instruction::C(c) if c.jump != Jump::None => Some(&c.jump),
+        _ => None,
+    }
+}
+
+/// The target of the jump at the address, which is known if the instruction before it is an A
+/// instruction.
+fn target(instructions: &[Instruction; MAX_INSTRUCTIONS], address: u16) -> Option<u16> {
+    match &instructions[address.checked_sub(1)? as usize] {
+        Instruction::A(a) => Some(a.dest as u16),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+    use crate::split_source;
+    use crate::test::on_large_stack;
+
+    /// The start, end, successors and predecessors of a block, and whether it ends with an
+    /// indirect jump.
+    type Block = (u16, u16, Vec<u16>, Vec<u16>, bool);
+
+    fn blocks(source: &str) -> Vec<Block> {
+        let mut address_table = SymbolTable::new();
+        let program = parse(split_source(source).unwrap(), &mut address_table).unwrap();
+        ControlFlowGraph::new(&program.instructions, &address_table)
+            .blocks
+            .into_iter()
+            .map(|b| (b.start, b.end, b.successors, b.predecessors, b.indirect))
+            .collect()
+    }
+
+    #[test]
+    fn blocks_split_at_labels_and_jumps() {
+        on_large_stack(|| {
+            let program =
+                "@0\nD=M\n@END\nD;JEQ\n(LOOP)\n@1\nM=D\n@LOOP\n0;JMP\n(END)\n@2\nA=M\n0;JMP";
+            assert_eq!(
+                blocks(program),
+                [
+                    (0, 4, vec![8, 4], vec![], false),
+                    (4, 8, vec![4], vec![0, 4], false),
+                    (8, 11, vec![], vec![0], true),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn blocks_fall_through() {
+        on_large_stack(|| {
+            assert_eq!(
+                blocks("@1\nD=A\n(NEXT)\nD=D+1"),
+                [
+                    (0, 2, vec![2], vec![], false),
+                    (2, 3, vec![3], vec![0], false)
+                ]
+            );
+            assert!(blocks("").is_empty());
+        });
+    }
+
+    #[test]
+    fn jumps_are_indirect_unless_their_target_is_in_the_block() {
+        on_large_stack(|| {
+            // The A register may be set elsewhere before jumping to TOP
+            assert_eq!(
+                blocks("@5\n(TOP)\nD;JGT\nD=0"),
+                [
+                    (0, 1, vec![1], vec![], false),
+                    (1, 2, vec![2], vec![0], true),
+                    (2, 3, vec![3], vec![1], false),
+                ]
+            );
+            // Targets past the end of the program leave the ROM
+            assert_eq!(blocks("@100\n0;JMP"), [(0, 2, vec![2], vec![], false)]);
+        });
+    }
+}
@@ -0,0 +1,282 @@
+use std::fmt::Write;
+
+use crate::instructions::{Instruction, Jump};
+use crate::MAX_INSTRUCTIONS;
+
+/// How much of a single ROM address has been exercised.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CoverageState {
+    NotExecuted,
+    /// A conditional jump that has only ever gone one way.
+    Partial,
+    Executed,
+}
+
+/// Records which ROM addresses have been executed, and which way each conditional jump went.
+/// Coverage is opt in, by placing a [Coverage] into the [crate::hack_cpu::CPUState].
+#[derive(Debug)]
+pub struct Coverage {
+    pub hits: Vec<u64>,
+    pub taken: Vec<u64>,
+    pub not_taken: Vec<u64>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            hits: vec![0; MAX_INSTRUCTIONS],
+            taken: vec![0; MAX_INSTRUCTIONS],
+            not_taken: vec![0; MAX_INSTRUCTIONS],
+        }
+    }
+
+    /// Records the execution of `instruction` at `pc`, after which the program counter was
+    /// `next_pc`.
+    pub fn record(self: &mut Self, pc: u16, instruction: &Instruction, next_pc: u16) {
+        let pc = pc as usize;
+        if pc >= MAX_INSTRUCTIONS {
+            return;
+        }
+        self.hits[pc] += 1;
+        if is_conditional_jump(instruction) {
+            if next_pc as usize == pc + 1 {
+                self.not_taken[pc] += 1;
+            } else {
+                self.taken[pc] += 1;
+            }
+        }
+    }
+
+    /// How much of the instruction at `address` has been exercised.
+    pub fn state(self: &Self, address: usize, instruction: &Instruction) -> CoverageState {
+        if self.hits[address] == 0 {
+            CoverageState::NotExecuted
+        } else if is_conditional_jump(instruction)
+            && (self.taken[address] == 0 || self.not_taken[address] == 0)
+        {
+            CoverageState::Partial
+        } else {
+            CoverageState::Executed
+        }
+    }
+
+    /// Produces an LCOV tracefile for the program at `source_path`. The `source_map` maps every
+    /// ROM address to the index of its source line, see [crate::parser::ParsedProgram].
+    pub fn lcov(
+        self: &Self,
+        source_path: &str,
+        source_map: &[usize],
+        instructions: &[Instruction; MAX_INSTRUCTIONS],
+    ) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "TN:");
+        let _ = writeln!(report, "SF:{source_path}");
+
+        let (mut lines_found, mut lines_hit) = (0, 0);
+        let mut last_line = None;
+        for (address, &line) in source_map.iter().enumerate() {
+            // Only the first instruction of every source line is reported
+            if last_line == Some(line) {
+                continue;
+            }
+            last_line = Some(line);
+            lines_found += 1;
+            if self.hits[address] > 0 {
+                lines_hit += 1;
+            }
+            let _ = writeln!(report, "DA:{},{}", line + 1, self.hits[address]);
+        }
+
+        let (mut branches_found, mut branches_hit) = (0, 0);
+        for (address, &line) in source_map.iter().enumerate() {
+            if !is_conditional_jump(&instructions[address]) {
+                continue;
+            }
+            for (branch, count) in [(0, self.taken[address]), (1, self.not_taken[address])] {
+                branches_found += 1;
+                let count = if self.hits[address] == 0 {
+                    String::from("-")
+                } else {
+                    if count > 0 {
+                        branches_hit += 1;
+                    }
+                    count.to_string()
+                };
+                let _ = writeln!(report, "BRDA:{},{},{},{}", line + 1, address, branch, count);
+            }
+        }
+
+        let _ = writeln!(report, "BRF:{branches_found}");
+        let _ = writeln!(report, "BRH:{branches_hit}");
+        let _ = writeln!(report, "LF:{lines_found}");
+        let _ = writeln!(report, "LH:{lines_hit}");
+        let _ = writeln!(report, "end_of_record");
+        report
+    }
+
+    /// Produces the source code annotated with the execution count of every line, in the style of
+    /// gcov. Lines with instructions that never ran are marked with `#####`, and conditional
+    /// jumps are followed by how often they were taken.
+    pub fn annotate(
+        self: &Self,
+        lines: &[String],
+        source_map: &[usize],
+        instructions: &[Instruction; MAX_INSTRUCTIONS],
+    ) -> String {
+        let mut first_address: Vec<Option<usize>> = vec![None; lines.len()];
+        for (address, &line) in source_map.iter().enumerate().rev() {
+            first_address[line] = Some(address);
+        }
+        let last_line = lines.iter().rposition(|l| !l.is_empty()).unwrap_or(0);
+
+        let mut report = String::new();
+        for (i, line) in lines.iter().enumerate().take(last_line + 1) {
+            let (count, branches) = match first_address[i] {
+                None => (String::from("-"), String::new()),
+                Some(address) => {
+                    let count = match self.hits[address] {
+                        0 => String::from("#####"),
+                        hits => hits.to_string(),
+                    };
+                    let branches = if is_conditional_jump(&instructions[address]) {
+                        format!(
+                            "    [taken {}, not taken {}]",
+                            self.taken[address], self.not_taken[address]
+                        )
+                    } else {
+                        String::new()
+                    };
+                    (count, branches)
+                }
+            };
+            let _ = writeln!(report, "{:>9}:{:>5}: {}{}", count, i + 1, line, branches);
+        }
+        report
+    }
+}
+
+/// Whether the instruction is a jump that depends on the result of its computation.
+fn is_conditional_jump(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::C(c) => !matches!(c.jump, Jump::None | Jump::JMP),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hack_cpu::CPUState;
+    use crate::parser::parse;
+    use crate::split_source;
+    use crate::test::on_large_stack;
+
+    /// Counts D down from 2 to 0, never takes the jump to END, and then loops at END, leaving the
+    /// last instruction unexecuted.
+    const PROGRAM: &'static str = "// Counts down\n@2\nD=A\n(LOOP)\n@1\nD=D-A\n@LOOP\nD;JGT\n@END\nD;JLT\n(END)\n@END\n0;JMP\n@0";
+    /// The instructions executed before the loop at END is reached, and then twice around it.
+    const CYCLES: usize = 16;
+
+    /// Runs the program, returning its coverage, source map, and instructions.
+    fn covered() -> (Coverage, Vec<usize>, Box<[Instruction; MAX_INSTRUCTIONS]>) {
+        let mut cpu = CPUState::new();
+        let program = parse(split_source(PROGRAM).unwrap(), &mut cpu.address_table).unwrap();
+        cpu.coverage = Some(Coverage::new());
+        for _ in 0..CYCLES {
+            cpu.interpret(&program.instructions[cpu.pc as usize]);
+        }
+        let coverage = cpu.coverage.take().unwrap();
+        (coverage, program.source_map, Box::new(program.instructions))
+    }
+
+    #[test]
+    fn states() {
+        on_large_stack(|| {
+            let (coverage, _, instructions) = covered();
+            let state = |address: usize| coverage.state(address, &instructions[address]);
+            assert_eq!(state(0), CoverageState::Executed);
+            assert_eq!(state(5), CoverageState::Executed);
+            assert_eq!(state(7), CoverageState::Partial);
+            assert_eq!(state(9), CoverageState::Executed);
+            assert_eq!(state(10), CoverageState::NotExecuted);
+        });
+    }
+
+    #[test]
+    fn lcov_reports_lines_and_branches() {
+        on_large_stack(|| {
+            let (coverage, source_map, instructions) = covered();
+            let expected = [
+                "TN:",
+                "SF:Main.asm",
+                "DA:2,1",
+                "DA:3,1",
+                "DA:5,2",
+                "DA:6,2",
+                "DA:7,2",
+                "DA:8,2",
+                "DA:9,1",
+                "DA:10,1",
+                "DA:12,2",
+                "DA:13,2",
+                "DA:14,0",
+                "BRDA:8,5,0,1",
+                "BRDA:8,5,1,1",
+                "BRDA:10,7,0,0",
+                "BRDA:10,7,1,1",
+                "BRF:4",
+                "BRH:3",
+                "LF:11",
+                "LH:10",
+                "end_of_record",
+            ];
+            let lcov = coverage.lcov("Main.asm", &source_map, &instructions);
+            assert_eq!(lcov.lines().collect::<Vec<&str>>(), expected);
+
+            // Branches that were never reached have no counts
+            let lcov = Coverage::new().lcov("Main.asm", &source_map, &instructions);
+            assert!(lcov.contains("BRDA:8,5,0,-\nBRDA:8,5,1,-\n"));
+            assert!(lcov.contains("BRH:0\nLF:11\nLH:0\n"));
+        });
+    }
+
+    #[test]
+    fn lcov_reports_each_line_once() {
+        on_large_stack(|| {
+            let (_, _, instructions) = covered();
+            let mut coverage = Coverage::new();
+            coverage.hits[1] = 3;
+            let lcov = coverage.lcov("Main.asm", &[0, 0, 1], &instructions);
+            assert!(lcov.contains("DA:1,0\nDA:2,0\n"));
+            assert!(lcov.contains("LF:2\nLH:0\n"));
+        });
+    }
+
+    #[test]
+    fn annotated_source() {
+        on_large_stack(|| {
+            let (coverage, source_map, instructions) = covered();
+            let lines: Vec<String> = PROGRAM.lines().map(String::from).collect();
+            let row =
+                |count: &str, line: usize| format!("{count:>9}:{line:>5}: {}", lines[line - 1]);
+            let expected = [
+                row("-", 1),
+                row("1", 2),
+                row("1", 3),
+                row("-", 4),
+                row("2", 5),
+                row("2", 6),
+                row("2", 7),
+                row("2", 8) + "    [taken 1, not taken 1]",
+                row("1", 9),
+                row("1", 10) + "    [taken 0, not taken 1]",
+                row("-", 11),
+                row("2", 12),
+                row("2", 13),
+                row("#####", 14),
+            ];
+            let annotated = coverage.annotate(&lines, &source_map, &instructions);
+            assert_eq!(annotated.lines().collect::<Vec<&str>>(), expected);
+        });
+    }
+}
@@ -0,0 +1,683 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::call_stack;
+use crate::debug::Breakpoint;
+use crate::hack_cpu::CPUState;
+use crate::instructions::Instruction;
+use crate::parser::MAX_RAM;
+use crate::preprocessor::{self, Origin};
+use crate::rpc::{read_message, write_message};
+use crate::{read_source_file, MAX_INSTRUCTIONS};
+
+/// The number of instructions executed between checks for a pause request while continuing.
+const INSTRUCTIONS_PER_POLL: usize = 100_000;
+/// The single thread of the program, as far as the editor is concerned.
+const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+const SYMBOLS_REFERENCE: u64 = 2;
+/// The memory reference given to the RAM. Addresses are in bytes, with every 16 bit word taking
+/// up two bytes in little endian order.
+const RAM_REFERENCE: &'static str = "ram";
+
+/// A program loaded by the `launch` request.
+struct Session {
+    path: PathBuf,
+    source_map: Vec<usize>,
+    /// The files and lines each instruction was written at, by ROM address, see [locations].
+    locations: Vec<Vec<(PathBuf, usize)>>,
+    instructions: Box<[Instruction; MAX_INSTRUCTIONS]>,
+    /// The ROM addresses of the breakpoints set by the editor, by canonical path of the file they
+    /// were set in, which are mirrored as [Breakpoint::PC] in the CPU.
+    source_breakpoints: HashMap<PathBuf, HashSet<u16>>,
+}
+
+/// Serves a single editor over the Debug Adapter Protocol, on the standard input and output.
+struct DebugAdapter {
+    cpu: CPUState,
+    session: Option<Session>,
+    stop_on_entry: bool,
+    messages: Receiver<Value>,
+    seq: u64,
+}
+
+/// Runs the debug adapter until the editor disconnects.
+pub fn serve() -> io::Result<()> {
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(io::stdin());
+        while let Ok(Some(message)) = read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut adapter = DebugAdapter {
+        cpu: CPUState::new(),
+        session: None,
+        stop_on_entry: true,
+        messages,
+        seq: 1,
+    };
+    while let Ok(message) = adapter.messages.recv() {
+        if !adapter.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl DebugAdapter {
+    /// Handles a single request. Returns false once the editor has disconnected.
+    fn handle(self: &mut Self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or("");
+        let arguments = &request["arguments"];
+        match command {
+            "initialize" => {
+                self.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsReadMemoryRequest": true,
+                    }),
+                )?;
+            }
+            // The editor sends the breakpoints once initialized, which needs the program
+            "launch" => match self.launch(arguments) {
+                Ok(()) => {
+                    self.respond(request, json!({}))?;
+                    self.event("initialized", json!({}))?;
+                }
+                Err(e) => self.fail(request, &e)?,
+            },
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(arguments);
+                self.respond(request, body)?;
+            }
+            "setExceptionBreakpoints" => self.respond(request, json!({ "breakpoints": [] }))?,
+            "configurationDone" => {
+                self.respond(request, json!({}))?;
+                if self.stop_on_entry {
+                    self.stopped("entry")?;
+                } else {
+                    self.resume(None)?;
+                }
+            }
+            "threads" => self.respond(
+                request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "HACK CPU" }] }),
+            )?,
+            "stackTrace" => {
+                let body = self.stack_trace();
+                self.respond(request, body)?;
+            }
+            "scopes" => self.respond(
+                request,
+                json!({ "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                    { "name": "Symbols", "variablesReference": SYMBOLS_REFERENCE, "expensive": false },
+                ] }),
+            )?,
+            "variables" => {
+                let body = self.variables(arguments["variablesReference"].as_u64().unwrap_or(0));
+                self.respond(request, body)?;
+            }
+            "stepIn" => {
+                self.respond(request, json!({}))?;
+                self.step()?;
+            }
+            "next" => {
+                self.respond(request, json!({}))?;
+                self.step_over()?;
+            }
+            "stepOut" => {
+                self.respond(request, json!({}))?;
+                self.step_out()?;
+            }
+            "continue" => {
+                self.respond(request, json!({ "allThreadsContinued": true }))?;
+                self.resume(None)?;
+            }
+            "pause" => {
+                self.respond(request, json!({}))?;
+                self.stopped("pause")?;
+            }
+            "readMemory" => match self.read_memory(arguments) {
+                Ok(body) => self.respond(request, body)?,
+                Err(e) => self.fail(request, &e)?,
+            },
+            "disconnect" | "terminate" => {
+                self.respond(request, json!({}))?;
+                return Ok(false);
+            }
+            _ => self.fail(request, &format!("Unsupported request {command}"))?,
+        }
+        Ok(true)
+    }
+
+    /// Loads the program given by the `program` argument.
+    fn launch(self: &mut Self, arguments: &Value) -> Result<(), String> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or(String::from("No program given to launch"))?;
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(true);
+        let lines = read_source_file(Path::new(path))?;
+        self.cpu = CPUState::new();
+        let (program, origins) = preprocessor::assemble_with_origins(
+            &lines,
+            Path::new(path),
+            &mut self.cpu.address_table,
+        )
+        .map_err(|e| e.to_string())?;
+        self.cpu.load_data(&program.data);
+        self.session = Some(Session {
+            path: PathBuf::from(path),
+            source_map: program.source_map,
+            locations: locations(&origins),
+            instructions: Box::new(program.instructions),
+            source_breakpoints: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    /// Replaces the breakpoints of a source file with those given by line, leaving those of other
+    /// files. Each line is moved to the first instruction at or after it in the same file.
+    fn set_breakpoints(self: &mut Self, arguments: &Value) -> Value {
+        let requested = arguments["breakpoints"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let Some(session) = &mut self.session else {
+            let breakpoints: Vec<Value> = requested
+                .iter()
+                .map(|breakpoint| {
+                    json!({
+                        "verified": false,
+                        "line": breakpoint["line"],
+                        "message": "No program has been launched",
+                    })
+                })
+                .collect();
+            return json!({ "breakpoints": breakpoints });
+        };
+        let path = arguments["source"]["path"]
+            .as_str()
+            .map_or(session.path.clone(), PathBuf::from);
+        let path = fs::canonicalize(&path).unwrap_or(path);
+        let file_breakpoints = session.source_breakpoints.entry(path.clone()).or_default();
+        for address in file_breakpoints.drain() {
+            self.cpu.breakpoints.remove(&Breakpoint::PC(address));
+        }
+
+        let mut breakpoints = vec![];
+        for breakpoint in requested {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            match breakpoint_address(&session.locations, &path, line) {
+                Some((address, written)) => {
+                    file_breakpoints.insert(address);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "line": written + 1,
+                    }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No instruction at or after this line",
+                })),
+            }
+        }
+        // An instruction can be written in several files, through an include or a macro
+        for address in session.source_breakpoints.values().flatten() {
+            self.cpu.breakpoints.insert(Breakpoint::PC(*address));
+        }
+        json!({ "breakpoints": breakpoints })
+    }
+
+    /// Reports the frames of the call stack, from the innermost to the outermost, see
+    /// [call_stack::reconstruct].
+    fn stack_trace(self: &Self) -> Value {
+        let Some(session) = &self.session else {
+            return json!({ "stackFrames": [], "totalFrames": 0 });
+        };
+        let frames: Vec<Value> = call_stack::reconstruct(&self.cpu, &session.instructions)
+            .into_iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let line = session
+                    .source_map
+                    .get(frame.address as usize)
+                    .map_or(0, |l| l + 1);
+                json!({
+                    "id": id,
+                    "name": frame.function,
+                    "line": line,
+                    "column": 1,
+                    "source": {
+                        "name": session.path.file_name().map(|n| n.to_string_lossy().into_owned()),
+                        "path": session.path,
+                    },
+                    "instructionPointerReference": frame.address.to_string(),
+                })
+            })
+            .collect();
+        json!({ "totalFrames": frames.len(), "stackFrames": frames })
+    }
+
+    fn variables(self: &Self, reference: u64) -> Value {
+        let variable = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let variables: Vec<Value> = match reference {
+            REGISTERS_REFERENCE => {
+                let m = self.cpu.ram.get(self.cpu.a.0 as usize).map(|m| m.0);
+                vec![
+                    variable("A", self.cpu.a.to_string()),
+                    variable("D", self.cpu.d.to_string()),
+                    variable("PC", self.cpu.pc.to_string()),
+                    variable("M", m.map_or(String::from("-"), |m| m.to_string())),
+                ]
+            }
+            SYMBOLS_REFERENCE => {
+                let table = &self.cpu.address_table;
+                let mut symbols: Vec<(&String, &u16)> = table.table.iter().collect();
+                symbols.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+                symbols
+                    .into_iter()
+                    .filter_map(|(name, address)| {
+                        let description = table.describe(name)?;
+                        if table.labels.contains(name) || table.constants.contains(name) {
+                            Some(variable(name, description))
+                        } else {
+                            let value = self.cpu.ram.get(*address as usize).map_or(0, |v| v.0);
+                            Some(variable(name, format!("{value} ({description})")))
+                        }
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        };
+        json!({ "variables": variables })
+    }
+
+    /// Reads bytes from the RAM, returning them base64 encoded.
+    fn read_memory(self: &Self, arguments: &Value) -> Result<Value, String> {
+        let reference = arguments["memoryReference"]
+            .as_str()
+            .unwrap_or(RAM_REFERENCE);
+        let base = if reference == RAM_REFERENCE {
+            0
+        } else {
+            reference
+                .parse::<i64>()
+                .map_err(|_| format!("Unknown memory reference {reference}"))?
+        };
+        let start = base + arguments["offset"].as_i64().unwrap_or(0);
+        let count = arguments["count"].as_i64().unwrap_or(0);
+        let start = start.clamp(0, MAX_RAM as i64 * 2) as usize;
+        let end = (start + count.max(0) as usize).min(MAX_RAM * 2);
+        let bytes: Vec<u8> = (start..end)
+            .map(|byte| self.cpu.ram[byte / 2].0.to_le_bytes()[byte % 2])
+            .collect();
+        Ok(json!({
+            "address": start.to_string(),
+            "data": base64(&bytes),
+            "unreadableBytes": count.max(0) as usize - bytes.len(),
+        }))
+    }
+
+    fn halted(self: &Self) -> bool {
+        self.cpu.pc as usize >= MAX_INSTRUCTIONS
+    }
+
+    /// Executes a single instruction.
+    fn step(self: &mut Self) -> io::Result<()> {
+        let Some(session) = &self.session else {
+            return self.stopped("step");
+        };
+        if !self.halted() {
+            self.cpu
+                .interpret(&session.instructions[self.cpu.pc as usize]);
+        }
+        if self.halted() {
+            self.terminated()
+        } else {
+            self.stopped("step")
+        }
+    }
+
+    /// Executes a single instruction, and if it calls a function, runs until the call returns to
+    /// the depth of the call stack before the instruction.
+    fn step_over(self: &mut Self) -> io::Result<()> {
+        let Some(session) = &self.session else {
+            return self.stopped("step");
+        };
+        let depth = call_stack::depth(&self.cpu, &session.instructions);
+        if !self.halted() {
+            self.cpu
+                .interpret(&session.instructions[self.cpu.pc as usize]);
+        }
+        if self.halted() {
+            return self.terminated();
+        }
+        if call_stack::depth(&self.cpu, &session.instructions) <= depth {
+            return self.stopped("step");
+        }
+        match call_stack::return_address(&self.cpu, &session.instructions) {
+            Some(address) => self.resume(Some((depth, address))),
+            None => self.stopped("step"),
+        }
+    }
+
+    /// Runs until the function being run returns to its caller. The outermost function has no
+    /// caller, so the program runs on as if continued.
+    fn step_out(self: &mut Self) -> io::Result<()> {
+        let Some(session) = &self.session else {
+            return self.stopped("step");
+        };
+        let depth = call_stack::depth(&self.cpu, &session.instructions);
+        match call_stack::return_address(&self.cpu, &session.instructions) {
+            Some(address) => self.resume(Some((depth - 1, address))),
+            None => self.resume(None),
+        }
+    }
+
+    /// Runs the program until it hits a breakpoint, leaves the ROM, or the editor asks to pause.
+    /// Stepping over and out of calls also stops `until` the PC is at a return address, with the
+    /// call stack at most a depth deep, so that returns of recursive calls are told apart.
+    /// Requests that arrive while running, other than pausing, are handled once stopped.
+    fn resume(self: &mut Self, until: Option<(usize, u16)>) -> io::Result<()> {
+        let Some(session) = self.session.take() else {
+            return self.stopped("pause");
+        };
+        let mut pending = vec![];
+        let reason = 'running: loop {
+            for _ in 0..INSTRUCTIONS_PER_POLL {
+                if self.halted() {
+                    break 'running None;
+                }
+                self.cpu
+                    .interpret(&session.instructions[self.cpu.pc as usize]);
+                if self.cpu.hit_breakpoint() {
+                    break 'running Some("breakpoint");
+                }
+                if until.is_some_and(|(depth, address)| {
+                    self.cpu.pc == address
+                        && call_stack::depth(&self.cpu, &session.instructions) <= depth
+                }) {
+                    break 'running Some("step");
+                }
+            }
+            loop {
+                match self.messages.try_recv() {
+                    Ok(message) if message["command"] == "pause" => {
+                        self.respond(&message, json!({}))?;
+                        break 'running Some("pause");
+                    }
+                    Ok(message) => pending.push(message),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => break 'running Some("pause"),
+                }
+            }
+        };
+        self.session = Some(session);
+
+        match reason {
+            Some(reason) => self.stopped(reason)?,
+            None => self.terminated()?,
+        }
+        for message in pending {
+            self.handle(&message)?;
+        }
+        Ok(())
+    }
+
+    fn stopped(self: &mut Self, reason: &str) -> io::Result<()> {
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )
+    }
+
+    fn terminated(self: &mut Self) -> io::Result<()> {
+        self.event("exited", json!({ "exitCode": 0 }))?;
+        self.event("terminated", json!({}))
+    }
+
+    fn respond(self: &mut Self, request: &Value, body: Value) -> io::Result<()> {
+        let seq = self.next_seq();
+        self.send(json!({
+            "seq": seq,
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(self: &mut Self, request: &Value, message: &str) -> io::Result<()> {
+        let seq = self.next_seq();
+        self.send(json!({
+            "seq": seq,
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(self: &mut Self, event: &str, body: Value) -> io::Result<()> {
+        let seq = self.next_seq();
+        self.send(json!({ "seq": seq, "type": "event", "event": event, "body": body }))
+    }
+
+    fn next_seq(self: &mut Self) -> u64 {
+        self.seq += 1;
+        self.seq - 1
+    }
+
+    fn send(self: &Self, message: Value) -> io::Result<()> {
+        write_message(&mut io::stdout().lock(), &message)
+    }
+}
+
+/// Lists the files and lines each instruction was written at, by ROM address. An instruction from
+/// an included file, or a macro, was also written at the `#include` or use of the macro that
+/// brought it in, so it has a location in each file along the way. Paths are made canonical, so
+/// that they compare equal to those the editor gives.
+fn locations(origins: &[Origin]) -> Vec<Vec<(PathBuf, usize)>> {
+    let mut canonical_paths: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut canonical = |path: &PathBuf| {
+        canonical_paths
+            .entry(path.clone())
+            .or_insert_with(|| fs::canonicalize(path).unwrap_or(path.clone()))
+            .clone()
+    };
+    origins
+        .iter()
+        .map(|origin| {
+            let mut locations = vec![(canonical(&origin.path), origin.line)];
+            let mut expanded_from = &origin.expanded_from;
+            while let Some(expansion) = expanded_from {
+                locations.push((canonical(&expansion.origin.path), expansion.origin.line));
+                expanded_from = &expansion.origin.expanded_from;
+            }
+            locations
+        })
+        .collect()
+}
+
+/// Finds the first instruction written in the file at or after a line, starting from 1. Returns
+/// its ROM address, and the line of the file it was written at, starting from 0.
+fn breakpoint_address(
+    locations: &[Vec<(PathBuf, usize)>],
+    path: &Path,
+    line: usize,
+) -> Option<(u16, usize)> {
+    locations
+        .iter()
+        .enumerate()
+        .find_map(|(address, locations)| {
+            locations
+                .iter()
+                .find(|(file, written)| file == path && written + 1 >= line)
+                .map(|(_, written)| (address as u16, *written))
+        })
+}
+
+/// Encodes bytes as standard base64, with padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::on_large_stack;
+    use std::num::Wrapping;
+
+    fn adapter() -> DebugAdapter {
+        DebugAdapter {
+            cpu: CPUState::new(),
+            session: None,
+            stop_on_entry: true,
+            messages: mpsc::channel().1,
+            seq: 1,
+        }
+    }
+
+    /// The addresses and lines of the breakpoints set at lines of a file.
+    fn set_breakpoints(adapter: &mut DebugAdapter, path: &Path, lines: &[u64]) -> Vec<Value> {
+        let breakpoints: Vec<Value> = lines.iter().map(|line| json!({ "line": line })).collect();
+        let body = adapter.set_breakpoints(&json!({
+            "source": { "path": path },
+            "breakpoints": breakpoints,
+        }));
+        body["breakpoints"].as_array().unwrap().clone()
+    }
+
+    #[test]
+    fn breakpoints_map_lines_of_each_file() {
+        on_large_stack(|| {
+            let directory = std::env::temp_dir().join("hack_dap_breakpoints");
+            fs::create_dir_all(&directory).unwrap();
+            let main = directory.join("Main.asm");
+            let library = directory.join("Lib.asm");
+            fs::write(&main, "@0\nD=A\n#include \"Lib.asm\"\n\n0;JMP").unwrap();
+            fs::write(&library, "// Adds one\n@1\nD=D+A").unwrap();
+
+            let mut adapter = adapter();
+            let breakpoints = set_breakpoints(&mut adapter, &main, &[1]);
+            assert_eq!(breakpoints[0]["verified"], false);
+
+            adapter
+                .launch(&json!({ "program": main.to_str().unwrap() }))
+                .unwrap();
+            let breakpoints = set_breakpoints(&mut adapter, &main, &[2, 3, 4, 9]);
+            let lines: Vec<&Value> = breakpoints.iter().map(|b| &b["line"]).collect();
+            assert_eq!(lines, [2, 3, 5, 9]);
+            assert_eq!(breakpoints[3]["verified"], false);
+            let library_breakpoints = set_breakpoints(&mut adapter, &library, &[1, 3]);
+            let lines: Vec<&Value> = library_breakpoints.iter().map(|b| &b["line"]).collect();
+            assert_eq!(lines, [2, 3]);
+
+            let expected = HashSet::from([1, 2, 3, 4].map(Breakpoint::PC));
+            assert_eq!(adapter.cpu.breakpoints, expected);
+
+            // Clearing the breakpoints of the library keeps the include's, at the same address
+            set_breakpoints(&mut adapter, &library, &[]);
+            assert!(adapter.cpu.breakpoints.contains(&Breakpoint::PC(2)));
+            assert!(!adapter.cpu.breakpoints.contains(&Breakpoint::PC(3)));
+        });
+    }
+
+    #[test]
+    fn symbols_are_described_by_kind() {
+        on_large_stack(|| {
+            let mut adapter = adapter();
+            let table = &mut adapter.cpu.address_table;
+            table.table.insert(String::from("LOOP"), 7);
+            table.labels.insert(String::from("LOOP"));
+            table.table.insert(String::from("WIDTH"), 32);
+            table.constants.insert(String::from("WIDTH"));
+            table.table.insert(String::from("counter"), 16);
+            adapter.cpu.ram[16] = Wrapping(3);
+            adapter.cpu.ram[32] = Wrapping(5);
+
+            let body = adapter.variables(SYMBOLS_REFERENCE);
+            let value = |name: &str| {
+                let variables = body["variables"].as_array().unwrap();
+                let variable = variables.iter().find(|v| v["name"] == name).unwrap();
+                variable["value"].as_str().unwrap().to_string()
+            };
+            assert_eq!(value("LOOP"), "ROM[7]");
+            assert_eq!(value("WIDTH"), "32");
+            assert_eq!(value("counter"), "3 (RAM[16])");
+            assert_eq!(value("SCREEN"), "0 (RAM[16384])");
+        });
+    }
+
+    #[test]
+    fn base64_is_padded() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xFF, 0xEF]), "/+8=");
+    }
+
+    #[test]
+    fn memory_is_read_within_the_ram() {
+        on_large_stack(|| {
+            let mut adapter = adapter();
+            adapter.cpu.ram[0] = Wrapping(0x1234);
+            adapter.cpu.ram[MAX_RAM - 1] = Wrapping(-1);
+
+            let body = adapter
+                .read_memory(&json!({ "memoryReference": "ram", "count": 3 }))
+                .unwrap();
+            assert_eq!(body["address"], "0");
+            assert_eq!(body["data"], base64(&[0x34, 0x12, 0]));
+            assert_eq!(body["unreadableBytes"], 0);
+
+            let last = (MAX_RAM * 2 - 2) as i64;
+            let body = adapter
+                .read_memory(&json!({ "memoryReference": "ram", "offset": last, "count": 4 }))
+                .unwrap();
+            assert_eq!(body["data"], base64(&[0xFF, 0xFF]));
+            assert_eq!(body["unreadableBytes"], 2);
+
+            let body = adapter
+                .read_memory(&json!({ "memoryReference": "ram", "offset": last + 10, "count": 4 }))
+                .unwrap();
+            assert_eq!(body["data"], "");
+            assert_eq!(body["unreadableBytes"], 4);
+
+            assert!(adapter
+                .read_memory(&json!({ "memoryReference": "rom", "count": 1 }))
+                .is_err());
+        });
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use imgui::Ui;
 
 use crate::hack_cpu::CPUState;
@@ -21,39 +23,10 @@ impl Breakpoint {
     /// The returning of a boolean is designed to inform whether or not the `remove` button has
     /// been clicked.
     pub fn display(self: &Self, ui: &Ui, cpustate: &CPUState) -> bool {
-        match self {
-            Breakpoint::A(v) => {
-                let text = format!("A: {v}");
-                if &cpustate.a.0 == v {
-                    ui.text_colored(RED, text);
-                } else {
-                    ui.text(text);
-                }
-            }
-            Breakpoint::D(v) => {
-                let text = format!("D: {v}");
-                if &cpustate.d.0 == v {
-                    ui.text_colored(RED, text);
-                } else {
-                    ui.text(text);
-                }
-            }
-            Breakpoint::PC(v) => {
-                let text = format!("PC: {v}");
-                if &cpustate.pc == v {
-                    ui.text_colored(RED, text);
-                } else {
-                    ui.text(text);
-                }
-            }
-            Breakpoint::RAM(n, v) => {
-                let text = format!("RAM[{n}]: {v}");
-                if &cpustate.ram[*n as usize].0 == v {
-                    ui.text_colored(RED, text);
-                } else {
-                    ui.text(text);
-                }
-            }
+        if self.hit(cpustate) {
+            ui.text_colored(RED, self.to_string());
+        } else {
+            ui.text(self.to_string());
         }
         ui.same_line();
         match self {
@@ -63,6 +36,27 @@ impl Breakpoint {
             Breakpoint::RAM(n, v) => ui.button(format!("Remove##RAM{n}{v}")),
         }
     }
+
+    /// Whether the current state of the CPU matches the breakpoint.
+    pub fn hit(self: &Self, cpustate: &CPUState) -> bool {
+        match self {
+            Breakpoint::A(v) => cpustate.a.0 == *v,
+            Breakpoint::D(v) => cpustate.d.0 == *v,
+            Breakpoint::PC(v) => cpustate.pc == *v,
+            Breakpoint::RAM(n, v) => cpustate.ram[*n as usize].0 == *v,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::A(v) => write!(f, "A: {v}"),
+            Breakpoint::D(v) => write!(f, "D: {v}"),
+            Breakpoint::PC(v) => write!(f, "PC: {v}"),
+            Breakpoint::RAM(n, v) => write!(f, "RAM[{n}]: {v}"),
+        }
+    }
 }
 
 /// Represents a choice between the possible [Breakpoint]s. This is used for a radio button when
@@ -0,0 +1,276 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::hack_cpu::CPUState;
+use crate::instructions::Instruction;
+use crate::MAX_INSTRUCTIONS;
+
+/// A program loaded into its own CPU, for running in lockstep with another one.
+pub struct Machine<'a> {
+    pub cpu: CPUState,
+    pub instructions: &'a [Instruction; MAX_INSTRUCTIONS],
+}
+
+/// What should be compared between the two machines after every cycle.
+pub struct Comparison {
+    pub registers: bool,
+    pub watched: Vec<RangeInclusive<u16>>,
+}
+
+/// The state of one of the machines at the point at which the two runs diverged.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub pc_before: u16,
+    pub instruction: String,
+    pub pc: u16,
+    pub a: i16,
+    pub d: i16,
+    pub halted: bool,
+}
+
+impl Snapshot {
+    fn new(pc_before: u16, instruction: String, cpu: &CPUState) -> Self {
+        Self {
+            pc_before,
+            instruction,
+            pc: cpu.pc,
+            a: cpu.a.0,
+            d: cpu.d.0,
+            halted: cpu.pc as usize >= MAX_INSTRUCTIONS,
+        }
+    }
+}
+
+/// Describes the first cycle at which two runs stopped agreeing.
+#[derive(Debug)]
+pub struct Divergence {
+    pub cycle: u64,
+    pub reasons: Vec<String>,
+    pub student: Snapshot,
+    pub reference: Snapshot,
+    /// The watched RAM addresses that differ, along with the student and reference values.
+    pub ram: Vec<(u16, i16, i16)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Diverged at cycle {}: {}",
+            self.cycle,
+            self.reasons.join(", ")
+        )?;
+        writeln!(f, "{:<14}{:<24}{}", "", "student", "reference")?;
+        let row = |f: &mut fmt::Formatter<'_>, name: &str, s: String, r: String| {
+            writeln!(f, "{:<14}{:<24}{}", name, s, r)
+        };
+        row(
+            f,
+            "Executed",
+            format!("{}: {}", self.student.pc_before, self.student.instruction),
+            format!(
+                "{}: {}",
+                self.reference.pc_before, self.reference.instruction
+            ),
+        )?;
+        row(
+            f,
+            "PC",
+            self.student.pc.to_string(),
+            self.reference.pc.to_string(),
+        )?;
+        if self.student.halted || self.reference.halted {
+            row(
+                f,
+                "Halted",
+                self.student.halted.to_string(),
+                self.reference.halted.to_string(),
+            )?;
+        }
+        row(
+            f,
+            "A",
+            self.student.a.to_string(),
+            self.reference.a.to_string(),
+        )?;
+        row(
+            f,
+            "D",
+            self.student.d.to_string(),
+            self.reference.d.to_string(),
+        )?;
+        for (address, student, reference) in &self.ram {
+            row(
+                f,
+                &format!("RAM[{address}]"),
+                student.to_string(),
+                reference.to_string(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the student and reference machines side by side, one instruction at a time, for up to
+/// `cycles` cycles. Returns the first point at which they differ according to `comparison`, or
+/// [None] if they agreed throughout.
+pub fn run_lockstep(
+    student: &mut Machine,
+    reference: &mut Machine,
+    comparison: &Comparison,
+    cycles: u64,
+) -> Option<Divergence> {
+    for cycle in 1..=cycles {
+        let student_halted = student.cpu.pc as usize >= MAX_INSTRUCTIONS;
+        let reference_halted = reference.cpu.pc as usize >= MAX_INSTRUCTIONS;
+        if student_halted && reference_halted {
+            return None;
+        }
+
+        let student_before = student.cpu.pc;
+        let student_instruction = step(student);
+        let reference_before = reference.cpu.pc;
+        let reference_instruction = step(reference);
+
+        let mut reasons = vec![];
+        if student_halted != reference_halted {
+            reasons.push(String::from("only one program halted"));
+        }
+        if comparison.registers {
+            if student.cpu.pc != reference.cpu.pc {
+                reasons.push(String::from("PC differs"));
+            }
+            if student.cpu.a != reference.cpu.a {
+                reasons.push(String::from("A differs"));
+            }
+            if student.cpu.d != reference.cpu.d {
+                reasons.push(String::from("D differs"));
+            }
+        }
+        let ram: Vec<(u16, i16, i16)> = comparison
+            .watched
+            .iter()
+            .flat_map(|range| range.clone())
+            .filter(|&address| {
+                student.cpu.ram[address as usize] != reference.cpu.ram[address as usize]
+            })
+            .map(|address| {
+                (
+                    address,
+                    student.cpu.ram[address as usize].0,
+                    reference.cpu.ram[address as usize].0,
+                )
+            })
+            .collect();
+        if !ram.is_empty() {
+            reasons.push(String::from("watched RAM differs"));
+        }
+
+        if !reasons.is_empty() {
+            return Some(Divergence {
+                cycle,
+                reasons,
+                student: Snapshot::new(student_before, student_instruction, &student.cpu),
+                reference: Snapshot::new(reference_before, reference_instruction, &reference.cpu),
+                ram,
+            });
+        }
+    }
+    None
+}
+
+/// Executes a single instruction on the machine, unless it has already left the ROM, and returns
+/// the instruction that was executed.
+fn step(machine: &mut Machine) -> String {
+    if machine.cpu.pc as usize >= MAX_INSTRUCTIONS {
+        return String::from("(halted)");
+    }
+    let instruction = &machine.instructions[machine.cpu.pc as usize];
+    machine.cpu.interpret(instruction);
+    instruction.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+    use crate::split_source;
+    use crate::symbol_table::SymbolTable;
+    use crate::test::on_large_stack;
+
+    fn assemble(source: &str) -> Box<[Instruction; MAX_INSTRUCTIONS]> {
+        let lines = split_source(source).unwrap();
+        Box::new(parse(lines, &mut SymbolTable::new()).unwrap().instructions)
+    }
+
+    /// Runs two programs in lockstep, comparing the registers and the first few words of the RAM.
+    fn compare(student: &str, reference: &str, registers: bool) -> Option<Divergence> {
+        let (student, reference) = (assemble(student), assemble(reference));
+        let comparison = Comparison {
+            registers,
+            watched: vec![0..=3],
+        };
+        run_lockstep(
+            &mut Machine {
+                cpu: CPUState::new(),
+                instructions: &student,
+            },
+            &mut Machine {
+                cpu: CPUState::new(),
+                instructions: &reference,
+            },
+            &comparison,
+            100,
+        )
+    }
+
+    #[test]
+    fn identical_programs_agree() {
+        on_large_stack(|| {
+            let program = "@5\nD=A\n@0\nM=D";
+            assert!(compare(program, program, true).is_none());
+        });
+    }
+
+    #[test]
+    fn registers_diverge() {
+        on_large_stack(|| {
+            let divergence = compare("@5\nD=A\n@1", "@5\nD=A\n@2", true).unwrap();
+            assert_eq!(divergence.cycle, 3);
+            assert_eq!(divergence.reasons, ["A differs"]);
+            assert_eq!(divergence.student.instruction, "@1");
+            assert_eq!(divergence.reference.instruction, "@2");
+            assert_eq!(divergence.student.pc_before, 2);
+            assert!(divergence.ram.is_empty());
+
+            // Without comparing the registers, only the watched RAM counts
+            assert!(compare("@5\nD=A\n@1", "@5\nD=A\n@2", false).is_none());
+        });
+    }
+
+    #[test]
+    fn watched_ram_diverges() {
+        on_large_stack(|| {
+            let divergence = compare("@7\nD=A\n@3\nM=D", "@7\nD=A\n@3\nM=D+1", false).unwrap();
+            assert_eq!(divergence.cycle, 4);
+            assert_eq!(divergence.reasons, ["watched RAM differs"]);
+            assert_eq!(divergence.ram, [(3, 7, 8)]);
+            assert!(divergence.to_string().contains("RAM[3]"));
+
+            // Writes outside of the watched range are not compared
+            assert!(compare("@7\nD=A\n@4\nM=D", "@7\nD=A\n@4\nM=D+1", false).is_none());
+        });
+    }
+
+    #[test]
+    fn halting_early_diverges() {
+        on_large_stack(|| {
+            // Jumping to the end of the ROM halts the program
+            let divergence = compare("@32767\n0;JMP", "@32767\nD=A", true).unwrap();
+            assert_eq!(divergence.cycle, 2);
+            assert!(divergence.student.halted);
+            assert!(!divergence.reference.halted);
+            assert!(divergence.to_string().contains("Halted"));
+        });
+    }
+}
@@ -0,0 +1,432 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use imgui::{InputTextCallbackHandler, InputTextMultilineCallback, TextCallbackData, Ui};
+use rfd::FileDialog;
+
+use crate::debug::RED;
+use crate::expression::is_symbol;
+use crate::parser::{split_comment, LineParsingError};
+use crate::preprocessor;
+use crate::symbol_table::SymbolTable;
+use crate::{split_source, ASM_FILE_EXTENSION};
+
+/// The name a new program is checked under, until it is saved. Includes are found relative to
+/// the directory it would be saved in.
+const UNTITLED: &'static str = "untitled.asm";
+const COMMENT_COLOR: [f32; 4] = [0.45, 0.65, 0.45, 1.0];
+const LABEL_COLOR: [f32; 4] = [1.0, 0.8, 0.0, 1.0];
+const DIRECTIVE_COLOR: [f32; 4] = [0.8, 0.5, 1.0, 1.0];
+const ADDRESS_COLOR: [f32; 4] = [0.4, 0.7, 1.0, 1.0];
+const DESTINATION_COLOR: [f32; 4] = [0.4, 0.9, 0.9, 1.0];
+const JUMP_COLOR: [f32; 4] = [1.0, 0.55, 0.3, 1.0];
+const INVOCATION_COLOR: [f32; 4] = [0.9, 0.5, 0.7, 1.0];
+/// The width and height of each zig and zag of the squiggle under lines with errors.
+const SQUIGGLE_STEP: f32 = 3.0;
+/// The fraction of the width of the editor taken by the text being edited, with the highlighted
+/// view of it beside it. Both are in one window, so that they scroll together.
+const INPUT_WIDTH_RATIO: f32 = 0.5;
+
+/// The kind of a piece of a line of source code, which decides its colour.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+    Plain,
+    Comment,
+    Label,
+    /// Preprocessor directives, such as `#include`, and directives such as `.equ`.
+    Directive,
+    Address,
+    Destination,
+    Jump,
+    /// The name of a macro or pseudo-instruction being used.
+    Invocation,
+}
+
+impl Token {
+    fn color(self: &Self) -> Option<[f32; 4]> {
+        match self {
+            Token::Plain => None,
+            Token::Comment => Some(COMMENT_COLOR),
+            Token::Label => Some(LABEL_COLOR),
+            Token::Directive => Some(DIRECTIVE_COLOR),
+            Token::Address => Some(ADDRESS_COLOR),
+            Token::Destination => Some(DESTINATION_COLOR),
+            Token::Jump => Some(JUMP_COLOR),
+            Token::Invocation => Some(INVOCATION_COLOR),
+        }
+    }
+}
+
+/// An editor for the source code of a program, which shows the code highlighted beside it, with
+/// the lines that fail to assemble underlined.
+pub struct Editor {
+    /// The file being edited, or [None] if it has never been saved.
+    pub path: Option<PathBuf>,
+    pub text: String,
+    /// The text as it was last opened or saved, to tell whether there are unsaved changes.
+    saved_text: String,
+    /// The errors of the text, by the index of their line.
+    errors: Vec<(usize, String)>,
+    /// Whether the RAM is kept when the text is run, rather than cleared.
+    pub keep_ram: bool,
+    /// The last error opening or saving a file.
+    file_error: Option<String>,
+    /// The byte index of the text cursor, which is kept in view as it moves.
+    cursor: usize,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            text: String::new(),
+            saved_text: String::new(),
+            errors: vec![],
+            keep_ram: false,
+            file_error: None,
+            cursor: 0,
+        }
+    }
+
+    /// Replaces the text with that of a file that was opened.
+    pub fn open(self: &mut Self, path: &Path, text: String) {
+        self.path = Some(path.to_path_buf());
+        self.saved_text = text.clone();
+        self.text = text;
+        self.file_error = None;
+        self.cursor = 0;
+        self.check(path.parent().unwrap_or(Path::new("")));
+    }
+
+    /// Whether the text has changed since it was last opened or saved.
+    pub fn is_modified(self: &Self) -> bool {
+        self.text != self.saved_text
+    }
+
+    /// The path the text is assembled as, which decides where its includes are found. New files
+    /// are assembled as if they were in the `directory`.
+    pub fn assembly_path(self: &Self, directory: &Path) -> PathBuf {
+        self.path
+            .clone()
+            .unwrap_or_else(|| directory.join(UNTITLED))
+    }
+
+    /// Builds the editor, returning whether the text should be run. New files are saved in the
+    /// `directory`, which is updated to the directory of the file saved.
+    pub fn build(self: &mut Self, ui: &Ui, directory: &mut PathBuf) -> bool {
+        let mut run = false;
+        let name = self
+            .path
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map_or(String::from(UNTITLED), |n| n.to_string_lossy().into_owned());
+        let modified = if self.is_modified() { "*" } else { "" };
+        ui.text(format!("{name}{modified}"));
+        ui.same_line();
+        if ui.button("Save") {
+            match self.path.clone() {
+                Some(path) => self.save(&path),
+                None => self.save_as(directory),
+            }
+        }
+        ui.same_line();
+        if ui.button("Save As") {
+            self.save_as(directory);
+        }
+        ui.same_line();
+        if ui.button("Run##editor") {
+            run = true;
+        }
+        ui.same_line();
+        ui.checkbox("Keep RAM", &mut self.keep_ram);
+        if let Some(e) = &self.file_error {
+            ui.text_colored(RED, e);
+        }
+        match self.errors.first() {
+            Some((line, message)) => ui.text_colored(
+                RED,
+                format!("{} errors, line {}: {message}", self.errors.len(), line + 1),
+            ),
+            None => ui.text_disabled("No errors"),
+        }
+
+        ui.child_window("##source").build(|| {
+            // The text being edited is tall enough to hold all of its lines, so that it never
+            // scrolls by itself, and the lines of the highlighted view are placed level with it
+            let top = ui.cursor_pos()[1] + ui.clone_style().frame_padding[1];
+            if self.build_input(ui, top) {
+                self.check(directory);
+            }
+            ui.same_line();
+            self.build_highlighted(ui, top);
+        });
+        run
+    }
+
+    /// Builds the text being edited, scrolling the window around it to keep the text cursor in
+    /// view when it moves. Returns whether the text changed.
+    fn build_input(self: &mut Self, ui: &Ui, top: f32) -> bool {
+        let line_height = ui.text_line_height();
+        let style = ui.clone_style();
+        let padding = style.frame_padding[1];
+        let lines = self.text.split('\n').count();
+        let [width, height] = ui.content_region_avail();
+        // Leaves room for the scrollbar of long lines, which would otherwise hide the last line
+        let text_height = lines as f32 * line_height + padding * 2.0 + style.scrollbar_size;
+        let size = [width * INPUT_WIDTH_RATIO, text_height.max(height)];
+        let previous_cursor = self.cursor;
+        let changed = ui
+            .input_text_multiline("##editor", &mut self.text, size)
+            .allow_tab_input(true)
+            .callback(
+                InputTextMultilineCallback::ALWAYS,
+                CursorTracker(&mut self.cursor),
+            )
+            .build();
+
+        if self.cursor != previous_cursor {
+            let before_cursor = self.text.get(..self.cursor).unwrap_or(&self.text);
+            let y = top + before_cursor.matches('\n').count() as f32 * line_height;
+            let visible_height = ui.window_size()[1];
+            if y - padding < ui.scroll_y() {
+                ui.set_scroll_y(y - padding);
+            } else if y + line_height + padding > ui.scroll_y() + visible_height {
+                ui.set_scroll_y(y + line_height + padding - visible_height);
+            }
+        }
+        changed
+    }
+
+    /// Builds the view of the text with its syntax highlighted, and its errors underlined. Only
+    /// the lines in view are built, each level with the line of the text being edited, whose
+    /// first line is at `top`.
+    fn build_highlighted(self: &Self, ui: &Ui, top: f32) {
+        let lines: Vec<&str> = self.text.split('\n').collect();
+        let number_width = lines.len().to_string().len();
+        let line_height = ui.text_line_height();
+        let left = ui.cursor_pos()[0];
+        let first = ((ui.scroll_y() - top) / line_height).floor().max(0.0) as usize;
+        let last = first + (ui.window_size()[1] / line_height).ceil() as usize + 1;
+        for index in first..last.min(lines.len()) {
+            ui.set_cursor_pos([left, top + index as f32 * line_height]);
+            ui.text_disabled(format!("{:>number_width$} ", index + 1));
+            ui.same_line_with_spacing(0.0, 0.0);
+            let start = ui.cursor_screen_pos();
+            for (token, text) in tokens(lines[index]) {
+                match token.color() {
+                    Some(color) => ui.text_colored(color, text),
+                    None => ui.text(text),
+                }
+                ui.same_line_with_spacing(0.0, 0.0);
+            }
+            let end = ui.item_rect_max();
+
+            let errors: Vec<&str> = self
+                .errors
+                .iter()
+                .filter(|(line, _)| *line == index)
+                .map(|(_, message)| message.as_str())
+                .collect();
+            if errors.is_empty() {
+                continue;
+            }
+            let end = [end[0].max(start[0] + SQUIGGLE_STEP * 4.0), end[1]];
+            draw_squiggle(ui, start[0], end[0], end[1]);
+            if ui.is_mouse_hovering_rect(start, end) {
+                ui.tooltip_text(errors.join("\n"));
+            }
+        }
+    }
+
+    /// Assembles the text, keeping its errors for highlighting. The includes of a new file are
+    /// found in the `directory`.
+    fn check(self: &mut Self, directory: &Path) {
+        let path = self.assembly_path(directory);
+        self.errors = match split_source(&self.text) {
+            Ok(lines) => preprocessor::check(&lines, &path, &mut SymbolTable::new())
+                .into_iter()
+                .map(|error| match error {
+                    LineParsingError::InvalidLine(line_number, _, reason) => {
+                        (line_number as usize - 1, reason)
+                    }
+                })
+                .collect(),
+            Err(e) => vec![(0, e)],
+        };
+    }
+
+    fn save(self: &mut Self, path: &Path) {
+        match fs::write(path, &self.text) {
+            Ok(()) => {
+                self.path = Some(path.to_path_buf());
+                self.saved_text = self.text.clone();
+                self.file_error = None;
+            }
+            Err(e) => {
+                self.file_error = Some(format!("Failed to save {}: {e}", path.display()));
+            }
+        }
+    }
+
+    fn save_as(self: &mut Self, directory: &mut PathBuf) {
+        let file = FileDialog::new()
+            .add_filter("asm", &[ASM_FILE_EXTENSION])
+            .set_directory(&directory)
+            .set_file_name(UNTITLED)
+            .save_file();
+        if let Some(path) = file {
+            if let Some(parent) = path.parent() {
+                *directory = parent.to_path_buf();
+            }
+            self.save(&path);
+        }
+    }
+}
+
+/// Records where the text cursor is while the text is being edited.
+struct CursorTracker<'a>(&'a mut usize);
+
+impl InputTextCallbackHandler for CursorTracker<'_> {
+    fn on_always(&mut self, data: TextCallbackData) {
+        *self.0 = data.cursor_pos();
+    }
+}
+
+/// Splits a line of source code into the pieces that are highlighted differently, keeping all of
+/// its text, including the spaces.
+fn tokens(line: &str) -> Vec<(Token, &str)> {
+    let (code, comment) = split_comment(line);
+    let trimmed = code.trim_start();
+    let (indent, code) = code.split_at(code.len() - trimmed.len());
+    let mut tokens = vec![(Token::Plain, indent)];
+    if code.starts_with('(') {
+        tokens.push((Token::Label, code));
+    } else if code.starts_with(['#', '.']) {
+        tokens.push((Token::Directive, code));
+    } else if code.starts_with('@') {
+        tokens.push((Token::Address, code));
+    } else if let Some(index) = invocation_name_end(code) {
+        let (name, arguments) = code.split_at(index);
+        tokens.push((Token::Invocation, name));
+        tokens.push((Token::Plain, arguments));
+    } else {
+        let (destination, rest) = match code.find('=') {
+            Some(index) => code.split_at(index),
+            None => ("", code),
+        };
+        let (computation, jump) = match rest.find(';') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        tokens.push((Token::Destination, destination));
+        tokens.push((Token::Plain, computation));
+        tokens.push((Token::Jump, jump));
+    }
+    tokens.push((Token::Comment, comment));
+    tokens.retain(|(_, text)| !text.is_empty());
+    tokens
+}
+
+/// Finds the end of the name of a macro or pseudo-instruction being used, such as `LD` in
+/// `LD D, 42`. A name followed by `=` or `;` is the destination or computation of a C instruction
+/// instead.
+fn invocation_name_end(code: &str) -> Option<usize> {
+    let (name, arguments) = code.split_once(char::is_whitespace)?;
+    let arguments = arguments.trim();
+    (is_symbol(name) && !arguments.is_empty() && !arguments.starts_with(['=', ';']))
+        .then_some(name.len())
+}
+
+/// Draws a red zigzag line from `start` to `end` along `y`.
+fn draw_squiggle(ui: &Ui, start: f32, end: f32, y: f32) {
+    let mut points = vec![];
+    let mut x = start;
+    let mut up = false;
+    while x <= end {
+        points.push([x, if up { y - SQUIGGLE_STEP } else { y }]);
+        x += SQUIGGLE_STEP;
+        up = !up;
+    }
+    ui.get_window_draw_list()
+        .add_polyline(points, RED)
+        .thickness(1.0)
+        .build();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn labels_directives_and_addresses() {
+        assert_eq!(tokens("(LOOP)"), [(Token::Label, "(LOOP)")]);
+        assert_eq!(
+            tokens("#include lib.asm"),
+            [(Token::Directive, "#include lib.asm")]
+        );
+        assert_eq!(
+            tokens("  .equ SIZE 8"),
+            [(Token::Plain, "  "), (Token::Directive, ".equ SIZE 8")]
+        );
+        assert_eq!(tokens("@SIZE"), [(Token::Address, "@SIZE")]);
+    }
+
+    #[test]
+    fn c_instructions() {
+        assert_eq!(
+            tokens("D=M"),
+            [(Token::Destination, "D"), (Token::Plain, "=M")]
+        );
+        assert_eq!(
+            tokens("0;JMP"),
+            [(Token::Plain, "0"), (Token::Jump, ";JMP")]
+        );
+        assert_eq!(
+            tokens("    AM=M-1;JNE"),
+            [
+                (Token::Plain, "    "),
+                (Token::Destination, "AM"),
+                (Token::Plain, "=M-1"),
+                (Token::Jump, ";JNE"),
+            ]
+        );
+        // Spaces around the parts of a C instruction do not make it an invocation
+        assert_eq!(
+            tokens("D = A"),
+            [(Token::Destination, "D "), (Token::Plain, "= A")]
+        );
+    }
+
+    #[test]
+    fn invocations() {
+        assert_eq!(
+            tokens("LD D, 42"),
+            [(Token::Invocation, "LD"), (Token::Plain, " D, 42")]
+        );
+        assert_eq!(
+            tokens("  PUSH D // save"),
+            [
+                (Token::Plain, "  "),
+                (Token::Invocation, "PUSH"),
+                (Token::Plain, " D "),
+                (Token::Comment, "// save"),
+            ]
+        );
+        // A name without arguments is not an invocation
+        assert_eq!(tokens("RET"), [(Token::Plain, "RET")]);
+    }
+
+    #[test]
+    fn comments_keep_all_of_the_text() {
+        assert_eq!(tokens("// only"), [(Token::Comment, "// only")]);
+        assert_eq!(
+            tokens("@5 // five"),
+            [(Token::Address, "@5 "), (Token::Comment, "// five")]
+        );
+        assert!(tokens("").is_empty());
+        for line in ["  (END) // end", "D;JGT   ", "\tLD RAM[i], 0", "MD=D+1 //"] {
+            let text: String = tokens(line).into_iter().map(|(_, text)| text).collect();
+            assert_eq!(text, line);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::symbol_table::SymbolTable;
+
+/// Whether the char can be a part of a symbol, as defined by the HACK assembly specification.
+pub fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | ':')
+}
+
+/// Whether the text is a symbol, such as `LOOP` or `R0`, rather than a number or an expression.
+pub fn is_symbol(text: &str) -> bool {
+    text.chars().next().is_some_and(|c| !c.is_ascii_digit()) && text.chars().all(is_symbol_char)
+}
+
+/// The symbols used in a constant expression, such as `SCREEN` and `WIDTH` in `SCREEN+WIDTH*10`.
+/// Numbers and character literals are skipped.
+pub fn symbols_in(expression: &str) -> Vec<&str> {
+    let mut symbols = vec![];
+    let mut start = None;
+    let mut in_literal = false;
+    for (i, c) in expression.char_indices().chain([(expression.len(), ' ')]) {
+        if !in_literal && is_symbol_char(c) {
+            start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = start.take() {
+            let token = &expression[start..i];
+            if is_symbol(token) {
+                symbols.push(token);
+            }
+        }
+        if c == '\'' {
+            in_literal = !in_literal;
+        }
+    }
+    symbols
+}
+
+/// Parses a single number, written in decimal, hexadecimal (`0x4000`), binary (`0b1010`), or as
+/// the code of a character (`'A'`).
+pub fn parse_literal(literal: &str) -> Option<i32> {
+    if let Some(hex) = literal.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = literal.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()
+    } else if let Some(character) = literal
+        .strip_prefix('\'')
+        .and_then(|rest| rest.strip_suffix('\''))
+    {
+        let mut chars = character.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c as i32),
+            _ => None,
+        }
+    } else {
+        literal.parse::<i32>().ok()
+    }
+}
+
+/// Evaluates a constant expression, such as `SCREEN+32*10` or `KBD-1`. Expressions are made of
+/// literals (see [parse_literal]), symbols from the [SymbolTable], the operators `+`, `-`, `*`
+/// and `/`, and parentheses.
+pub fn evaluate(expression: &str, address_table: &SymbolTable) -> Result<i32, String> {
+    let mut evaluator = Evaluator {
+        chars: expression.chars().peekable(),
+        address_table,
+    };
+    let value = evaluator.sum()?;
+    evaluator.skip_spaces();
+    match evaluator.chars.next() {
+        None => Ok(value),
+        Some(c) => Err(format!("Unexpected {c} in {expression}")),
+    }
+}
+
+/// A recursive descent evaluator, where each method handles one level of operator precedence.
+struct Evaluator<'a> {
+    chars: Peekable<Chars<'a>>,
+    address_table: &'a SymbolTable,
+}
+
+impl Evaluator<'_> {
+    fn sum(self: &mut Self) -> Result<i32, String> {
+        let mut value = self.product()?;
+        loop {
+            self.skip_spaces();
+            let Some(operator) = self.chars.next_if(|c| matches!(c, '+' | '-')) else {
+                return Ok(value);
+            };
+            let rhs = self.product()?;
+            value = match operator {
+                '+' => value.checked_add(rhs),
+                _ => value.checked_sub(rhs),
+            }
+            .ok_or("The expression overflows")?;
+        }
+    }
+
+    fn product(self: &mut Self) -> Result<i32, String> {
+        let mut value = self.unary()?;
+        loop {
+            self.skip_spaces();
+            let Some(operator) = self.chars.next_if(|c| matches!(c, '*' | '/')) else {
+                return Ok(value);
+            };
+            let rhs = self.unary()?;
+            value = match operator {
+                '*' => value.checked_mul(rhs).ok_or("The expression overflows")?,
+                _ => value.checked_div(rhs).ok_or("Division by zero")?,
+            };
+        }
+    }
+
+    fn unary(self: &mut Self) -> Result<i32, String> {
+        self.skip_spaces();
+        if self.chars.next_if_eq(&'-').is_some() {
+            return self
+                .unary()?
+                .checked_neg()
+                .ok_or(String::from("The expression overflows"));
+        }
+        self.primary()
+    }
+
+    fn primary(self: &mut Self) -> Result<i32, String> {
+        match self.chars.peek() {
+            Some('(') => {
+                self.chars.next();
+                let value = self.sum()?;
+                self.skip_spaces();
+                match self.chars.next() {
+                    Some(')') => Ok(value),
+                    _ => Err(String::from("Missing a closing bracket")),
+                }
+            }
+            Some('\'') => {
+                let mut literal = String::from(self.chars.next().unwrap());
+                for c in self.chars.by_ref() {
+                    literal.push(c);
+                    if c == '\'' {
+                        break;
+                    }
+                }
+                parse_literal(&literal).ok_or(format!("Invalid character {literal}"))
+            }
+            Some(c) if is_symbol_char(*c) => {
+                let mut token = String::new();
+                while let Some(c) = self.chars.next_if(|c| is_symbol_char(*c)) {
+                    token.push(c);
+                }
+                if is_symbol(&token) {
+                    match self.address_table.table.get(&token) {
+                        Some(address) => Ok(*address as i32),
+                        None => Err(format!("Unknown symbol {token}")),
+                    }
+                } else {
+                    parse_literal(&token).ok_or(format!("Invalid number {token}"))
+                }
+            }
+            Some(c) => Err(format!("Unexpected {c}")),
+            None => Err(String::from("Unexpected end of the expression")),
+        }
+    }
+
+    fn skip_spaces(self: &mut Self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse_line;
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        symbols.table.insert(String::from("WIDTH"), 32);
+        symbols
+    }
+
+    fn eval(expression: &str) -> Result<i32, String> {
+        evaluate(expression, &symbols())
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(parse_literal("42"), Some(42));
+        assert_eq!(parse_literal("-7"), Some(-7));
+        assert_eq!(parse_literal("0x4000"), Some(16384));
+        assert_eq!(parse_literal("0xffff"), Some(65535));
+        assert_eq!(parse_literal("0b1010"), Some(10));
+        assert_eq!(parse_literal("'A'"), Some(65));
+        assert_eq!(parse_literal("' '"), Some(32));
+    }
+
+    #[test]
+    fn invalid_literals() {
+        assert_eq!(parse_literal(""), None);
+        assert_eq!(parse_literal("0x"), None);
+        assert_eq!(parse_literal("0xg"), None);
+        assert_eq!(parse_literal("0b102"), None);
+        assert_eq!(parse_literal("'AB'"), None);
+        assert_eq!(parse_literal("''"), None);
+        assert_eq!(parse_literal("'A"), None);
+        assert_eq!(parse_literal("LOOP"), None);
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1+2*3"), Ok(7));
+        assert_eq!(eval("(1+2)*3"), Ok(9));
+        assert_eq!(eval("10-4-3"), Ok(3));
+        assert_eq!(eval("100/10/5"), Ok(2));
+        assert_eq!(eval("7/2"), Ok(3));
+        assert_eq!(eval(" SCREEN + WIDTH * 10 "), Ok(16704));
+        assert_eq!(eval("KBD-1"), Ok(24575));
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(eval("-1"), Ok(-1));
+        assert_eq!(eval("--1"), Ok(1));
+        assert_eq!(eval("2*-3"), Ok(-6));
+        assert_eq!(eval("-(2+3)"), Ok(-5));
+        assert_eq!(eval("1 - -1"), Ok(2));
+    }
+
+    #[test]
+    fn literals_in_expressions() {
+        assert_eq!(eval("0x10+0b1"), Ok(17));
+        assert_eq!(eval("'a'-'A'"), Ok(32));
+        assert_eq!(eval("' '*2"), Ok(64));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval("UNKNOWN"), Err(String::from("Unknown symbol UNKNOWN")));
+        assert_eq!(eval("1/0"), Err(String::from("Division by zero")));
+        assert_eq!(eval("(1+2"), Err(String::from("Missing a closing bracket")));
+        assert_eq!(
+            eval("1+"),
+            Err(String::from("Unexpected end of the expression"))
+        );
+        assert_eq!(eval("1 2"), Err(String::from("Unexpected 2 in 1 2")));
+        assert_eq!(eval("1+)"), Err(String::from("Unexpected )")));
+        assert_eq!(eval("12abc"), Err(String::from("Invalid number 12abc")));
+    }
+
+    #[test]
+    fn symbols_in_expressions() {
+        assert_eq!(symbols_in("SCREEN+WIDTH*10"), ["SCREEN", "WIDTH"]);
+        assert_eq!(symbols_in("(LOOP$1 + 1) - R0"), ["LOOP$1", "R0"]);
+        assert_eq!(symbols_in("'A'+0x10+12"), Vec::<&str>::new());
+        assert_eq!(symbols_in("' '+X"), ["X"]);
+        assert_eq!(symbols_in(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        let overflow = Err(String::from("The expression overflows"));
+        assert_eq!(eval("2147483647+1"), overflow);
+        assert_eq!(eval("0-2147483647-2"), overflow);
+        assert_eq!(eval("65536*65536"), overflow);
+        assert_eq!(eval("-(0-2147483647-1)"), overflow);
+    }
+
+    #[test]
+    fn a_instructions_must_fit_in_15_bits() {
+        let mut symbols = symbols();
+        assert!(parse_line("@32767", &mut symbols).is_ok());
+        assert!(parse_line("@SCREEN*2-1", &mut symbols).is_ok());
+        assert!(parse_line("@32768", &mut symbols).is_err());
+        assert!(parse_line("@SCREEN*2", &mut symbols).is_err());
+        assert!(parse_line("@0-1", &mut symbols).is_err());
+    }
+}
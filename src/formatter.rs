@@ -0,0 +1,294 @@
+use crate::expression::is_symbol;
+use crate::parser::split_comment;
+
+const CHARACTER_QUOTE: char = '\'';
+const INDENT: &'static str = "    ";
+/// The fewest spaces between the code of a line and its trailing comment.
+const COMMENT_GAP: usize = 2;
+const DATA_DIRECTIVE: &'static str = ".data";
+const MACRO_DIRECTIVE: &'static str = "#macro";
+const LF: &'static str = "\n";
+const CRLF: &'static str = "\r\n";
+
+/// How a line of source code is laid out.
+#[derive(Clone, Copy, PartialEq)]
+enum Layout {
+    Blank,
+    /// A line holding only a comment, which is indented like the code after it.
+    Comment,
+    /// Labels and preprocessor directives, which start at the beginning of the line.
+    Unindented,
+    /// Instructions, directives, and uses of macros and pseudo-instructions.
+    Indented,
+}
+
+/// A line of source code, with its code formatted, but not yet indented.
+struct Line<'a> {
+    layout: Layout,
+    code: String,
+    comment: Option<&'a str>,
+}
+
+/// Formats the source code of a program in a consistent style, without changing what it
+/// assembles to. Labels and preprocessor directives start at the beginning of their line, and
+/// everything else is indented beneath them. Spaces are removed from instructions, and trailing
+/// comments are aligned within each paragraph. Comments are kept as they were written, and so are
+/// the line endings, which follow the first line of the source code.
+pub fn format(source: &str) -> String {
+    let mut lines: Vec<Line> = source.lines().map(parse_line).collect();
+    // Comments describe the code after them, so they are indented to match it, other than the
+    // comments at the top of the file, and those at the end of a paragraph
+    let first_code = lines
+        .iter()
+        .position(|line| matches!(line.layout, Layout::Unindented | Layout::Indented))
+        .unwrap_or(lines.len());
+    let mut next_indent = Layout::Unindented;
+    for (i, line) in lines.iter_mut().enumerate().rev() {
+        match line.layout {
+            Layout::Unindented | Layout::Indented => next_indent = line.layout,
+            Layout::Comment if i < first_code => line.layout = Layout::Unindented,
+            Layout::Comment => line.layout = next_indent,
+            Layout::Blank => next_indent = Layout::Unindented,
+        }
+    }
+
+    let mut formatted: Vec<String> = vec![];
+    for paragraph in lines.split(|line| line.layout == Layout::Blank) {
+        if paragraph.is_empty() {
+            continue;
+        }
+        if !formatted.is_empty() {
+            formatted.push(String::new());
+        }
+        let code: Vec<String> = paragraph.iter().map(indent).collect();
+        let comment_column = paragraph
+            .iter()
+            .zip(&code)
+            .filter(|(line, code)| line.comment.is_some() && !code.trim().is_empty())
+            .map(|(_, code)| code.chars().count() + COMMENT_GAP)
+            .max()
+            .unwrap_or(0);
+        for (line, code) in paragraph.iter().zip(code) {
+            formatted.push(match line.comment {
+                Some(comment) if line.code.is_empty() => format!("{code}{comment}"),
+                Some(comment) => format!("{code:<comment_column$}{comment}"),
+                None => code,
+            });
+        }
+    }
+    let line_ending = line_ending(source);
+    let mut formatted = formatted.join(line_ending);
+    formatted.push_str(line_ending);
+    formatted
+}
+
+/// The line ending of the first line of the source code, either `\n` or `\r\n`.
+fn line_ending(source: &str) -> &'static str {
+    match source.find(LF) {
+        Some(end) if source[..end].ends_with('\r') => CRLF,
+        _ => LF,
+    }
+}
+
+fn indent(line: &Line) -> String {
+    match line.layout {
+        Layout::Indented => format!("{INDENT}{}", line.code),
+        _ => line.code.clone(),
+    }
+}
+
+fn parse_line(line: &str) -> Line<'_> {
+    let (code, comment) = split_comment(line);
+    let comment = (!comment.is_empty()).then(|| comment.trim_end());
+    let code = code.trim();
+    let (layout, code) = if code.is_empty() {
+        match comment {
+            Some(_) => (Layout::Comment, String::new()),
+            None => (Layout::Blank, String::new()),
+        }
+    } else if code.starts_with('(') {
+        (Layout::Unindented, remove_spaces(code))
+    } else if code.starts_with('#') {
+        (Layout::Unindented, format_preprocessor_directive(code))
+    } else if code.starts_with('.') {
+        (Layout::Indented, format_directive(code))
+    } else if let Some((name, args)) = invocation(code) {
+        (Layout::Indented, format_invocation(name, args))
+    } else {
+        (Layout::Indented, remove_spaces(code))
+    };
+    Line {
+        layout,
+        code,
+        comment,
+    }
+}
+
+/// Splits a use of a macro or a pseudo-instruction, such as `LD D, 42`, into its name and
+/// arguments. A name followed by `=` or `;` is the destination or computation of a C instruction
+/// instead.
+fn invocation(code: &str) -> Option<(&str, &str)> {
+    let (name, args) = code.split_once(char::is_whitespace)?;
+    let args = args.trim();
+    if !is_symbol(name) || args.starts_with(['=', ';']) {
+        return None;
+    }
+    Some((name, args))
+}
+
+fn format_invocation(name: &str, args: &str) -> String {
+    let args: Vec<String> = args.split(',').map(remove_spaces).collect();
+    format!("{name} {}", args.join(", "))
+}
+
+/// Formats a preprocessor directive. The parameters of a `#macro` are separated by commas, and the
+/// arguments of other directives are kept as they were written, so that quoted paths are intact.
+fn format_preprocessor_directive(code: &str) -> String {
+    let (directive, args) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+    let args = args.trim();
+    if args.is_empty() {
+        return directive.to_string();
+    }
+    if directive != MACRO_DIRECTIVE {
+        return format!("{directive} {args}");
+    }
+    match args.split_once(char::is_whitespace) {
+        Some((name, parameters)) => format!("{directive} {}", format_invocation(name, parameters)),
+        None => format!("{directive} {args}"),
+    }
+}
+
+/// Formats a directive, collapsing its spaces, and separating the values of a `.data` directive
+/// with commas.
+fn format_directive(code: &str) -> String {
+    let code = collapse_spaces(code);
+    match code.split_once(':') {
+        Some((address, values)) if code.starts_with(DATA_DIRECTIVE) => {
+            let values: Vec<&str> = values.split(',').map(|value| value.trim()).collect();
+            format!("{}: {}", address.trim(), values.join(", "))
+        }
+        _ => code,
+    }
+}
+
+/// Removes the spaces from code, other than those within character literals.
+fn remove_spaces(code: &str) -> String {
+    let mut in_literal = false;
+    code.chars()
+        .filter(|c| {
+            if *c == CHARACTER_QUOTE {
+                in_literal = !in_literal;
+            }
+            in_literal || !c.is_whitespace()
+        })
+        .collect()
+}
+
+/// Replaces every run of spaces in the code with a single space, other than within character
+/// literals.
+fn collapse_spaces(code: &str) -> String {
+    let mut collapsed = String::new();
+    let mut in_literal = false;
+    let mut previous_space = false;
+    for c in code.chars() {
+        if c == CHARACTER_QUOTE {
+            in_literal = !in_literal;
+        }
+        let space = !in_literal && c.is_whitespace();
+        if space && previous_space {
+            continue;
+        }
+        collapsed.push(if space { ' ' } else { c });
+        previous_space = space;
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::preprocessor;
+    use crate::split_source;
+    use crate::symbol_table::SymbolTable;
+    use crate::test::on_large_stack;
+    use std::path::Path;
+
+    /// A program written carelessly, using each kind of line the formatter lays out.
+    const UNFORMATTED: &'static str = "// Fills the screen
+  .equ   ROWS   256
+.data 100 :1,2 ,  3
+#extended
+#macro   ADD   X,Y
+@{X}
+D = D + A  // add
+   #endmacro
+
+  (LOOP)
+  // the loop body
+@ LOOP
+ ADD  1 , 2
+LD D ,RAM[ 100 ]
+  @' '
+ D=D+A // space
+    0 ; JMP
+// done
+";
+
+    const FORMATTED: &'static str = "// Fills the screen
+    .equ ROWS 256
+    .data 100: 1, 2, 3
+#extended
+#macro ADD X, Y
+    @{X}
+    D=D+A  // add
+#endmacro
+
+(LOOP)
+    // the loop body
+    @LOOP
+    ADD 1, 2
+    LD D, RAM[100]
+    @' '
+    D=D+A  // space
+    0;JMP
+// done
+";
+
+    #[test]
+    fn formats_each_kind_of_line() {
+        assert_eq!(format(UNFORMATTED), FORMATTED);
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        assert_eq!(format(FORMATTED), FORMATTED);
+        assert_eq!(format(&format(UNFORMATTED)), format(UNFORMATTED));
+        assert_eq!(format(""), "\n");
+        assert_eq!(format("\n\n@1\n\n\n@2\n\n"), "    @1\n\n    @2\n");
+    }
+
+    #[test]
+    fn line_endings_are_kept() {
+        let crlf = FORMATTED.replace('\n', "\r\n");
+        assert_eq!(format(&crlf), crlf);
+        assert_eq!(format(&UNFORMATTED.replace('\n', "\r\n")), crlf);
+        assert_eq!(format("@1\nD=A\r\n"), "    @1\n    D=A\n");
+        assert_eq!(format("@1"), "    @1\n");
+    }
+
+    #[test]
+    fn formatting_keeps_the_assembled_program() {
+        on_large_stack(|| {
+            let assemble = |source: &str| {
+                let lines = split_source(source).unwrap();
+                preprocessor::assemble(&lines, Path::new("Test.asm"), &mut SymbolTable::new())
+                    .unwrap()
+            };
+            let before = assemble(UNFORMATTED);
+            let after = assemble(&format(UNFORMATTED));
+            assert!(after.instructions == before.instructions);
+            assert_eq!(after.data, before.data);
+            assert_eq!(after.source_map, before.source_map);
+        });
+    }
+}
@@ -0,0 +1,498 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::num::Wrapping;
+use std::ops::Range;
+
+use crate::debug::Breakpoint;
+use crate::hack_cpu::CPUState;
+use crate::instructions::Instruction;
+use crate::parser::MAX_RAM;
+use crate::MAX_INSTRUCTIONS;
+
+pub const DEFAULT_PORT: u16 = 1234;
+
+/// The number of instructions executed between checks for an interrupt from the debugger while
+/// continuing.
+const INSTRUCTIONS_PER_POLL: usize = 100_000;
+/// The byte sent by the debugger to interrupt a running program.
+const INTERRUPT: u8 = 0x03;
+/// Sent in reply to commands that are not supported.
+const UNSUPPORTED: &'static str = "";
+/// The stop reply for a program that stopped on a breakpoint, a step, or an interrupt (SIGTRAP).
+const STOPPED: &'static str = "S05";
+/// The stop reply for a program whose program counter left the ROM.
+const EXITED: &'static str = "W00";
+
+/// Describes the registers to the debugger, in the order used by the `g` and `p` commands.
+const TARGET_XML: &'static str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nand2tetris.hack.core">
+    <reg name="a" bitsize="16" type="int16" regnum="0"/>
+    <reg name="d" bitsize="16" type="int16" regnum="1"/>
+    <reg name="pc" bitsize="16" type="code_ptr" regnum="2"/>
+  </feature>
+</target>"#;
+
+/// A message from the debugger.
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+/// Serves a single debugger over the GDB remote serial protocol. The registers A, D and PC are
+/// exposed as registers 0, 1 and 2, and the RAM as memory, where every 16 bit word takes up two
+/// bytes in little endian order, so `RAM[n]` lives at byte address `2n`.
+struct GdbStub<'a> {
+    cpu: CPUState,
+    instructions: &'a [Instruction; MAX_INSTRUCTIONS],
+    stream: TcpStream,
+}
+
+/// Listens on `port` for a debugger, and serves it until it detaches or kills the program.
+pub fn serve(
+    port: u16,
+    cpu: CPUState,
+    instructions: &[Instruction; MAX_INSTRUCTIONS],
+) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for a debugger on port {port}");
+    let (stream, address) = listener.accept()?;
+    println!("Debugger connected from {address}");
+    stream.set_nodelay(true)?;
+    let mut stub = GdbStub {
+        cpu,
+        instructions,
+        stream,
+    };
+    stub.run()
+}
+
+impl<'a> GdbStub<'a> {
+    /// Handles commands until the debugger goes away.
+    fn run(self: &mut Self) -> io::Result<()> {
+        loop {
+            let command = match self.read_packet()? {
+                Some(Packet::Command(command)) => command,
+                Some(Packet::Interrupt) => {
+                    self.send(STOPPED)?;
+                    continue;
+                }
+                None => return Ok(()),
+            };
+            match command.as_bytes().first() {
+                Some(b'k') => return Ok(()),
+                Some(b'D') => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                _ => {
+                    let reply = self.handle(&command)?;
+                    self.send(&reply)?;
+                }
+            }
+        }
+    }
+
+    /// Executes a single command, and returns the reply to it.
+    fn handle(self: &mut Self, command: &str) -> io::Result<String> {
+        let kind = command.get(..1).unwrap_or("");
+        let args = command.get(1..).unwrap_or("");
+        let reply = match kind {
+            "?" => String::from(STOPPED),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "c" => self.resume()?,
+            "s" => self.step(),
+            "Z" => self.breakpoint(args, true),
+            "z" => self.breakpoint(args, false),
+            "H" => String::from("OK"),
+            "q" => self.query(command),
+            _ => String::from(UNSUPPORTED),
+        };
+        Ok(reply)
+    }
+
+    fn query(self: &Self, command: &str) -> String {
+        if command.starts_with("qSupported") {
+            String::from("PacketSize=4000;qXfer:features:read+")
+        } else if let Some(args) = command.strip_prefix("qXfer:features:read:target.xml:") {
+            match parse_address_length(args) {
+                Some((offset, length)) => {
+                    let offset = offset.min(TARGET_XML.len());
+                    let end = (offset + length).min(TARGET_XML.len());
+                    let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                    format!("{marker}{}", &TARGET_XML[offset..end])
+                }
+                None => String::from("E01"),
+            }
+        } else if command == "qAttached" {
+            String::from("1")
+        } else if command == "qC" {
+            String::from("QC1")
+        } else if command == "qfThreadInfo" {
+            String::from("m1")
+        } else if command == "qsThreadInfo" {
+            String::from("l")
+        } else {
+            String::from(UNSUPPORTED)
+        }
+    }
+
+    fn registers(self: &Self) -> [i16; 3] {
+        [self.cpu.a.0, self.cpu.d.0, self.cpu.pc as i16]
+    }
+
+    fn set_register(self: &mut Self, register: usize, value: i16) {
+        match register {
+            0 => self.cpu.a = Wrapping(value),
+            1 => self.cpu.d = Wrapping(value),
+            _ => self.cpu.pc = value as u16,
+        }
+    }
+
+    fn read_registers(self: &Self) -> String {
+        self.registers().iter().map(|r| encode_word(*r)).collect()
+    }
+
+    fn write_registers(self: &mut Self, args: &str) -> String {
+        for register in 0..3 {
+            match args
+                .get(register * 4..register * 4 + 4)
+                .and_then(decode_word)
+            {
+                Some(value) => self.set_register(register, value),
+                None => return String::from("E01"),
+            }
+        }
+        String::from("OK")
+    }
+
+    fn read_register(self: &Self, args: &str) -> String {
+        match usize::from_str_radix(args, 16) {
+            Ok(register) if register < 3 => encode_word(self.registers()[register]),
+            _ => String::from("E01"),
+        }
+    }
+
+    fn write_register(self: &mut Self, args: &str) -> String {
+        let parsed = args.split_once('=').and_then(|(register, value)| {
+            let register = usize::from_str_radix(register, 16).ok()?;
+            Some((register, decode_word(value)?))
+        });
+        match parsed {
+            Some((register, value)) if register < 3 => {
+                self.set_register(register, value);
+                String::from("OK")
+            }
+            _ => String::from("E01"),
+        }
+    }
+
+    fn read_memory(self: &Self, args: &str) -> String {
+        let Some(bytes) = parse_address_length(args).and_then(memory_bytes) else {
+            return String::from("E01");
+        };
+        bytes
+            .map(|byte| {
+                let word = self.cpu.ram[byte / 2].0.to_le_bytes();
+                format!("{:02x}", word[byte % 2])
+            })
+            .collect()
+    }
+
+    fn write_memory(self: &mut Self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return String::from("E01");
+        };
+        let Some(bytes) = parse_address_length(range).and_then(memory_bytes) else {
+            return String::from("E01");
+        };
+        // Each byte is two hex digits, which are only sliced apart safely in ASCII
+        if !data.is_ascii() || Some(data.len()) != bytes.len().checked_mul(2) {
+            return String::from("E01");
+        }
+        for (i, byte) in bytes.enumerate() {
+            let Ok(value) = u8::from_str_radix(&data[i * 2..i * 2 + 2], 16) else {
+                return String::from("E01");
+            };
+            let mut word = self.cpu.ram[byte / 2].0.to_le_bytes();
+            word[byte % 2] = value;
+            self.cpu.ram[byte / 2] = Wrapping(i16::from_le_bytes(word));
+        }
+        String::from("OK")
+    }
+
+    /// Inserts or removes a breakpoint. Software and hardware breakpoints are both mapped onto
+    /// [Breakpoint::PC], where the address is the index of the instruction in the ROM.
+    fn breakpoint(self: &mut Self, args: &str, insert: bool) -> String {
+        let mut parts = args.split(',');
+        let (Some(kind), Some(address)) = (parts.next(), parts.next()) else {
+            return String::from("E01");
+        };
+        if kind != "0" && kind != "1" {
+            return String::from(UNSUPPORTED);
+        }
+        let Ok(address) = u16::from_str_radix(address, 16) else {
+            return String::from("E01");
+        };
+        if insert {
+            self.cpu.breakpoints.insert(Breakpoint::PC(address));
+        } else {
+            self.cpu.breakpoints.remove(&Breakpoint::PC(address));
+        }
+        String::from("OK")
+    }
+
+    fn halted(self: &Self) -> bool {
+        self.cpu.pc as usize >= MAX_INSTRUCTIONS
+    }
+
+    fn step(self: &mut Self) -> String {
+        if self.halted() {
+            return String::from(EXITED);
+        }
+        self.cpu.interpret(&self.instructions[self.cpu.pc as usize]);
+        String::from(if self.halted() { EXITED } else { STOPPED })
+    }
+
+    /// Runs the program until it hits a breakpoint, leaves the ROM, or is interrupted by the
+    /// debugger.
+    fn resume(self: &mut Self) -> io::Result<String> {
+        loop {
+            for _ in 0..INSTRUCTIONS_PER_POLL {
+                if self.halted() {
+                    return Ok(String::from(EXITED));
+                }
+                self.cpu.interpret(&self.instructions[self.cpu.pc as usize]);
+                if self.cpu.hit_breakpoint() {
+                    return Ok(String::from(STOPPED));
+                }
+            }
+            if self.interrupted()? {
+                return Ok(String::from(STOPPED));
+            }
+        }
+    }
+
+    /// Checks, without blocking, whether the debugger has sent an interrupt.
+    fn interrupted(self: &mut Self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0u8];
+        let result = match self.stream.read(&mut byte) {
+            Ok(1) => Ok(byte[0] == INTERRUPT),
+            Ok(_) => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    fn read_byte(self: &mut Self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Reads the next packet from the debugger, acknowledging it. Returns [None] once the
+    /// debugger has disconnected.
+    fn read_packet(self: &mut Self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Packet::Interrupt)),
+                Some(b'$') => break,
+                // Acknowledgements, and anything else between packets
+                Some(_) => continue,
+            }
+        }
+
+        let mut data = vec![];
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+        let mut checksum = [0u8; 2];
+        self.stream.read_exact(&mut checksum)?;
+
+        let expected = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|c| u8::from_str_radix(c, 16).ok());
+        if expected != Some(checksum_of(&data)) {
+            self.stream.write_all(b"-")?;
+            return self.read_packet();
+        }
+        self.stream.write_all(b"+")?;
+        Ok(Some(Packet::Command(
+            String::from_utf8_lossy(&data).into_owned(),
+        )))
+    }
+
+    fn send(self: &mut Self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Encodes a 16 bit register as hex, in little endian byte order.
+fn encode_word(value: i16) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Decodes a 16 bit register encoded by [encode_word].
+fn decode_word(hex: &str) -> Option<i16> {
+    let low = u8::from_str_radix(hex.get(0..2)?, 16).ok()?;
+    let high = u8::from_str_radix(hex.get(2..4)?, 16).ok()?;
+    Some(i16::from_le_bytes([low, high]))
+}
+
+/// Parses the `address,length` arguments, both in hex, shared by the memory commands.
+fn parse_address_length(args: &str) -> Option<(usize, usize)> {
+    let (address, length) = args.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+/// The bytes of the RAM addressed by the memory commands, where each word is two bytes, or [None]
+/// if any of them are outside of the RAM.
+fn memory_bytes((address, length): (usize, usize)) -> Option<Range<usize>> {
+    let end = address.checked_add(length)?;
+    (end <= MAX_RAM * 2).then_some(address..end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::on_large_stack;
+
+    /// Connects a stub to a debugger over the loopback interface, returning both ends.
+    fn connect(instructions: &[Instruction; MAX_INSTRUCTIONS]) -> (GdbStub<'_>, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let debugger = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let stub = GdbStub {
+            cpu: CPUState::new(),
+            instructions,
+            stream,
+        };
+        (stub, debugger)
+    }
+
+    fn received(debugger: &mut TcpStream, length: usize) -> String {
+        let mut bytes = vec![0; length];
+        debugger.read_exact(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn command(stub: &mut GdbStub) -> Option<String> {
+        match stub.read_packet().unwrap() {
+            Some(Packet::Command(command)) => Some(command),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn packets_are_framed_and_checked() {
+        on_large_stack(|| {
+            let instructions = Box::new([const { Instruction::None }; MAX_INSTRUCTIONS]);
+            let (mut stub, mut debugger) = connect(&instructions);
+            assert_eq!(checksum_of(b"OK"), 0x9a);
+            assert_eq!(checksum_of(b""), 0);
+
+            debugger.write_all(b"+$m0,4#fd").unwrap();
+            assert_eq!(command(&mut stub).as_deref(), Some("m0,4"));
+            assert_eq!(received(&mut debugger, 1), "+");
+
+            // A packet with the wrong checksum is refused, and the next one is read instead
+            debugger.write_all(b"$g#00$g#67").unwrap();
+            assert_eq!(command(&mut stub).as_deref(), Some("g"));
+            assert_eq!(received(&mut debugger, 2), "-+");
+
+            debugger.write_all(&[INTERRUPT]).unwrap();
+            assert!(matches!(
+                stub.read_packet().unwrap(),
+                Some(Packet::Interrupt)
+            ));
+
+            stub.send("OK").unwrap();
+            assert_eq!(received(&mut debugger, 6), "$OK#9a");
+
+            drop(debugger);
+            assert!(stub.read_packet().unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn memory_is_little_endian() {
+        on_large_stack(|| {
+            let instructions = Box::new([const { Instruction::None }; MAX_INSTRUCTIONS]);
+            let (mut stub, _debugger) = connect(&instructions);
+            stub.cpu.ram[1] = Wrapping(0x1234);
+            assert_eq!(stub.handle("m2,2").unwrap(), "3412");
+            assert_eq!(stub.handle("m3,1").unwrap(), "12");
+
+            assert_eq!(stub.handle("M0,2:cdab").unwrap(), "OK");
+            assert_eq!(stub.cpu.ram[0].0, 0xabcd_u16 as i16);
+            // Writing a single byte keeps the other byte of the word
+            assert_eq!(stub.handle("M3,1:ff").unwrap(), "OK");
+            assert_eq!(stub.cpu.ram[1].0, 0xff34_u16 as i16);
+
+            let last = MAX_RAM * 2 - 1;
+            assert_eq!(stub.handle(&format!("m{last:x},1")).unwrap(), "00");
+            assert_eq!(stub.handle(&format!("m{last:x},2")).unwrap(), "E01");
+            assert_eq!(stub.handle("M0,2:abc").unwrap(), "E01");
+            assert_eq!(stub.handle("M0,1:zz").unwrap(), "E01");
+            assert_eq!(stub.handle("M0,1").unwrap(), "E01");
+        });
+    }
+
+    #[test]
+    fn addresses_and_lengths() {
+        assert_eq!(parse_address_length("1f,a"), Some((31, 10)));
+        assert_eq!(parse_address_length("zz,1"), None);
+        assert_eq!(parse_address_length("10"), None);
+        assert_eq!(memory_bytes((0, MAX_RAM * 2)), Some(0..MAX_RAM * 2));
+        assert_eq!(memory_bytes((1, MAX_RAM * 2)), None);
+        assert_eq!(memory_bytes((usize::MAX, 2)), None);
+    }
+
+    #[test]
+    fn breakpoints_are_inserted_and_removed() {
+        on_large_stack(|| {
+            let instructions = Box::new([const { Instruction::None }; MAX_INSTRUCTIONS]);
+            let (mut stub, _debugger) = connect(&instructions);
+            assert_eq!(stub.handle("Z0,1f,1").unwrap(), "OK");
+            assert_eq!(stub.handle("Z1,20,1").unwrap(), "OK");
+            let expected = [Breakpoint::PC(31), Breakpoint::PC(32)];
+            assert_eq!(stub.cpu.breakpoints, expected.into_iter().collect());
+
+            assert_eq!(stub.handle("z0,1f,1").unwrap(), "OK");
+            assert!(!stub.cpu.breakpoints.contains(&Breakpoint::PC(31)));
+            // Watchpoints are not supported
+            assert_eq!(stub.handle("Z2,0,2").unwrap(), UNSUPPORTED);
+            assert_eq!(stub.handle("Z0").unwrap(), "E01");
+            assert_eq!(stub.handle("Z0,zz,1").unwrap(), "E01");
+        });
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use image::{ImageFormat, Rgb, RgbImage};
+
+use crate::hack_cpu::CPUState;
+use crate::screen_capture::{screen_image, PNG_FILE_EXTENSION};
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Pixels of a reference image darker than this are black, so that references saved with other
+/// colours, or as greyscale, still compare as the black and white of the screen.
+const BLACK_THRESHOLD: u32 = 128;
+/// The colours of the diff image. Pixels that match are shown faded, so that the differences
+/// stand out.
+const MATCHING_BLACK: Rgb<u8> = Rgb([150, 150, 150]);
+const MATCHING_WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+/// A pixel that is black in the reference, but white on the screen.
+const MISSING_PIXEL: Rgb<u8> = Rgb([255, 0, 0]);
+/// A pixel that is white in the reference, but black on the screen.
+const EXTRA_PIXEL: Rgb<u8> = Rgb([0, 0, 255]);
+/// Added to the name of a reference to name its diff image, such as `Square.diff.png`.
+const DIFF_SUFFIX: &'static str = "diff";
+
+/// A screen that did not match its reference image.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub reference: PathBuf,
+    /// The number of pixels that differ.
+    pub pixels: usize,
+    /// Where the image highlighting the differing pixels was saved.
+    pub diff: PathBuf,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The screen does not match {}: {} of {} pixels differ, see {}",
+            self.reference.display(),
+            self.pixels,
+            SCREEN_WIDTH * SCREEN_HEIGHT,
+            self.diff.display()
+        )
+    }
+}
+
+/// The path the diff image of a reference is saved to unless another is chosen, which is beside
+/// the reference.
+pub fn default_diff_path(reference: &Path) -> PathBuf {
+    let stem = reference
+        .file_stem()
+        .map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    reference.with_file_name(format!("{stem}.{DIFF_SUFFIX}.{PNG_FILE_EXTENSION}"))
+}
+
+/// Compares the screen against a reference PNG image. If any pixel differs, an image is saved to
+/// `diff` with the differing pixels in red where the reference is black, and in blue where it is
+/// white.
+pub fn compare_screen(
+    cpu: &CPUState,
+    reference: &Path,
+    diff: &Path,
+) -> Result<Option<Mismatch>, String> {
+    let expected = image::open(reference)
+        .map_err(|e| format!("Failed to open reference {}: {e}", reference.display()))?
+        .to_rgb8();
+    if expected.dimensions() != (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32) {
+        return Err(format!(
+            "Reference {} is {}x{}, but the screen is {SCREEN_WIDTH}x{SCREEN_HEIGHT}",
+            reference.display(),
+            expected.width(),
+            expected.height()
+        ));
+    }
+    let actual = screen_image(cpu);
+    let mut pixels = 0;
+    let diff_image = RgbImage::from_fn(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, |x, y| {
+        match (
+            is_black(expected.get_pixel(x, y)),
+            is_black(actual.get_pixel(x, y)),
+        ) {
+            (true, true) => MATCHING_BLACK,
+            (false, false) => MATCHING_WHITE,
+            (true, false) => {
+                pixels += 1;
+                MISSING_PIXEL
+            }
+            (false, true) => {
+                pixels += 1;
+                EXTRA_PIXEL
+            }
+        }
+    });
+    if pixels == 0 {
+        return Ok(None);
+    }
+    diff_image
+        .save_with_format(diff, ImageFormat::Png)
+        .map_err(|e| format!("Failed to save diff {}: {e}", diff.display()))?;
+    Ok(Some(Mismatch {
+        reference: reference.to_path_buf(),
+        pixels,
+        diff: diff.to_path_buf(),
+    }))
+}
+
+fn is_black(pixel: &Rgb<u8>) -> bool {
+    let [r, g, b] = pixel.0;
+    (r as u32 + g as u32 + b as u32) / 3 < BLACK_THRESHOLD
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SCREEN_LOCATION;
+    use std::fs;
+    use std::num::Wrapping;
+
+    /// Saves a white reference image to a directory of its own, with the given pixels coloured.
+    fn reference(name: &str, pixels: &[(u32, u32, Rgb<u8>)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("hack_golden_{name}"));
+        fs::create_dir_all(&directory).unwrap();
+        let mut image =
+            RgbImage::from_pixel(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, MATCHING_WHITE);
+        for (x, y, colour) in pixels {
+            image.put_pixel(*x, *y, *colour);
+        }
+        let path = directory.join("Reference.png");
+        image.save_with_format(&path, ImageFormat::Png).unwrap();
+        path
+    }
+
+    #[test]
+    fn differing_pixels_are_coloured() {
+        // A dark grey pixel in the reference counts as black
+        let reference = reference(
+            "mismatch",
+            &[(0, 0, Rgb([0, 0, 0])), (1, 0, Rgb([100, 100, 100]))],
+        );
+        let diff = default_diff_path(&reference);
+        let _ = fs::remove_file(&diff);
+        let mut cpu = CPUState::new();
+        // The least significant bit is the leftmost pixel
+        cpu.ram[SCREEN_LOCATION] = Wrapping(0b100001);
+
+        let mismatch = compare_screen(&cpu, &reference, &diff).unwrap().unwrap();
+        assert_eq!(mismatch.pixels, 2);
+        assert_eq!(mismatch.diff, diff);
+        let image = image::open(&diff).unwrap().to_rgb8();
+        assert_eq!(*image.get_pixel(0, 0), MATCHING_BLACK);
+        assert_eq!(*image.get_pixel(1, 0), MISSING_PIXEL);
+        assert_eq!(*image.get_pixel(5, 0), EXTRA_PIXEL);
+        assert_eq!(*image.get_pixel(2, 0), MATCHING_WHITE);
+        assert_eq!(*image.get_pixel(0, 1), MATCHING_WHITE);
+    }
+
+    #[test]
+    fn matching_screens_save_no_diff() {
+        let reference = reference("match", &[(17, 1, Rgb([0, 0, 0]))]);
+        let diff = default_diff_path(&reference);
+        let _ = fs::remove_file(&diff);
+        let mut cpu = CPUState::new();
+        cpu.ram[SCREEN_LOCATION + 32 + 1] = Wrapping(0b10);
+
+        assert!(compare_screen(&cpu, &reference, &diff).unwrap().is_none());
+        assert!(!diff.exists());
+    }
+
+    #[test]
+    fn references_must_have_the_size_of_the_screen() {
+        let directory = std::env::temp_dir().join("hack_golden_size");
+        fs::create_dir_all(&directory).unwrap();
+        let reference = directory.join("Small.png");
+        RgbImage::new(16, 16)
+            .save_with_format(&reference, ImageFormat::Png)
+            .unwrap();
+        let error = compare_screen(
+            &CPUState::new(),
+            &reference,
+            &directory.join("Small.diff.png"),
+        )
+        .unwrap_err();
+        assert!(error.ends_with("is 16x16, but the screen is 512x256"));
+    }
+
+    #[test]
+    fn diffs_are_saved_beside_the_reference() {
+        assert_eq!(
+            default_diff_path(Path::new("screens/Square.png")),
+            PathBuf::from("screens/Square.diff.png")
+        );
+    }
+}
@@ -1,7 +1,11 @@
+use crate::coverage::Coverage;
 use crate::debug::Breakpoint;
-use crate::instructions::{Comp, Destination, Instruction, Jump, A, C};
+use crate::instructions::{Comp, Destination, Instruction, A, C};
+use crate::memory_activity::MemoryActivity;
 use crate::parser::MAX_RAM;
+use crate::profiler::Profiler;
 use crate::symbol_table;
+use crate::trace::{MemoryAccess, TraceEntry, Tracer};
 use std::collections::HashSet;
 use std::{
     num::Wrapping,
@@ -11,7 +15,10 @@ use std::{
 
 /// Represents the HACK CPU state, including the 3 registers, and the RAM. It additionally stores
 /// the [symbol_table::SymbolTable] (also known as an address table, useful for the labels in the program code) and
-/// the [Breakpoint]s (used for debugging programs).
+/// the [Breakpoint]s (used for debugging programs), and optionally a [Tracer] which records every
+/// executed instruction, a [Profiler] which counts how often each instruction is executed, a
+/// [Coverage] which records which instructions and jumps have been exercised, and a
+/// [MemoryActivity] which counts the reads and writes of each RAM address.
 #[derive(Debug)]
 pub struct CPUState {
     pub a: Wrapping<i16>,
@@ -20,6 +27,10 @@ pub struct CPUState {
     pub ram: [Wrapping<i16>; MAX_RAM],
     pub address_table: symbol_table::SymbolTable,
     pub breakpoints: HashSet<Breakpoint>,
+    pub tracer: Option<Tracer>,
+    pub profiler: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+    pub memory_activity: Option<MemoryActivity>,
 }
 
 impl CPUState {
@@ -36,6 +47,10 @@ impl CPUState {
             ram: std::array::from_fn(|_| Wrapping(0)),
             address_table: symbol_table::SymbolTable::new(),
             breakpoints: HashSet::new(),
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            memory_activity: None,
         }
     }
 
@@ -47,11 +62,66 @@ impl CPUState {
 
     /// Executes the next instruction, according to the program counter (PC) register
     pub fn interpret(self: &mut Self, instruction: &Instruction) {
+        let pc = self.pc;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc);
+        }
+        if let Some(activity) = &mut self.memory_activity {
+            activity.record(instruction, self.a.0 as u16);
+        }
+        if self.tracer.is_some() {
+            self.traced_interpret(instruction);
+        } else {
+            self.execute(instruction);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, instruction, self.pc);
+        }
+    }
+
+    /// Executes a single instruction, without recording it in the profiler, coverage, tracer or
+    /// memory activity, as for instructions that are not part of the program. Returns whether the
+    /// instruction jumped, which may be to the next address.
+    pub fn execute(self: &mut Self, instruction: &Instruction) -> bool {
         match instruction {
             Instruction::A(a) => self.a_instruction(&a),
-            Instruction::C(c) => self.c_instruction(&c),
+            Instruction::C(c) => return self.c_instruction(&c),
             Instruction::Label(_) | Instruction::None => self.pc += 1,
         }
+        false
+    }
+
+    /// Executes the next instruction, and records it, along with its effects, in the [Tracer].
+    fn traced_interpret(self: &mut Self, instruction: &Instruction) {
+        let (pc, a, d) = (self.pc, self.a.0, self.d.0);
+        let address = a as u16;
+        let (reads, writes) = match instruction {
+            Instruction::C(c) => (c.comp.reads_memory(), c.dest.writes_memory()),
+            _ => (false, false),
+        };
+        let read = reads.then(|| MemoryAccess {
+            address,
+            value: self.ram[address as usize].0,
+        });
+
+        self.execute(instruction);
+
+        let entry = TraceEntry {
+            pc,
+            instruction: instruction.machine_code(),
+            a_before: a,
+            d_before: d,
+            a_after: self.a.0,
+            d_after: self.d.0,
+            read,
+            write: writes.then(|| MemoryAccess {
+                address,
+                value: self.ram[address as usize].0,
+            }),
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(entry);
+        }
     }
 
     /// Executes an A instruction
@@ -60,8 +130,8 @@ impl CPUState {
         self.pc += 1;
     }
 
-    /// Executes a C instruction
-    fn c_instruction(self: &mut Self, c: &C) {
+    /// Executes a C instruction, returning whether it jumped.
+    fn c_instruction(self: &mut Self, c: &C) -> bool {
         let answer: Wrapping<i16> = match c.comp {
             Comp::Zero => Wrapping(0),
             Comp::One => Wrapping(1),
@@ -125,53 +195,24 @@ impl CPUState {
             }
         }
 
-        self.pc = match c.jump {
-            Jump::None => self.pc + 1,
-            Jump::JGT => {
-                if answer > Wrapping(0) {
-                    // TODO check that A > 0?
-                    self.a.0 as u16
-                } else {
-                    self.pc + 1
-                }
-            }
-            Jump::JEQ => {
-                if answer == Wrapping(0) {
-                    self.a.0 as u16
-                } else {
-                    self.pc + 1
-                }
-            }
-            Jump::JGE => {
-                if answer >= Wrapping(0) {
-                    self.a.0 as u16
-                } else {
-                    self.pc + 1
-                }
-            }
-            Jump::JLT => {
-                if answer < Wrapping(0) {
-                    self.a.0 as u16
-                } else {
-                    self.pc + 1
-                }
-            }
-            Jump::JNE => {
-                if answer != Wrapping(0) {
-                    self.a.0 as u16
-                } else {
-                    self.pc + 1
-                }
-            }
-            Jump::JLE => {
-                if answer <= Wrapping(0) {
-                    self.a.0 as u16
-                } else {
-                    self.pc + 1
-                }
-            }
-            Jump::JMP => self.a.0 as u16,
-        };
+        let jumped = c.jump.taken(answer.0);
+        self.pc = if jumped { self.a.0 as u16 } else { self.pc + 1 };
+        jumped
+    }
+
+    /// Whether the current state of the CPU matches any of its [Breakpoint]s, meaning that
+    /// execution should halt.
+    pub fn hit_breakpoint(self: &Self) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.hit(self))
+    }
+
+    /// Writes the initial values of a program, set by its `.data` directives, to the RAM.
+    pub fn load_data(self: &mut Self, data: &[(u16, i16)]) {
+        for (address, value) in data {
+            self.ram[*address as usize] = Wrapping(*value);
+        }
     }
 
     /// Resets the RAM of the CPU to be all zeroes once more
@@ -1,7 +1,24 @@
+use crate::call_stack::{self, Frame};
+use crate::cfg::{ControlFlowGraph, DOT_FILE_EXTENSION};
+use crate::coverage::{Coverage, CoverageState};
 use crate::debug::{Breakpoint, BreakpointSelector, RED};
-use crate::instructions::Instruction;
-use crate::parser::{parse, LineParsingError, MAX_RAM};
-use crate::{CPUState, ASM_FILE_EXTENSION, SCREEN_RATIO};
+use crate::editor::Editor;
+use crate::instructions::{Instruction, Jump};
+use crate::lint::{self, Warning};
+use crate::memory_activity::MemoryActivity;
+use crate::memory_map::{self, STACK_BASE, STACK_END, STACK_POINTER};
+use crate::monitor;
+use crate::navigation::{matches_pattern, TableNavigation};
+use crate::number_format::NumberFormat;
+use crate::parser::{LineParsingError, MAX_RAM};
+use crate::preprocessor;
+use crate::profiler::Profiler;
+use crate::screen_capture::{
+    self, Recorder, DEFAULT_RECORD_INTERVAL, GIF_FILE_EXTENSION, PNG_FILE_EXTENSION,
+};
+use crate::symbol_table::SymbolTable;
+use crate::trace::{TraceFilter, Tracer, CSV_FILE_EXTENSION, TRACE_FILE_EXTENSION};
+use crate::{read_source_file, split_source, CPUState, ASM_FILE_EXTENSION, SCREEN_RATIO};
 use crate::{
     INSTRUCTIONS_PER_REFRESH, KBD_LOCATION, MAX_INSTRUCTIONS, SCREEN_HEIGHT, SCREEN_LENGTH,
     SCREEN_LOCATION, SCREEN_WIDTH,
@@ -17,14 +34,43 @@ use imgui::*;
 use imgui_glium_renderer::{Renderer, Texture};
 use rfd::FileDialog;
 use std::borrow::Cow;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
 use std::{env, fs};
 use std::{error::Error, num::Wrapping, usize};
 
 const RAM_AND_ROM_WIDTH: f32 = 350.0;
 const CONTROL_WINDOW_HEIGHT: f32 = 155.0;
 const DEBUG_BOX_SIZE: f32 = 60.0;
+const MONITOR_WINDOW_SIZE: [f32; 2] = [500.0, 300.0];
+const WARNINGS_WINDOW_SIZE: [f32; 2] = [500.0, 200.0];
+const CFG_WINDOW_SIZE: [f32; 2] = [600.0, 300.0];
+const SEGMENTS_WINDOW_SIZE: [f32; 2] = [300.0, 450.0];
+const STACK_TABLE_HEIGHT: f32 = 150.0;
+const SEGMENT_ENTRIES: i32 = 8;
+const SELECTED_COLOR: ImColor32 = ImColor32::from_rgb(0, 90, 160);
+const RAM_CONTEXT_POPUP: &'static str = "ram_context";
+/// How long a RAM cell flashes for after it changes.
+const FLASH_DURATION: Duration = Duration::from_millis(800);
+const OVERVIEW_HEIGHT: f32 = 16.0;
+const EDITOR_WINDOW_SIZE: [f32; 2] = [800.0, 500.0];
+/// How often the file of the program is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(3);
+const NOTIFICATION_MARGIN: f32 = 10.0;
+const CALL_STACK_WINDOW_SIZE: [f32; 2] = [400.0, 250.0];
+const FORMAT_SELECTOR_WIDTH: f32 = 90.0;
+const REGISTER_INPUT_WIDTH: f32 = 150.0;
+const YELLOW: [f32; 4] = [1.0, 0.8, 0.0, 1.0];
+/// Marks the target of a jump in the ROM view.
+const JUMP_ARROW: &'static str = "→";
+/// Marks the addresses held by the pointer registers in the RAM view.
+const POINTER_ARROW: &'static str = "←";
+/// The choice of the ROM view format that shows the instructions, rather than their machine code.
+const INSTRUCTIONS_FORMAT: &'static str = "Instructions";
 
 // Key codes
 const NEWLINE_KEY: i16 = 128;
@@ -53,18 +99,74 @@ const F10_KEY: i16 = 150;
 const F11_KEY: i16 = 151;
 const F12_KEY: i16 = 152;
 
+/// A row of the ROM view.
+enum RomRow {
+    /// An [Instruction::Label], shown above the instruction it names.
+    Label(Instruction),
+    /// The instruction at a ROM address.
+    Instruction(u16),
+}
+
 /// Represents the GUI for the HACK CPU, and stores the [CPUState] for executing programs.
 pub struct HackGUI {
     pub screen_texture_id: Option<TextureId>,
     pub cpu: CPUState,
     pub instructions: [Instruction; MAX_INSTRUCTIONS],
-    pub num_labels: usize,
+    /// The rows of the ROM view, which show the labels of the program above their instructions.
+    rom_rows: Vec<RomRow>,
+    /// The ROM addresses that each pseudo-instruction of the program expanded to.
+    expansions: Vec<(Range<usize>, String)>,
     pub running: bool,
     next_breakpoint: Option<BreakpointSelector>,
     adram_value: i16,
     pcvalue: u16,
     program_error: Option<LineParsingError>,
     last_dir: PathBuf,
+    trace_pc_range: [i32; 2],
+    trace_filter_ram: bool,
+    trace_ram_range: [i32; 2],
+    show_monitor: bool,
+    monitor_input: String,
+    monitor_output: Vec<String>,
+    show_warnings: bool,
+    warnings: Vec<Warning>,
+    show_cfg: bool,
+    cfg: ControlFlowGraph,
+    show_segments: bool,
+    /// The number of entries shown for each segment in the VM segments window.
+    segment_entries: i32,
+    show_call_stack: bool,
+    show_editor: bool,
+    editor: Editor,
+    /// The file the program was loaded from, and when it was last modified, which is watched to
+    /// reload the program when it changes.
+    program_file: Option<(PathBuf, SystemTime)>,
+    /// The files included by the program, and when each was last modified, which are watched
+    /// along with the [Self::program_file].
+    included_files: Vec<(PathBuf, SystemTime)>,
+    last_reload_check: Instant,
+    /// The message being shown, and when it was first shown.
+    notification: Option<(String, Instant)>,
+    rom_navigation: TableNavigation,
+    /// The RAM as it was in the last frame, and when each address that recently changed did so.
+    previous_ram: Vec<i16>,
+    ram_changes: HashMap<usize, Instant>,
+    ram_navigation: TableNavigation,
+    /// The RAM address whose context menu was last opened.
+    ram_context: Option<usize>,
+    /// The call stack when the execution was last paused.
+    call_stack: Vec<Frame>,
+    /// The symbol each A instruction of the program was written with, by ROM address.
+    symbols: HashMap<u16, String>,
+    /// How the RAM, and the A register that addresses it, are displayed.
+    ram_format: NumberFormat,
+    register_format: NumberFormat,
+    /// How the machine code of the ROM is displayed, or [None] to show the instructions.
+    rom_format: Option<NumberFormat>,
+    /// The recording of the screen in progress, which captures frames as the program runs.
+    recorder: Option<Recorder>,
+    /// The number of cycles between the frames of the next recording.
+    record_interval: i32,
 }
 
 impl HackGUI {
@@ -73,19 +175,52 @@ impl HackGUI {
         screen_texture_id: Option<TextureId>,
         cpu: CPUState,
         instructions: [Instruction; MAX_INSTRUCTIONS],
-        num_labels: usize,
     ) -> Self {
+        let cfg = ControlFlowGraph::new(&instructions, &cpu.address_table);
+        let rom_rows = rom_rows(&cpu.address_table);
         Self {
             screen_texture_id,
             cpu,
             instructions,
-            num_labels,
+            rom_rows,
+            expansions: vec![],
             running: false,
             next_breakpoint: None,
             adram_value: 0,
             pcvalue: 0,
             program_error: None,
             last_dir: env::current_dir().unwrap(),
+            trace_pc_range: [0, MAX_INSTRUCTIONS as i32 - 1],
+            trace_filter_ram: false,
+            trace_ram_range: [0, MAX_RAM as i32 - 1],
+            show_monitor: false,
+            monitor_input: String::new(),
+            monitor_output: vec![],
+            show_warnings: false,
+            warnings: vec![],
+            show_cfg: false,
+            cfg,
+            show_segments: false,
+            segment_entries: SEGMENT_ENTRIES,
+            show_call_stack: false,
+            show_editor: false,
+            editor: Editor::new(),
+            program_file: None,
+            included_files: vec![],
+            last_reload_check: Instant::now(),
+            notification: None,
+            rom_navigation: TableNavigation::default(),
+            previous_ram: vec![0; MAX_RAM],
+            ram_changes: HashMap::new(),
+            ram_navigation: TableNavigation::default(),
+            ram_context: None,
+            call_stack: vec![],
+            symbols: HashMap::new(),
+            ram_format: NumberFormat::Decimal,
+            register_format: NumberFormat::Decimal,
+            rom_format: None,
+            recorder: None,
+            record_interval: DEFAULT_RECORD_INTERVAL as i32,
         }
     }
 
@@ -130,8 +265,9 @@ impl HackGUI {
                                 .pick_file();
                             if let Some(input_path) = file {
                                 self.last_dir = input_path.parent().unwrap().to_path_buf();
-                                let contents: String = fs::read_to_string(input_path)
+                                let contents: String = fs::read_to_string(&input_path)
                                     .expect("Should have been able to read file");
+                                self.editor.open(&input_path, contents.clone());
                                 let instructions: Vec<String> =
                                     contents.split("\n").map(|s| s.trim().to_string()).collect();
                                 if instructions.len() > MAX_INSTRUCTIONS {
@@ -145,7 +281,7 @@ impl HackGUI {
                                         ret[i] = instruction.to_string();
                                     }
 
-                                    match self.new_program(ret) {
+                                    match self.new_program(ret, &input_path) {
                                         Ok(_) => {self.program_error = None},
                                         Err(e) => {self.program_error = Some(e);},
                                     };
@@ -157,6 +293,7 @@ impl HackGUI {
                         }
                         if ui.button("Step") {
                             self.cpu.interpret(&self.instructions[self.cpu.pc as usize]);
+                            self.record_cycle();
                             if let Some(kbd_letter) = key {
                                 self.cpu.ram[KBD_LOCATION] = get_keycode(kbd_letter);
                             } else {
@@ -166,43 +303,36 @@ impl HackGUI {
                         if ui.button("Reset") {
                             self.cpu.pc = 0;
                         }
+                        ui.same_line();
+                        ui.checkbox("Monitor", &mut self.show_monitor);
+                        ui.same_line();
+                        ui.checkbox(
+                            format!("Warnings ({})##warnings", self.warnings.len()),
+                            &mut self.show_warnings,
+                        );
+                        ui.same_line();
+                        ui.checkbox("CFG", &mut self.show_cfg);
+                        ui.same_line();
+                        ui.checkbox("Segments", &mut self.show_segments);
+                        ui.same_line();
+                        ui.checkbox("Call stack", &mut self.show_call_stack);
+                        ui.same_line();
+                        ui.checkbox("Editor", &mut self.show_editor);
+                        self.build_trace_controls(ui);
                         running_ui.end();
 
                         if self.running {
-                            'instructions: for _ in 0..INSTRUCTIONS_PER_REFRESH {
+                            for _ in 0..INSTRUCTIONS_PER_REFRESH {
                                 if self.cpu.pc >= MAX_INSTRUCTIONS as u16 {
                                     self.running = false;
                                     self.cpu.pc = MAX_INSTRUCTIONS as u16 - 1;
                                     break;
                                 }
                                 self.cpu.interpret(&self.instructions[self.cpu.pc as usize]);
-                                for breakpoint in &self.cpu.breakpoints {
-                                    match breakpoint {
-                                        Breakpoint::A(v) => {
-                                            if self.cpu.a.0 == *v {
-                                                self.running = false;
-                                                break 'instructions;
-                                            }
-                                        }
-                                        Breakpoint::D(v) => {
-                                            if self.cpu.d.0 == *v {
-                                                self.running = false;
-                                                break 'instructions;
-                                            }
-                                        }
-                                        Breakpoint::PC(v) => {
-                                            if self.cpu.pc == *v {
-                                                self.running = false;
-                                                break 'instructions;
-                                            }
-                                        }
-                                        Breakpoint::RAM(n, v) => {
-                                            if self.cpu.ram[*n as usize].0 == *v {
-                                                self.running = false;
-                                                break 'instructions;
-                                            }
-                                        }
-                                    }
+                                self.record_cycle();
+                                if self.cpu.hit_breakpoint() {
+                                    self.running = false;
+                                    break;
                                 }
                             }
                             if let Some(kbd_letter) = key {
@@ -214,6 +344,70 @@ impl HackGUI {
                     });
     }
 
+    /// Builds the controls for recording an execution trace, and saving it to a file.
+    fn build_trace_controls(&mut self, ui: &Ui) {
+        let mut tracing = self.cpu.tracer.is_some();
+        if ui.checkbox("Trace", &mut tracing) {
+            self.cpu.tracer = if tracing {
+                Some(Tracer::new(self.trace_filter()))
+            } else {
+                None
+            };
+        }
+        ui.same_line();
+        let save_ui = ui.begin_disabled(self.cpu.tracer.is_none());
+        if ui.button("Save trace") {
+            let file = FileDialog::new()
+                .add_filter("trace", &[TRACE_FILE_EXTENSION])
+                .add_filter("csv", &[CSV_FILE_EXTENSION])
+                .set_directory(&self.last_dir)
+                .save_file();
+            if let (Some(path), Some(tracer)) = (file, &self.cpu.tracer) {
+                match tracer.save(&path) {
+                    Ok(()) => self.notify(format!("Saved the trace to {}", path.display())),
+                    Err(e) => self.notify(format!(
+                        "Failed to save the trace to {}: {e}",
+                        path.display()
+                    )),
+                }
+            }
+        }
+        save_ui.end();
+        if let Some(tracer) = &self.cpu.tracer {
+            ui.same_line();
+            ui.text(format!("{} entries", tracer.entries.len()));
+        }
+
+        ui.set_next_item_width(DEBUG_BOX_SIZE * 2.0);
+        ui.input_int2("PC##trace_pc", &mut self.trace_pc_range)
+            .build();
+        ui.same_line();
+        ui.checkbox("RAM##trace_filter_ram", &mut self.trace_filter_ram);
+        ui.same_line();
+        ui.set_next_item_width(DEBUG_BOX_SIZE * 2.0);
+        ui.input_int2("##trace_ram", &mut self.trace_ram_range)
+            .build();
+        let filter = self.trace_filter();
+        if let Some(tracer) = &mut self.cpu.tracer {
+            tracer.filter = filter;
+        }
+    }
+
+    /// Builds the [TraceFilter] described by the trace controls.
+    fn trace_filter(&self) -> TraceFilter {
+        // The boxes accept any number, including negative ones, which would wrap around
+        let range = |r: [i32; 2], size: usize| {
+            let [start, end] = r.map(|bound| bound.clamp(0, size as i32 - 1) as u16);
+            start..=end
+        };
+        TraceFilter {
+            pc_range: range(self.trace_pc_range, MAX_INSTRUCTIONS),
+            address_range: self
+                .trace_filter_ram
+                .then(|| range(self.trace_ram_range, MAX_RAM)),
+        }
+    }
+
     /// Builds the window that creates [Breakpoint]s in the CPU.
     fn build_debug_window(&mut self, ui: &Ui, window_width: f32) {
         ui.child_window("Debug")
@@ -321,8 +515,55 @@ impl HackGUI {
                 if ui.input_int("##pc", &mut temp).build() {
                     *val = temp as _;
                 }
-                let num_cols = 2;
-                let num_rows = (MAX_INSTRUCTIONS + self.num_labels) as i32;
+                let mut profiling = self.cpu.profiler.is_some();
+                if ui.checkbox("Profile", &mut profiling) {
+                    self.cpu.profiler = profiling.then(Profiler::new);
+                }
+                if self.cpu.profiler.is_some() {
+                    ui.same_line();
+                    if ui.button("Reset##profile") {
+                        self.cpu.profiler = Some(Profiler::new());
+                    }
+                }
+                ui.same_line();
+                let mut covering = self.cpu.coverage.is_some();
+                if ui.checkbox("Coverage", &mut covering) {
+                    self.cpu.coverage = covering.then(Coverage::new);
+                }
+                ui.same_line();
+                let mut rom_formats = vec![INSTRUCTIONS_FORMAT];
+                rom_formats.extend(NumberFormat::NAMES);
+                let mut index = self.rom_format.map_or(0, |format| {
+                    1 + NumberFormat::ALL
+                        .iter()
+                        .position(|f| *f == format)
+                        .unwrap_or(0)
+                });
+                ui.set_next_item_width(FORMAT_SELECTOR_WIDTH);
+                if ui.combo_simple_string("##rom_format", &mut index, &rom_formats) {
+                    self.rom_format = index.checked_sub(1).map(|i| NumberFormat::ALL[i]);
+                }
+                self.rom_navigation.build(
+                    ui,
+                    "rom",
+                    &self.cpu.address_table,
+                    MAX_INSTRUCTIONS,
+                    |pattern| {
+                        (0..MAX_INSTRUCTIONS as u16)
+                            .filter(|address| {
+                                let instruction = &self.instructions[*address as usize];
+                                let symbolic = self.symbols.get(address).map(|s| format!("@{s}"));
+                                *instruction != Instruction::None
+                                    && (matches_pattern(pattern, &instruction.to_string())
+                                        || symbolic.is_some_and(|s| matches_pattern(pattern, &s)))
+                            })
+                            .collect()
+                    },
+                );
+                let max_count = self.cpu.profiler.as_ref().map_or(0, |p| p.max());
+                let num_cols =
+                    2 + self.cpu.profiler.is_some() as usize + !self.expansions.is_empty() as usize;
+                let num_rows = self.rom_rows.len() as i32;
 
                 let flags = imgui::TableFlags::ROW_BG
                     | imgui::TableFlags::RESIZABLE
@@ -334,6 +575,12 @@ impl HackGUI {
                 {
                     ui.table_setup_column("");
                     ui.table_setup_column("Instructions");
+                    if self.cpu.profiler.is_some() {
+                        ui.table_setup_column("Count");
+                    }
+                    if !self.expansions.is_empty() {
+                        ui.table_setup_column("Expanded from");
+                    }
 
                     // Freeze first row so headers are visible when scrolling
                     ui.table_setup_scroll_freeze(num_cols, 1);
@@ -341,27 +588,102 @@ impl HackGUI {
                     ui.table_headers_row();
 
                     let clip = imgui::ListClipper::new(num_rows).begin(ui);
-                    let mut offset = 0;
                     for row_num in clip.iter() {
                         ui.table_next_row();
                         ui.table_set_column_index(0);
-                        if (row_num - offset) as u16 == self.cpu.pc {
+                        self.rom_navigation
+                            .lay_out_row(ui, row_num as usize, |address| {
+                                self.rom_rows
+                                    .iter()
+                                    .position(|row| {
+                                        matches!(row, RomRow::Instruction(a) if *a == address)
+                                    })
+                                    .unwrap_or(0)
+                            });
+                        let address = match &self.rom_rows[row_num as usize] {
+                            RomRow::Label(label) => {
+                                ui.table_set_column_index(1);
+                                ui.text_colored(YELLOW, format!("{}", label));
+                                continue;
+                            }
+                            RomRow::Instruction(address) => *address,
+                        };
+                        if address == self.cpu.pc {
                             ui.table_set_bg_color(
                                 TableBgTarget::ROW_BG1,
                                 ImColor32::from_rgb(100, 100, 0),
                             );
                         }
-                        match self.instructions[row_num as usize] {
-                            Instruction::Label(_) => {
-                                offset += 1;
-                                ui.text("");
-                                ui.table_set_column_index(1);
-                                ui.text(format!("{}", self.instructions[row_num as usize]));
+                        if self.rom_navigation.selected == Some(address) {
+                            ui.table_set_bg_color(TableBgTarget::CELL_BG, SELECTED_COLOR);
+                        }
+                        let instruction = &self.instructions[address as usize];
+                        if let Some(coverage) = &self.cpu.coverage {
+                            if let Some(color) =
+                                coverage_color(coverage, address as usize, instruction)
+                            {
+                                ui.table_set_bg_color(TableBgTarget::ROW_BG0, color);
                             }
-                            Instruction::A(_) | Instruction::C(_) | Instruction::None => {
-                                ui.text(format!("{}", row_num - offset));
-                                ui.table_set_column_index(1);
-                                ui.text(format!("{}", self.instructions[row_num as usize]));
+                        }
+                        let warnings: Vec<String> = self
+                            .warnings
+                            .iter()
+                            .filter(|w| w.address == address)
+                            .map(|w| w.message.clone())
+                            .collect();
+                        if warnings.is_empty() {
+                            ui.text(format!("{}", address));
+                        } else {
+                            ui.text_colored(YELLOW, format!("{}", address));
+                            if ui.is_item_hovered() {
+                                ui.tooltip_text(warnings.join("\n"));
+                            }
+                        }
+                        ui.table_set_column_index(1);
+                        match (self.rom_format, self.symbols.get(&address)) {
+                            (Some(_), _) if *instruction == Instruction::None => {}
+                            (Some(format), _) => {
+                                ui.text(format.format(instruction.machine_code() as i16));
+                                if ui.is_item_hovered() {
+                                    ui.tooltip_text(format!("{}", instruction));
+                                }
+                            }
+                            (None, Some(symbol)) => {
+                                ui.text(format!("@{symbol}"));
+                                if ui.is_item_hovered() {
+                                    ui.tooltip_text(format!("{}", instruction));
+                                }
+                            }
+                            (None, None) => ui.text(format!("{}", instruction)),
+                        }
+                        if let Some(target) = self
+                            .jump_target(address)
+                            .filter(|_| self.rom_format.is_none())
+                        {
+                            ui.same_line();
+                            ui.text_disabled(format!("{JUMP_ARROW} {target}"));
+                        }
+                        if let Some(profiler) = &self.cpu.profiler {
+                            let count = profiler.counts[address as usize];
+                            ui.table_set_column_index(2);
+                            if count > 0 {
+                                ui.table_set_bg_color(
+                                    TableBgTarget::CELL_BG,
+                                    heat_color(count, max_count),
+                                );
+                                ui.text(format!("{}", count));
+                            }
+                        }
+                        if let Some((addresses, pseudo_instruction)) = self
+                            .expansions
+                            .iter()
+                            .find(|(addresses, _)| addresses.contains(&(address as usize)))
+                        {
+                            ui.table_set_column_index(num_cols - 1);
+                            if addresses.start == address as usize {
+                                ui.text(pseudo_instruction);
+                            } else {
+                                ui.text_disabled("|");
                             }
                         }
                     }
@@ -381,14 +703,33 @@ impl HackGUI {
                 if ui.button("Reset##RAM") {
                     self.cpu.reset_ram();
                 }
-                let val = &mut self.cpu.a.0;
-                let mut temp = *val as i32;
+                ui.same_line();
+                format_selector(ui, "##ram_format", &mut self.ram_format);
+                ui.same_line();
+                let mut counting = self.cpu.memory_activity.is_some();
+                if ui.checkbox("Heatmap", &mut counting) {
+                    self.cpu.memory_activity = counting.then(MemoryActivity::new);
+                }
+                self.record_ram_changes();
                 ui.text("A: ");
                 ui.same_line();
-                if ui.input_int("##a", &mut temp).build() {
-                    *val = temp as _;
+                input_word(ui, "##a", &mut self.cpu.a.0, self.ram_format);
+                let ram_format = self.ram_format;
+                let ram = &self.cpu.ram;
+                self.ram_navigation
+                    .build(ui, "ram", &self.cpu.address_table, MAX_RAM, |pattern| {
+                        let Some(value) = ram_format.parse(pattern) else {
+                            return vec![];
+                        };
+                        (0..MAX_RAM as u16)
+                            .filter(|address| ram[*address as usize].0 == value)
+                            .collect()
+                    });
+                let max_accesses = self.cpu.memory_activity.as_ref().map_or(0, |a| a.max());
+                if self.cpu.memory_activity.is_some() {
+                    self.build_activity_overview(ui);
                 }
-                let num_cols = 2;
+                let num_cols = 3 + self.cpu.memory_activity.is_some() as usize;
                 let num_rows = MAX_RAM as i32;
 
                 let flags = imgui::TableFlags::ROW_BG
@@ -396,11 +737,17 @@ impl HackGUI {
                     | imgui::TableFlags::BORDERS_H
                     | imgui::TableFlags::BORDERS_V;
 
+                // The cell right clicked in this frame, which opens the context menu
+                let mut context_address = None;
                 if let Some(_t) =
                     ui.begin_table_with_sizing("longtable", num_cols, flags, [-1.0, 0.0], 0.0)
                 {
                     ui.table_setup_column("");
                     ui.table_setup_column("Memory");
+                    ui.table_setup_column("Region");
+                    if self.cpu.memory_activity.is_some() {
+                        ui.table_setup_column("Reads/Writes");
+                    }
 
                     // Freeze first row so headers are visible when scrolling
                     ui.table_setup_scroll_freeze(num_cols, 1);
@@ -411,26 +758,173 @@ impl HackGUI {
                     for row_num in clip.iter() {
                         ui.table_next_row();
                         ui.table_set_column_index(0);
+                        self.ram_navigation
+                            .lay_out_row(ui, row_num as usize, |address| address as usize);
                         ui.text(format!("{}", row_num));
+                        let mut clicked = ui.is_item_clicked_with_button(MouseButton::Right);
                         if !self.running && row_num == self.cpu.a.0 as i32 {
                             ui.table_set_bg_color(
                                 TableBgTarget::ROW_BG1,
                                 ImColor32::from_rgb(100, 100, 0),
                             );
                         }
+                        if self.ram_navigation.selected == Some(row_num as u16) {
+                            ui.table_set_bg_color(TableBgTarget::CELL_BG, SELECTED_COLOR);
+                        }
 
                         ui.table_set_column_index(1);
-                        let val = &mut self.cpu.ram[row_num as usize].0;
-                        let mut temp = *val as i32;
-                        if ui.input_int(format!("##ram{}", row_num), &mut temp).build() {
-                            *val = temp as _;
+                        let flash = self
+                            .ram_changes
+                            .get(&(row_num as usize))
+                            .map(|changed| flash_color(changed.elapsed()))
+                            .map(|color| ui.push_style_color(StyleColor::FrameBg, color));
+                        input_word(
+                            ui,
+                            format!("##ram{}", row_num),
+                            &mut self.cpu.ram[row_num as usize].0,
+                            self.ram_format,
+                        );
+                        drop(flash);
+                        clicked |= ui.is_item_clicked_with_button(MouseButton::Right);
+                        if clicked {
+                            context_address = Some(row_num as usize);
+                        }
+
+                        // Registers are named by their use in the VM, and the other regions are
+                        // named in full where they begin
+                        let address = row_num as usize;
+                        ui.table_set_column_index(2);
+                        match (
+                            memory_map::register_name(address),
+                            memory_map::region(address),
+                        ) {
+                            (Some(name), _) => ui.text(name),
+                            (None, Some(region)) if *region.addresses.start() == address => {
+                                ui.text(region.name)
+                            }
+                            (None, Some(region)) => ui.text_disabled(region.name),
+                            (None, None) => {}
+                        }
+                        let pointers = memory_map::pointers_to(&self.cpu.ram, address);
+                        if !pointers.is_empty() {
+                            ui.same_line();
+                            ui.text_colored(
+                                YELLOW,
+                                format!("{POINTER_ARROW} {}", pointers.join(", ")),
+                            );
+                        }
+
+                        if let Some(activity) = &self.cpu.memory_activity {
+                            let accesses = activity.accesses(address);
+                            ui.table_set_column_index(3);
+                            if accesses > 0 {
+                                ui.table_set_bg_color(
+                                    TableBgTarget::CELL_BG,
+                                    heat_color(accesses, max_accesses),
+                                );
+                                ui.text(format!(
+                                    "{}/{}",
+                                    activity.reads[address], activity.writes[address]
+                                ));
+                            }
                         }
                     }
                 }
+                if context_address.is_some() {
+                    self.ram_context = context_address;
+                    ui.open_popup(RAM_CONTEXT_POPUP);
+                }
+                ui.popup(RAM_CONTEXT_POPUP, || {
+                    let Some(address) = self.ram_context else {
+                        return;
+                    };
+                    let pointer = self.cpu.ram[address].0 as u16;
+                    if ui
+                        .menu_item_config(format!("Follow as pointer to {pointer}"))
+                        .enabled((pointer as usize) < MAX_RAM)
+                        .build()
+                    {
+                        self.ram_navigation.go_to(pointer);
+                    }
+                });
                 running_ui.end();
             });
     }
 
+    /// Notes the RAM addresses whose values have changed since the last frame, so that they flash
+    /// in the RAM view, and forgets the changes that have finished flashing.
+    fn record_ram_changes(self: &mut Self) {
+        let now = Instant::now();
+        for (address, (previous, current)) in
+            self.previous_ram.iter_mut().zip(&self.cpu.ram).enumerate()
+        {
+            if *previous != current.0 {
+                *previous = current.0;
+                self.ram_changes.insert(address, now);
+            }
+        }
+        self.ram_changes
+            .retain(|_, changed| now.duration_since(*changed) < FLASH_DURATION);
+    }
+
+    /// Builds a strip showing the reads and writes of the whole RAM, where each column of pixels
+    /// covers a range of addresses, and clicking goes to the addresses of a column.
+    fn build_activity_overview(self: &mut Self, ui: &Ui) {
+        let Some(activity) = &self.cpu.memory_activity else {
+            return;
+        };
+        let width = ui.content_region_avail()[0].max(1.0);
+        let origin = ui.cursor_screen_pos();
+        let columns = width as usize;
+        let column_accesses: Vec<u64> = (0..columns)
+            .map(|column| {
+                let addresses = overview_addresses(column, columns);
+                addresses.map(|address| activity.accesses(address)).sum()
+            })
+            .collect();
+        let max_accesses = column_accesses.iter().copied().max().unwrap_or(0);
+        let draw_list = ui.get_window_draw_list();
+        draw_list
+            .add_rect(
+                origin,
+                [origin[0] + width, origin[1] + OVERVIEW_HEIGHT],
+                ImColor32::from_rgb(40, 40, 40),
+            )
+            .filled(true)
+            .build();
+        for (column, accesses) in column_accesses.iter().enumerate() {
+            if *accesses == 0 {
+                continue;
+            }
+            let x = origin[0] + column as f32;
+            draw_list
+                .add_rect(
+                    [x, origin[1]],
+                    [x + 1.0, origin[1] + OVERVIEW_HEIGHT],
+                    heat_color(*accesses, max_accesses),
+                )
+                .filled(true)
+                .build();
+        }
+        drop(draw_list);
+
+        ui.invisible_button("##activity_overview", [width, OVERVIEW_HEIGHT]);
+        let column = ((ui.io().mouse_pos[0] - origin[0]) as usize).min(columns - 1);
+        let addresses = overview_addresses(column, columns);
+        if ui.is_item_hovered() {
+            let region = memory_map::region(addresses.start).map_or("", |r| r.name);
+            ui.tooltip_text(format!(
+                "{}-{} {region}: {} accesses",
+                addresses.start,
+                addresses.end - 1,
+                column_accesses[column]
+            ));
+        }
+        if ui.is_item_clicked() {
+            self.ram_navigation.go_to(addresses.start as u16);
+        }
+    }
+
     /// Builds the screen pane. This rewrites the contents of the screen texture. Note that this
     /// additionally creates the pane that displays the breakpoints, and what key is currently
     /// pressed.
@@ -442,6 +936,7 @@ impl HackGUI {
             .child_flags(ChildFlags::BORDERS)
             .build(|| {
                 ui.text("Screen");
+                self.build_capture_controls(ui);
 
                 if let Some(sti) = self.screen_texture_id {
                     if let Some(st) = renderer.textures().get_mut(sti) {
@@ -478,13 +973,12 @@ impl HackGUI {
                     ui.text("Keyboard: ");
                 }
                 let running_ui = ui.begin_disabled(self.running);
-                let val = &mut self.cpu.d.0;
-                let mut temp = *val as i32;
                 ui.text("D: ");
                 ui.same_line();
-                if ui.input_int("##d", &mut temp).build() {
-                    *val = temp as _;
-                }
+                ui.set_next_item_width(REGISTER_INPUT_WIDTH);
+                input_word(ui, "##d", &mut self.cpu.d.0, self.register_format);
+                ui.same_line();
+                format_selector(ui, "##register_format", &mut self.register_format);
                 running_ui.end();
 
                 ui.child_window("Breakpoints")
@@ -504,6 +998,335 @@ impl HackGUI {
             });
     }
 
+    /// Builds the monitor console, where commands are typed in to control the CPU, as an
+    /// alternative to the debug window.
+    fn build_monitor_window(&mut self, ui: &Ui) {
+        let mut open = self.show_monitor;
+        ui.window("Monitor")
+            .size(MONITOR_WINDOW_SIZE, Condition::FirstUseEver)
+            .opened(&mut open)
+            .build(|| {
+                let footer_height = ui.frame_height_with_spacing();
+                ui.child_window("monitor_output")
+                    .size([0.0, -footer_height])
+                    .build(|| {
+                        for line in &self.monitor_output {
+                            ui.text(line);
+                        }
+                        // Follow the output, unless the user has scrolled up
+                        if ui.scroll_y() >= ui.scroll_max_y() {
+                            ui.set_scroll_here_y_with_ratio(1.0);
+                        }
+                    });
+                let running_ui = ui.begin_disabled(self.running);
+                ui.set_next_item_width(-1.0);
+                if ui
+                    .input_text("##monitor_input", &mut self.monitor_input)
+                    .enter_returns_true(true)
+                    .build()
+                {
+                    let command = std::mem::take(&mut self.monitor_input);
+                    self.monitor_output.push(format!("> {command}"));
+                    let result = match command.trim().strip_prefix("load ") {
+                        Some(path) => self.load_from_monitor(Path::new(path.trim())),
+                        None => monitor::execute(&command, &mut self.cpu, &mut self.instructions),
+                    };
+                    let output = match result {
+                        Ok(output) => output,
+                        Err(e) => format!("Error: {e}"),
+                    };
+                    self.monitor_output
+                        .extend(output.lines().map(|line| line.to_string()));
+                    ui.set_keyboard_focus_here_with_offset(FocusedWidget::Previous);
+                }
+                running_ui.end();
+            });
+        self.show_monitor = open;
+    }
+
+    /// Runs the `load` command of the monitor through [HackGUI::new_program], so that the views of
+    /// the program are replaced along with its instructions.
+    fn load_from_monitor(self: &mut Self, path: &Path) -> Result<String, String> {
+        let lines = read_source_file(path)?;
+        self.new_program(lines, path).map_err(|e| e.to_string())?;
+        self.program_error = None;
+        self.cpu.pc = 0;
+        Ok(format!("Loaded {}", path.display()))
+    }
+
+    /// Builds the window that lists the warnings found in the program by [lint::analyze]. The
+    /// instructions they concern are also highlighted in the ROM view.
+    fn build_warnings_window(&mut self, ui: &Ui) {
+        let mut open = self.show_warnings;
+        ui.window("Warnings")
+            .size(WARNINGS_WINDOW_SIZE, Condition::FirstUseEver)
+            .opened(&mut open)
+            .build(|| {
+                if self.warnings.is_empty() {
+                    ui.text("No warnings");
+                }
+                for warning in &self.warnings {
+                    ui.text_colored(YELLOW, format!("ROM[{}]", warning.address));
+                    ui.same_line();
+                    ui.text_wrapped(&warning.message);
+                }
+            });
+        self.show_warnings = open;
+    }
+
+    /// Builds the window that shows the stack and the segments of the VM, found through the
+    /// pointers at the start of the RAM, in the format of the RAM view.
+    fn build_segments_window(&mut self, ui: &Ui) {
+        let mut open = self.show_segments;
+        ui.window("VM segments")
+            .size(SEGMENTS_WINDOW_SIZE, Condition::FirstUseEver)
+            .opened(&mut open)
+            .build(|| {
+                let running_ui = ui.begin_disabled(self.running);
+                let flags = imgui::TableFlags::ROW_BG
+                    | imgui::TableFlags::BORDERS_H
+                    | imgui::TableFlags::BORDERS_V
+                    | imgui::TableFlags::SCROLL_Y;
+
+                let sp = self.cpu.ram[STACK_POINTER].0 as u16 as usize;
+                if ui.collapsing_header(
+                    format!("Stack (SP = {sp})###stack"),
+                    TreeNodeFlags::DEFAULT_OPEN,
+                ) {
+                    if (STACK_BASE..=STACK_END + 1).contains(&sp) {
+                        // The top of the stack is shown first
+                        let depth = sp - STACK_BASE;
+                        if depth == 0 {
+                            ui.text_disabled("The stack is empty");
+                        } else if let Some(_t) = ui.begin_table_with_sizing(
+                            "stack",
+                            2,
+                            flags,
+                            [0.0, STACK_TABLE_HEIGHT],
+                            0.0,
+                        ) {
+                            let clip = imgui::ListClipper::new(depth as i32).begin(ui);
+                            for row_num in clip.iter() {
+                                let address = sp - 1 - row_num as usize;
+                                ui.table_next_row();
+                                ui.table_set_column_index(0);
+                                ui.text(format!("{address}"));
+                                ui.table_set_column_index(1);
+                                input_word(
+                                    ui,
+                                    format!("##stack{address}"),
+                                    &mut self.cpu.ram[address].0,
+                                    self.ram_format,
+                                );
+                            }
+                        }
+                    } else {
+                        ui.text_colored(YELLOW, "SP is outside of the stack");
+                    }
+                }
+
+                ui.text("Entries: ");
+                ui.same_line();
+                ui.set_next_item_width(DEBUG_BOX_SIZE * 2.0);
+                if ui
+                    .input_int("##segment_entries", &mut self.segment_entries)
+                    .build()
+                {
+                    self.segment_entries = self.segment_entries.max(1);
+                }
+                for segment in &memory_map::SEGMENTS {
+                    let base = self.cpu.ram[segment.pointer].0 as u16 as usize;
+                    let header = format!(
+                        "{} ({} = {base})###{}",
+                        segment.name, segment.pointer_name, segment.name
+                    );
+                    if !ui.collapsing_header(header, TreeNodeFlags::empty()) {
+                        continue;
+                    }
+                    if let Some(_t) = ui.begin_table_with_flags(segment.name, 3, flags) {
+                        for i in 0..self.segment_entries as usize {
+                            let address = base + i;
+                            if address >= MAX_RAM {
+                                break;
+                            }
+                            ui.table_next_row();
+                            ui.table_set_column_index(0);
+                            ui.text(format!("{} {i}", segment.name));
+                            ui.table_set_column_index(1);
+                            ui.text_disabled(format!("{address}"));
+                            ui.table_set_column_index(2);
+                            input_word(
+                                ui,
+                                format!("##{}{i}", segment.name),
+                                &mut self.cpu.ram[address].0,
+                                self.ram_format,
+                            );
+                        }
+                    }
+                }
+                running_ui.end();
+            });
+        self.show_segments = open;
+    }
+
+    /// Builds the window for editing the source code of the program, which is assembled and run
+    /// in place of the current program when asked.
+    fn build_editor_window(&mut self, ui: &Ui) {
+        let mut open = self.show_editor;
+        let mut run = false;
+        ui.window("Editor")
+            .size(EDITOR_WINDOW_SIZE, Condition::FirstUseEver)
+            .opened(&mut open)
+            .build(|| run = self.editor.build(ui, &mut self.last_dir));
+        self.show_editor = open;
+        if run {
+            self.run_editor();
+        }
+    }
+
+    /// Assembles the text of the editor into the ROM, and runs it from the start. The RAM is
+    /// cleared first, unless the editor keeps it. Mistakes are underlined in the editor, and
+    /// shown in the error window.
+    fn run_editor(self: &mut Self) {
+        // A text too long to split is already reported by the editor
+        let Ok(lines) = split_source(&self.editor.text) else {
+            return;
+        };
+        let path = self.editor.assembly_path(&self.last_dir);
+        if !self.editor.keep_ram {
+            self.cpu.reset_ram();
+        }
+        match self.new_program(lines, &path) {
+            Ok(_) => {
+                self.program_error = None;
+                self.cpu.pc = 0;
+                self.running = true;
+            }
+            Err(e) => self.program_error = Some(e),
+        }
+    }
+
+    /// Builds the window that lists the functions being run by a program translated from VM
+    /// code, reconstructed whenever the execution is paused.
+    fn build_call_stack_window(&mut self, ui: &Ui) {
+        if !self.running {
+            self.call_stack = call_stack::reconstruct(&self.cpu, &self.instructions);
+        }
+        let mut open = self.show_call_stack;
+        ui.window("Call Stack")
+            .size(CALL_STACK_WINDOW_SIZE, Condition::FirstUseEver)
+            .opened(&mut open)
+            .build(|| {
+                let flags = imgui::TableFlags::ROW_BG
+                    | imgui::TableFlags::RESIZABLE
+                    | imgui::TableFlags::BORDERS_H
+                    | imgui::TableFlags::BORDERS_V
+                    | imgui::TableFlags::SCROLL_Y;
+                if let Some(_t) = ui.begin_table_with_flags("call_stack", 4, flags) {
+                    ui.table_setup_column("Function");
+                    ui.table_setup_column("ROM");
+                    ui.table_setup_column("LCL");
+                    ui.table_setup_column("ARG");
+                    ui.table_setup_scroll_freeze(4, 1);
+                    ui.table_headers_row();
+                    for frame in &self.call_stack {
+                        ui.table_next_row();
+                        ui.table_set_column_index(0);
+                        ui.text(&frame.function);
+                        ui.table_set_column_index(1);
+                        ui.text(format!("{}", frame.address));
+                        ui.table_set_column_index(2);
+                        ui.text(format!("{}", frame.local));
+                        ui.table_set_column_index(3);
+                        ui.text(format!("{}", frame.argument));
+                    }
+                }
+            });
+        self.show_call_stack = open;
+    }
+
+    /// Builds the window that lists the basic blocks of the program, along with the blocks that
+    /// can run before and after each of them, and highlights the block being executed.
+    fn build_cfg_window(&mut self, ui: &Ui) {
+        let mut open = self.show_cfg;
+        ui.window("Control flow")
+            .size(CFG_WINDOW_SIZE, Condition::FirstUseEver)
+            .opened(&mut open)
+            .build(|| {
+                if ui.button("Export DOT") {
+                    let file = FileDialog::new()
+                        .add_filter("dot", &[DOT_FILE_EXTENSION])
+                        .set_directory(&self.last_dir)
+                        .save_file();
+                    if let Some(path) = file {
+                        let dot = self.cfg.to_dot(
+                            &self.instructions,
+                            &self.symbols,
+                            &self.cpu.address_table,
+                        );
+                        match fs::write(&path, dot) {
+                            Ok(()) => self.notify(format!(
+                                "Saved the control flow graph to {}",
+                                path.display()
+                            )),
+                            Err(e) => self.notify(format!(
+                                "Failed to save the control flow graph to {}: {e}",
+                                path.display()
+                            )),
+                        }
+                    }
+                }
+                ui.same_line();
+                ui.text(format!("{} blocks", self.cfg.blocks.len()));
+
+                let names = |starts: &[u16]| {
+                    starts
+                        .iter()
+                        .map(|start| self.cfg.name(*start, &self.cpu.address_table))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                };
+                let flags = imgui::TableFlags::ROW_BG
+                    | imgui::TableFlags::RESIZABLE
+                    | imgui::TableFlags::BORDERS_H
+                    | imgui::TableFlags::BORDERS_V
+                    | imgui::TableFlags::SCROLL_Y;
+                if let Some(_t) = ui.begin_table_with_flags("cfg_blocks", 4, flags) {
+                    ui.table_setup_column("Block");
+                    ui.table_setup_column("Addresses");
+                    ui.table_setup_column("Predecessors");
+                    ui.table_setup_column("Successors");
+                    ui.table_setup_scroll_freeze(4, 1);
+                    ui.table_headers_row();
+
+                    let clip = imgui::ListClipper::new(self.cfg.blocks.len() as i32).begin(ui);
+                    for row_num in clip.iter() {
+                        let block = &self.cfg.blocks[row_num as usize];
+                        ui.table_next_row();
+                        if (block.start..block.end).contains(&self.cpu.pc) {
+                            ui.table_set_bg_color(
+                                TableBgTarget::ROW_BG1,
+                                ImColor32::from_rgb(100, 100, 0),
+                            );
+                        }
+                        ui.table_set_column_index(0);
+                        ui.text(self.cfg.name(block.start, &self.cpu.address_table));
+                        ui.table_set_column_index(1);
+                        ui.text(format!("{}-{}", block.start, block.end - 1));
+                        ui.table_set_column_index(2);
+                        ui.text(names(&block.predecessors));
+                        ui.table_set_column_index(3);
+                        let mut successors = names(&block.successors);
+                        if block.indirect {
+                            successors.push_str(if successors.is_empty() { "A" } else { ", A" });
+                        }
+                        ui.text(successors);
+                    }
+                }
+            });
+        self.show_cfg = open;
+    }
+
     /// This builds the window appears when there is an error in the source file, and briefly
     /// describes the error, along with where to find it.
     fn build_error_window(
@@ -521,11 +1344,11 @@ impl HackGUI {
             .resizable(true)
             .build(|| {
                 match error {
-                    LineParsingError::InvalidLine(line_number, line) => ui.text_colored(
+                    LineParsingError::InvalidLine(line_number, line, reason) => ui.text_colored(
                         RED,
                         format!(
-                            "ERROR READING PROGRAM: Error in program at line {}: {}",
-                            line_number, line
+                            "ERROR READING PROGRAM: Error in program at line {}: {} ({})",
+                            line_number, line, reason
                         ),
                     ),
                 };
@@ -535,6 +1358,7 @@ impl HackGUI {
     /// Calls the various build functions, and places them in the correct layout on the screen.
     pub fn show_textures(&mut self, ui: &Ui, renderer: &mut Renderer, key: &Option<Key>) {
         let [window_width, window_height] = ui.io().display_size;
+        self.reload_if_changed();
         ui.window("CPU Emulator")
             .size([window_width, window_height], Condition::Always)
             .position([0.0, 0.0], Condition::Always)
@@ -560,34 +1384,328 @@ impl HackGUI {
 
                 self.build_screen(ui, renderer, key);
 
+                if self.show_monitor {
+                    self.build_monitor_window(ui);
+                }
+
+                if self.show_warnings {
+                    self.build_warnings_window(ui);
+                }
+
+                if self.show_cfg {
+                    self.build_cfg_window(ui);
+                }
+
+                if self.show_segments {
+                    self.build_segments_window(ui);
+                }
+
+                if self.show_call_stack {
+                    self.build_call_stack_window(ui);
+                }
+
+                if self.show_editor {
+                    self.build_editor_window(ui);
+                }
+
                 if let Some(e) = &self.program_error {
                     self.build_error_window(ui, e, window_width, window_height);
                 }
+
+                self.build_notification(ui, window_width, window_height);
             });
     }
 
+    /// Names the target of the jump at the ROM address, if it is a jumping C instruction right
+    /// after an A instruction. The target is named by the symbol of the A instruction, or else by
+    /// a label at the address it loads.
+    fn jump_target(self: &Self, address: u16) -> Option<String> {
+        let Instruction::C(c) = &self.instructions[address as usize] else {
+            return None;
+        };
+        if c.jump == Jump::None {
+            return None;
+        }
+        let previous = address.checked_sub(1)?;
+        let Instruction::A(a) = &self.instructions[previous as usize] else {
+            return None;
+        };
+        if let Some(symbol) = self.symbols.get(&previous) {
+            return Some(symbol.clone());
+        }
+        let target = a.dest as u16;
+        let label = self
+            .cpu
+            .address_table
+            .labels_by_address()
+            .into_iter()
+            .find(|(label_address, _)| *label_address == target)
+            .map(|(_, label)| label.to_string());
+        Some(label.unwrap_or_else(|| target.to_string()))
+    }
+
     /// Reads a program source code from a file, and loads it into the CPU, or displays the error
-    /// window if there is a mistake within.
+    /// window if there is a mistake within. Includes are found relative to the `path` of the file.
     pub fn new_program(
         self: &mut Self,
         instructions: [String; MAX_INSTRUCTIONS],
+        path: &Path,
     ) -> Result<bool, LineParsingError> {
-        self.cpu.reset_address_table();
+        // The symbols of the program loaded are kept until the new one assembles, since the ROM
+        // view and the breakpoints of a reload still refer to them
+        let mut address_table = SymbolTable::new();
+        let program = preprocessor::assemble(&instructions, path, &mut address_table)?;
+        self.cpu.address_table = address_table;
+        self.cpu.load_data(&program.data);
+        // What was recorded of the previous program does not apply to the instructions of this one
+        if self.cpu.profiler.is_some() {
+            self.cpu.profiler = Some(Profiler::new());
+        }
+        if self.cpu.coverage.is_some() {
+            self.cpu.coverage = Some(Coverage::new());
+        }
+        if self.cpu.memory_activity.is_some() {
+            self.cpu.memory_activity = Some(MemoryActivity::new());
+        }
+        self.expansions = program.expansions;
+        self.included_files = program
+            .included
+            .into_iter()
+            .filter_map(|path| modified_time(&path).map(|modified| (path, modified)))
+            .collect();
+        let instructions = program.instructions;
+        self.warnings = lint::analyze(
+            &instructions,
+            &program.symbols,
+            &program.expression_symbols,
+            &self.cpu.address_table,
+        );
+        self.cfg = ControlFlowGraph::new(&instructions, &self.cpu.address_table);
+        self.symbols = program.symbols;
+        self.rom_rows = rom_rows(&self.cpu.address_table);
+        self.instructions = instructions;
+        self.program_file = modified_time(path).map(|modified| (path.to_path_buf(), modified));
+
+        Ok(true)
+    }
 
-        let instructions = parse(instructions, &mut self.cpu.address_table)?;
+    /// Reloads the program if its file, or a file it includes, has changed since it was loaded. The
+    /// files are only checked every [RELOAD_INTERVAL], since this is called every frame.
+    fn reload_if_changed(self: &mut Self) {
+        if self.last_reload_check.elapsed() < RELOAD_INTERVAL {
+            return;
+        }
+        self.last_reload_check = Instant::now();
+        let Some((path, loaded)) = self.program_file.clone() else {
+            return;
+        };
+        let changed = |(path, loaded): &(PathBuf, SystemTime)| {
+            modified_time(path).is_some_and(|modified| modified != *loaded)
+        };
+        if changed(&(path.clone(), loaded)) || self.included_files.iter().any(changed) {
+            self.reload(&path);
+        }
+    }
+
+    /// Loads the program from its file again, moving the breakpoints on ROM addresses along with
+    /// the labels they follow. Breakpoints whose labels are gone are removed.
+    fn reload(self: &mut Self, path: &Path) {
+        let name = path
+            .file_name()
+            .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        let lines = match read_source_file(path) {
+            Ok(lines) => lines,
+            Err(e) => {
+                self.notify(format!("Failed to reload {name}: {e}"));
+                return;
+            }
+        };
+        let breakpoints = self.labelled_pc_breakpoints();
+        match self.new_program(lines, path) {
+            Ok(_) => self.program_error = None,
+            Err(e) => {
+                // The broken version is only reported once, and fixing it reloads the file
+                self.program_file =
+                    modified_time(path).map(|modified| (path.to_path_buf(), modified));
+                for (file, loaded) in &mut self.included_files {
+                    *loaded = modified_time(file).unwrap_or(*loaded);
+                }
+                self.notify(format!("{name} has errors, and was not reloaded"));
+                self.program_error = Some(e);
+                return;
+            }
+        }
+        let removed = self.resolve_pc_breakpoints(breakpoints);
+        if self.editor.path.as_deref() == Some(path) && !self.editor.is_modified() {
+            if let Ok(text) = fs::read_to_string(path) {
+                self.editor.open(path, text);
+            }
+        }
+        match removed {
+            0 => self.notify(format!("Reloaded {name}")),
+            removed => self.notify(format!(
+                "Reloaded {name}, and removed {removed} breakpoints whose labels are gone"
+            )),
+        }
+    }
 
-        let num_labels = instructions
+    /// Describes each breakpoint on a ROM address by the closest label at or before it, if there
+    /// is one, and the distance of the address from the label.
+    fn labelled_pc_breakpoints(self: &Self) -> Vec<(Option<String>, u16)> {
+        let labels = self.cpu.address_table.labels_by_address();
+        self.cpu
+            .breakpoints
             .iter()
-            .filter(|&x| match x {
-                Instruction::Label(_) => true,
-                _ => false,
+            .filter_map(|breakpoint| match breakpoint {
+                Breakpoint::PC(address) => Some(*address),
+                _ => None,
             })
-            .count();
+            .map(|address| {
+                match labels
+                    .iter()
+                    .take_while(|(start, _)| *start <= address)
+                    .last()
+                {
+                    Some((start, label)) => (Some(label.to_string()), address - start),
+                    None => (None, address),
+                }
+            })
+            .collect()
+    }
 
-        self.instructions = instructions;
-        self.num_labels = num_labels;
+    /// Replaces the breakpoints on ROM addresses with those described by
+    /// [HackGUI::labelled_pc_breakpoints], in the program loaded since. Returns the number of
+    /// breakpoints that could not be found again.
+    fn resolve_pc_breakpoints(self: &mut Self, breakpoints: Vec<(Option<String>, u16)>) -> usize {
+        self.cpu
+            .breakpoints
+            .retain(|breakpoint| !matches!(breakpoint, Breakpoint::PC(_)));
+        let mut removed = 0;
+        for (label, offset) in breakpoints {
+            let start = match &label {
+                Some(label) if self.cpu.address_table.labels.contains(label) => {
+                    self.cpu.address_table.table[label]
+                }
+                Some(_) => {
+                    removed += 1;
+                    continue;
+                }
+                None => 0,
+            };
+            self.cpu.breakpoints.insert(Breakpoint::PC(start + offset));
+        }
+        removed
+    }
 
-        Ok(true)
+    /// Builds the buttons that save the screen to a PNG file, and record it to an animated GIF or
+    /// to numbered PNG files as the program runs.
+    fn build_capture_controls(self: &mut Self, ui: &Ui) {
+        ui.same_line();
+        if ui.button("Screenshot") {
+            let file = FileDialog::new()
+                .add_filter("png", &[PNG_FILE_EXTENSION])
+                .set_directory(&self.last_dir)
+                .save_file();
+            if let Some(path) = file {
+                match screen_capture::save_screenshot(&self.cpu, &path) {
+                    Ok(()) => self.notify(format!("Saved the screen to {}", path.display())),
+                    Err(e) => self.notify(e),
+                }
+            }
+        }
+        ui.same_line();
+        if let Some(recorder) = &self.recorder {
+            if ui.button(format!(
+                "Stop recording ({} frames)##record",
+                recorder.frames
+            )) {
+                self.stop_recording();
+            }
+            return;
+        }
+        if ui.button("Record") {
+            let file = FileDialog::new()
+                .add_filter("gif", &[GIF_FILE_EXTENSION])
+                .add_filter("png frames", &[PNG_FILE_EXTENSION])
+                .set_directory(&self.last_dir)
+                .save_file();
+            if let Some(path) = file {
+                let recorder =
+                    Recorder::new(&path, self.record_interval as u64).and_then(|mut recorder| {
+                        recorder.capture(&self.cpu)?;
+                        Ok(recorder)
+                    });
+                match recorder {
+                    Ok(recorder) => self.recorder = Some(recorder),
+                    Err(e) => self.notify(e),
+                }
+            }
+        }
+        ui.same_line();
+        ui.text("every");
+        ui.same_line();
+        ui.set_next_item_width(DEBUG_BOX_SIZE * 2.0);
+        if ui
+            .input_int("cycles##record_interval", &mut self.record_interval)
+            .build()
+        {
+            self.record_interval = self.record_interval.max(1);
+        }
+    }
+
+    /// Captures a frame of the recording in progress once it is due, after each cycle. The
+    /// recording is stopped if the frame cannot be saved.
+    fn record_cycle(self: &mut Self) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if let Err(e) = recorder.tick(&self.cpu) {
+            self.recorder = None;
+            self.notify(e);
+        }
+    }
+
+    /// Ends the recording in progress, writing the frames that are still pending.
+    fn stop_recording(self: &mut Self) {
+        let Some(recorder) = self.recorder.take() else {
+            return;
+        };
+        let frames = recorder.frames;
+        let path = recorder.path.clone();
+        match recorder.finish() {
+            Ok(()) => self.notify(format!("Recorded {frames} frames to {}", path.display())),
+            Err(e) => self.notify(e),
+        }
+    }
+
+    /// Shows a message for a moment, over the rest of the GUI.
+    fn notify(self: &mut Self, message: String) {
+        self.notification = Some((message, Instant::now()));
+    }
+
+    /// Builds the window holding the last notification, until it has been shown for the
+    /// [NOTIFICATION_DURATION].
+    fn build_notification(self: &mut Self, ui: &Ui, window_width: f32, window_height: f32) {
+        let Some((message, shown)) = &self.notification else {
+            return;
+        };
+        if shown.elapsed() > NOTIFICATION_DURATION {
+            self.notification = None;
+            return;
+        }
+        ui.window("##notification")
+            .position(
+                [
+                    window_width - NOTIFICATION_MARGIN,
+                    window_height - NOTIFICATION_MARGIN,
+                ],
+                Condition::Always,
+            )
+            .position_pivot([1.0, 1.0])
+            .no_decoration()
+            .always_auto_resize(true)
+            .focus_on_appearing(false)
+            .build(|| ui.text(message));
     }
 }
 
@@ -619,6 +1737,103 @@ where
     return Ok(texture);
 }
 
+/// Builds a combo box choosing a [NumberFormat], with a hidden `label`.
+fn format_selector(ui: &Ui, label: &str, format: &mut NumberFormat) {
+    let mut index = NumberFormat::ALL
+        .iter()
+        .position(|f| f == format)
+        .unwrap_or(0);
+    ui.set_next_item_width(FORMAT_SELECTOR_WIDTH);
+    if ui.combo_simple_string(label, &mut index, &NumberFormat::NAMES) {
+        *format = NumberFormat::ALL[index];
+    }
+}
+
+/// Builds an input for a 16 bit word, displayed and edited in the `format`. Signed decimal words
+/// are edited with the usual step buttons, and other formats are written out and read when Enter
+/// is pressed. Returns whether the word was changed.
+fn input_word(ui: &Ui, label: impl AsRef<str>, value: &mut i16, format: NumberFormat) -> bool {
+    if format == NumberFormat::Decimal {
+        let mut temp = *value as i32;
+        if ui.input_int(label, &mut temp).build() {
+            *value = temp as _;
+            return true;
+        }
+        return false;
+    }
+    let mut text = format.format(*value);
+    if ui
+        .input_text(label, &mut text)
+        .enter_returns_true(true)
+        .build()
+    {
+        if let Some(parsed) = format.parse(&text) {
+            *value = parsed;
+            return true;
+        }
+    }
+    false
+}
+
+/// Lists the rows of the ROM view, with every label of the program right above the instruction at
+/// its address.
+fn rom_rows(address_table: &SymbolTable) -> Vec<RomRow> {
+    let mut labels = address_table.labels_by_address().into_iter().peekable();
+    let mut rows = vec![];
+    for address in 0..MAX_INSTRUCTIONS as u16 {
+        while let Some((_, label)) = labels.next_if(|(label_address, _)| *label_address == address)
+        {
+            rows.push(RomRow::Label(Instruction::Label(label.to_string())));
+        }
+        rows.push(RomRow::Instruction(address));
+    }
+    rows
+}
+
+/// When the file at the path was last modified, if it can be found.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// The RAM addresses covered by a column of the overview strip of the memory activity. There are
+/// fewer columns than addresses, so no column is empty.
+fn overview_addresses(column: usize, columns: usize) -> Range<usize> {
+    column * MAX_RAM / columns..(column + 1) * MAX_RAM / columns
+}
+
+/// Returns the background colour of a RAM cell that changed `elapsed` ago, which fades away over
+/// the [FLASH_DURATION].
+fn flash_color(elapsed: Duration) -> [f32; 4] {
+    let fade = 1.0 - elapsed.as_secs_f32() / FLASH_DURATION.as_secs_f32();
+    [0.1, 0.7, 0.2, fade.clamp(0.0, 1.0)]
+}
+
+/// Returns the background colour of a cell in a heat column, which grows redder as the count gets
+/// closer to the maximum.
+fn heat_color(count: u64, max_count: u64) -> ImColor32 {
+    let heat = count as f32 / max_count.max(1) as f32;
+    ImColor32::from_rgba(255, 0, 0, (40.0 + heat * 215.0) as u8)
+}
+
+/// Returns the background colour of a row in the ROM view, according to how much of its
+/// instruction has been covered. Empty rows are not coloured.
+fn coverage_color(
+    coverage: &Coverage,
+    address: usize,
+    instruction: &Instruction,
+) -> Option<ImColor32> {
+    if *instruction == Instruction::None {
+        return None;
+    }
+    Some(match coverage.state(address, instruction) {
+        CoverageState::NotExecuted => ImColor32::from_rgba(200, 0, 0, 80),
+        CoverageState::Partial => ImColor32::from_rgba(200, 150, 0, 80),
+        CoverageState::Executed => ImColor32::from_rgba(0, 160, 0, 80),
+    })
+}
+
 /// Given the slice of the RAM where the screen data is stored, it returns a framebuffer in RGBA of
 /// the screen.
 pub fn hack_to_rgba(screen: &[Wrapping<i16>]) -> Vec<u8> {
@@ -0,0 +1,682 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::num::Wrapping;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::ControlFlowGraph;
+use crate::coverage::Coverage;
+use crate::dap;
+use crate::diff::{run_lockstep, Comparison, Machine};
+use crate::formatter;
+use crate::gdbstub;
+use crate::golden;
+use crate::hack_cpu::CPUState;
+use crate::instructions::Instruction;
+use crate::lint;
+use crate::lsp;
+use crate::monitor;
+use crate::parser::MAX_RAM;
+use crate::preprocessor;
+use crate::profiler::Profiler;
+use crate::screen_capture::{self, Recorder, DEFAULT_RECORD_INTERVAL};
+use crate::symbol_table::SymbolTable;
+use crate::test_script;
+use crate::trace::{self, TraceFilter, Tracer};
+use crate::{read_source_file, split_source, MAX_INSTRUCTIONS};
+
+const DEFAULT_CYCLES: u64 = 10_000_000;
+
+const USAGE: &'static str = "Usage:
+    cpuemulator                                 Start the GUI
+    cpuemulator run <program.asm> [options]     Run a program without the GUI
+    cpuemulator diff <student.asm> <reference.asm> [options]
+                                                Run two programs in lockstep on the same input,
+                                                and report the first cycle at which they diverge
+    cpuemulator analyze <program.asm>           Warn about likely mistakes in a program, without
+                                                running it
+    cpuemulator cfg <program.asm> [out.dot]     Export the control flow graph of a program in the
+                                                Graphviz DOT format, to stdout if no file is given
+    cpuemulator fmt <program.asm>... [--check] Format programs in place, or with --check, list the
+                                                programs that are not formatted
+    cpuemulator gdb <program.asm> [--port N] [--set ADDRESS=VALUE]
+                                                Serve a program to a debugger over the GDB remote
+                                                serial protocol (default port 1234)
+    cpuemulator dap                             Serve the Debug Adapter Protocol on the standard
+                                                input and output, for debugging from an editor
+    cpuemulator lsp                             Serve the Language Server Protocol on the standard
+                                                input and output, for checking programs in an editor
+    cpuemulator monitor [program.asm] [--set ADDRESS=VALUE]
+                                                Control the machine from an interactive console,
+                                                type help for the commands
+    cpuemulator test <script.tst>...            Run test scripts, see cpuemulator help test
+    cpuemulator trace-csv <in.trace> <out.csv> [--trace-pc FROM-TO] [--trace-addr FROM-TO]
+                                                Export a binary trace as CSV
+
+Options for run and diff:
+    --cycles N              Stop after N instructions (default 10000000)
+    --set ADDRESS=VALUE     Set RAM[ADDRESS] before starting, may be repeated
+
+Options for run:
+    --trace FILE            Record an execution trace to FILE (.csv for text, binary otherwise)
+    --trace-pc FROM-TO      Only trace instructions with a PC in the range
+    --trace-addr FROM-TO    Only trace instructions accessing the RAM in the range
+    --profile               Print how often each label and instruction was executed
+    --coverage FILE         Write an LCOV coverage report of the run to FILE
+    --annotate FILE         Write the source annotated with the coverage of every line to FILE
+    --screenshot FILE       Save the screen to a PNG file when the program stops
+    --record FILE           Record the screen to FILE, an animated GIF if it ends in .gif, or
+                            numbered PNG files named after it otherwise
+    --record-every N        Capture a frame of the recording every N instructions (default 100000)
+    --until CONDITION       Stop once the condition is met, such as END, PC=END or RAM[0]=5
+    --expect-screen FILE    Fail unless the screen matches the reference PNG image when the
+                            program stops
+    --screen-diff FILE      Where to save the image of the pixels that differ from the reference
+                            (default FILE.diff.png beside the reference)
+
+Options for diff:
+    --watch FROM-TO         Compare the RAM in the range after every cycle, may be repeated
+    --ram-only              Do not compare the A, D and PC registers";
+
+/// The options accepted by each of the commands that run a program, see [parse_run_options].
+const RUN_FLAGS: [&'static str; 14] = [
+    "--cycles",
+    "--set",
+    "--trace",
+    "--trace-pc",
+    "--trace-addr",
+    "--profile",
+    "--coverage",
+    "--annotate",
+    "--screenshot",
+    "--record",
+    "--record-every",
+    "--until",
+    "--expect-screen",
+    "--screen-diff",
+];
+const DIFF_FLAGS: [&'static str; 4] = ["--cycles", "--set", "--watch", "--ram-only"];
+const ANALYZE_FLAGS: [&'static str; 0] = [];
+const GDB_FLAGS: [&'static str; 2] = ["--port", "--set"];
+const MONITOR_FLAGS: [&'static str; 1] = ["--set"];
+
+/// Options shared by the commands that run a program.
+struct RunOptions {
+    programs: Vec<PathBuf>,
+    cycles: u64,
+    ram: Vec<(u16, i16)>,
+    trace: Option<PathBuf>,
+    trace_filter: TraceFilter,
+    watch: Vec<RangeInclusive<u16>>,
+    ram_only: bool,
+    profile: bool,
+    coverage: Option<PathBuf>,
+    annotate: Option<PathBuf>,
+    screenshot: Option<PathBuf>,
+    record: Option<PathBuf>,
+    record_interval: u64,
+    until: Option<String>,
+    expected_screen: Option<PathBuf>,
+    screen_diff: Option<PathBuf>,
+    port: u16,
+}
+
+impl RunOptions {
+    /// Returns the programs given on the command line, checking that there are exactly `N`.
+    fn programs<const N: usize>(self: &Self) -> Result<[&PathBuf; N], String> {
+        let programs: Vec<&PathBuf> = self.programs.iter().collect();
+        programs
+            .try_into()
+            .map_err(|p: Vec<&PathBuf>| format!("Expected {N} programs, got {}\n{USAGE}", p.len()))
+    }
+
+    /// Creates a CPU with the RAM set up according to the options.
+    fn cpu(self: &Self) -> CPUState {
+        let mut cpu = CPUState::new();
+        self.set_ram(&mut cpu);
+        cpu
+    }
+
+    /// Creates a CPU with the program loaded, and the RAM set up according to the options. The
+    /// options take precedence over the `.data` directives of the program.
+    fn load(self: &Self, path: &Path) -> Result<(CPUState, Program), String> {
+        let mut cpu = CPUState::new();
+        let program = load_program(path, &mut cpu)?;
+        self.set_ram(&mut cpu);
+        Ok((cpu, program))
+    }
+
+    fn set_ram(self: &Self, cpu: &mut CPUState) {
+        for (address, value) in &self.ram {
+            cpu.ram[*address as usize] = Wrapping(*value);
+        }
+    }
+}
+
+/// Runs the emulator without the GUI, according to the command line arguments (excluding the
+/// name of the executable).
+pub fn run(args: &[String]) -> Result<(), String> {
+    match args[0].as_str() {
+        "run" => run_program(&args[1..]),
+        "diff" => diff_programs(&args[1..]),
+        "analyze" => analyze_program(&args[1..]),
+        "cfg" => export_cfg(&args[1..]),
+        "fmt" => format_programs(&args[1..]),
+        "gdb" => serve_gdb(&args[1..]),
+        "dap" => dap::serve().map_err(|e| format!("Debug adapter error: {e}")),
+        "lsp" => lsp::serve().map_err(|e| format!("Language server error: {e}")),
+        "monitor" => run_monitor(&args[1..]),
+        "trace-csv" => export_trace_csv(&args[1..]),
+        "test" => run_test_scripts(&args[1..]),
+        "help" if args.get(1).is_some_and(|topic| topic == "test") => {
+            println!("{}", test_script::HELP);
+            Ok(())
+        }
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        command => Err(format!("Unknown command {command}\n{USAGE}")),
+    }
+}
+
+/// Runs a program until it leaves the ROM, meets the `--until` condition, or the cycle limit is
+/// reached, and prints the final state of the registers.
+fn run_program(args: &[String]) -> Result<(), String> {
+    let options = parse_run_options(args, &RUN_FLAGS)?;
+    let [program] = options.programs()?;
+    let (mut cpu, program) = options.load(program)?;
+    if options.trace.is_some() {
+        cpu.tracer = Some(Tracer::new(options.trace_filter.clone()));
+    }
+    if options.profile {
+        cpu.profiler = Some(Profiler::new());
+    }
+
+    if options.coverage.is_some() || options.annotate.is_some() {
+        cpu.coverage = Some(Coverage::new());
+    }
+    if let Some(condition) = &options.until {
+        cpu.breakpoints
+            .insert(monitor::parse_condition(condition, &cpu)?);
+    }
+
+    let executed = match &options.record {
+        Some(path) => {
+            let mut recorder = Recorder::new(path, options.record_interval)?;
+            let executed = execute_recording(
+                &mut cpu,
+                &program.instructions,
+                options.cycles,
+                &mut recorder,
+            )?;
+            let frames = recorder.frames;
+            recorder.finish()?;
+            println!("Recorded {frames} frames to {}", path.display());
+            executed
+        }
+        None => execute(&mut cpu, &program.instructions, options.cycles),
+    };
+    println!(
+        "Executed {executed} instructions. PC: {} A: {} D: {}",
+        cpu.pc, cpu.a, cpu.d
+    );
+
+    if let Some(path) = &options.screenshot {
+        screen_capture::save_screenshot(&cpu, path)?;
+    }
+    if let (Some(path), Some(tracer)) = (&options.trace, &cpu.tracer) {
+        if tracer.is_full() {
+            eprintln!(
+                "Warning: the trace reached {} entries, and was truncated",
+                trace::MAX_TRACE_ENTRIES
+            );
+        }
+        tracer
+            .save(path)
+            .map_err(|e| format!("Failed to write trace {}: {e}", path.display()))?;
+    }
+    if let Some(profiler) = &cpu.profiler {
+        print!(
+            "{}",
+            profiler.report(&cpu.address_table, &program.instructions)
+        );
+    }
+    if let Some(coverage) = &cpu.coverage {
+        if let Some(path) = &options.coverage {
+            let report = coverage.lcov(
+                &program.path.display().to_string(),
+                &program.source_map,
+                &program.instructions,
+            );
+            fs::write(path, report)
+                .map_err(|e| format!("Failed to write coverage {}: {e}", path.display()))?;
+        }
+        if let Some(path) = &options.annotate {
+            let report =
+                coverage.annotate(&program.lines, &program.source_map, &program.instructions);
+            fs::write(path, report)
+                .map_err(|e| format!("Failed to write coverage {}: {e}", path.display()))?;
+        }
+    }
+    if let Some(reference) = &options.expected_screen {
+        let diff = match &options.screen_diff {
+            Some(diff) => diff.clone(),
+            None => golden::default_diff_path(reference),
+        };
+        if let Some(mismatch) = golden::compare_screen(&cpu, reference, &diff)? {
+            return Err(mismatch.to_string());
+        }
+        println!("The screen matches {}", reference.display());
+    }
+    Ok(())
+}
+
+/// Runs test scripts, stopping at the first that fails.
+fn run_test_scripts(args: &[String]) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(format!("Expected test scripts\n{USAGE}"));
+    }
+    for script in args {
+        let report =
+            test_script::run(Path::new(script)).map_err(|e| format!("{script}: FAILED: {e}"))?;
+        println!(
+            "{script}: passed, {} commands, {} screens compared",
+            report.commands, report.screens
+        );
+    }
+    Ok(())
+}
+
+/// Runs a student's program and a reference solution in lockstep, and reports the first cycle at
+/// which their registers, or the watched RAM, diverge.
+fn diff_programs(args: &[String]) -> Result<(), String> {
+    let options = parse_run_options(args, &DIFF_FLAGS)?;
+    let [student_path, reference_path] = options.programs()?;
+    let comparison = Comparison {
+        registers: !options.ram_only,
+        watched: options.watch.clone(),
+    };
+    if !comparison.registers && comparison.watched.is_empty() {
+        return Err(String::from(
+            "Nothing to compare, use --watch to compare the RAM",
+        ));
+    }
+    if let Some(range) = comparison
+        .watched
+        .iter()
+        .find(|r| *r.end() as usize >= MAX_RAM)
+    {
+        return Err(format!(
+            "Watched range {}-{} is out of the RAM",
+            range.start(),
+            range.end()
+        ));
+    }
+
+    let (student_cpu, student_program) = options.load(student_path)?;
+    let (reference_cpu, reference_program) = options.load(reference_path)?;
+    let mut student = Machine {
+        cpu: student_cpu,
+        instructions: &student_program.instructions,
+    };
+    let mut reference = Machine {
+        cpu: reference_cpu,
+        instructions: &reference_program.instructions,
+    };
+
+    match run_lockstep(&mut student, &mut reference, &comparison, options.cycles) {
+        Some(divergence) => Err(divergence.to_string()),
+        None => {
+            println!("No divergence within {} cycles", options.cycles);
+            Ok(())
+        }
+    }
+}
+
+/// Prints the warnings found in a program by [lint::analyze], along with their source lines.
+fn analyze_program(args: &[String]) -> Result<(), String> {
+    let options = parse_run_options(args, &ANALYZE_FLAGS)?;
+    let [path] = options.programs()?;
+    let (cpu, program) = options.load(path)?;
+    let warnings = lint::analyze(
+        &program.instructions,
+        &program.symbols,
+        &program.expression_symbols,
+        &cpu.address_table,
+    );
+    for warning in &warnings {
+        match program.source_map.get(warning.address as usize) {
+            Some(line) => println!("{}:{}: {warning}", path.display(), line + 1),
+            None => println!("{}: {warning}", path.display()),
+        }
+    }
+    println!("{} warnings", warnings.len());
+    Ok(())
+}
+
+/// Writes the control flow graph of a program in the DOT format.
+fn export_cfg(args: &[String]) -> Result<(), String> {
+    let (path, output) = match args {
+        [path] => (path, None),
+        [path, output] => (path, Some(output)),
+        _ => {
+            return Err(format!(
+                "Expected a program, and optionally an output file\n{USAGE}"
+            ))
+        }
+    };
+    let mut cpu = CPUState::new();
+    let program = load_program(Path::new(path), &mut cpu)?;
+    let graph = ControlFlowGraph::new(&program.instructions, &cpu.address_table);
+    let dot = graph.to_dot(&program.instructions, &program.symbols, &cpu.address_table);
+    match output {
+        Some(output) => {
+            fs::write(output, dot).map_err(|e| format!("Failed to write {output}: {e}"))
+        }
+        None => {
+            print!("{dot}");
+            Ok(())
+        }
+    }
+}
+
+/// Formats programs in place, or with `--check`, fails if any of them are not formatted.
+fn format_programs(args: &[String]) -> Result<(), String> {
+    let mut check = false;
+    let mut paths = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {flag}")),
+            path => paths.push(path),
+        }
+    }
+    if paths.is_empty() {
+        return Err(format!("Expected at least one program\n{USAGE}"));
+    }
+    let mut unformatted = 0;
+    for path in paths {
+        let path = Path::new(path);
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        let formatted = formatter::format(&source);
+        if formatted == source {
+            continue;
+        }
+        check_unchanged(path, &source, &formatted)?;
+        if check {
+            println!("{} is not formatted", path.display());
+            unformatted += 1;
+        } else {
+            fs::write(path, formatted)
+                .map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+            println!("Formatted {}", path.display());
+        }
+    }
+    if unformatted > 0 {
+        return Err(format!("{unformatted} programs are not formatted"));
+    }
+    Ok(())
+}
+
+/// Checks that formatting a program does not change what it assembles to. Programs that do not
+/// assemble are formatted regardless, since there is nothing to compare.
+fn check_unchanged(path: &Path, source: &str, formatted: &str) -> Result<(), String> {
+    let assemble = |source: &str| {
+        let lines = split_source(source).ok()?;
+        preprocessor::assemble(&lines, path, &mut SymbolTable::new()).ok()
+    };
+    let Some(before) = assemble(source) else {
+        return Ok(());
+    };
+    match assemble(formatted) {
+        Some(after) if after.instructions == before.instructions && after.data == before.data => {
+            Ok(())
+        }
+        _ => Err(format!(
+            "Formatting {} would change the program, so it was left as it is",
+            path.display()
+        )),
+    }
+}
+
+/// Loads a program, and waits for a debugger to connect to it over the GDB remote serial
+/// protocol.
+fn serve_gdb(args: &[String]) -> Result<(), String> {
+    let options = parse_run_options(args, &GDB_FLAGS)?;
+    let [program] = options.programs()?;
+    let (cpu, program) = options.load(program)?;
+    gdbstub::serve(options.port, cpu, &program.instructions)
+        .map_err(|e| format!("GDB server error: {e}"))
+}
+
+/// Converts a binary trace into CSV, optionally filtering it.
+fn export_trace_csv(args: &[String]) -> Result<(), String> {
+    let mut paths: Vec<&String> = vec![];
+    let mut filter = TraceFilter::default();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--trace-pc" => filter.pc_range = parse_range(take_value(args, &mut i)?)?,
+            "--trace-addr" => filter.address_range = Some(parse_range(take_value(args, &mut i)?)?),
+            _ => paths.push(&args[i]),
+        }
+        i += 1;
+    }
+    let [input, output] = paths[..] else {
+        return Err(format!("Expected an input and an output file\n{USAGE}"));
+    };
+
+    let entries =
+        trace::load(Path::new(input)).map_err(|e| format!("Failed to read trace {input}: {e}"))?;
+    let mut tracer = Tracer::new(filter);
+    for entry in entries {
+        tracer.record(entry);
+    }
+    tracer
+        .save(Path::new(output))
+        .map_err(|e| format!("Failed to write {output}: {e}"))
+}
+
+/// Reads monitor commands from the standard input until it ends, or the user quits.
+fn run_monitor(args: &[String]) -> Result<(), String> {
+    let options = parse_run_options(args, &MONITOR_FLAGS)?;
+    let (mut cpu, mut instructions) = match &options.programs[..] {
+        [] => (
+            options.cpu(),
+            [const { Instruction::None }; MAX_INSTRUCTIONS],
+        ),
+        [program] => {
+            let (cpu, program) = options.load(program)?;
+            (cpu, program.instructions)
+        }
+        programs => {
+            return Err(format!(
+                "Expected 1 program, got {}\n{USAGE}",
+                programs.len()
+            ))
+        }
+    };
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        let mut command = String::new();
+        if stdin.read_line(&mut command).map_err(|e| e.to_string())? == 0 {
+            return Ok(());
+        }
+        match command.trim() {
+            "quit" | "exit" | "q" => return Ok(()),
+            command => match monitor::execute(command, &mut cpu, &mut instructions) {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => println!("{output}"),
+                Err(e) => println!("Error: {e}"),
+            },
+        }
+    }
+}
+
+/// A program read from a source file, along with its parsed instructions.
+struct Program {
+    path: PathBuf,
+    lines: Vec<String>,
+    source_map: Vec<usize>,
+    symbols: HashMap<u16, String>,
+    expression_symbols: HashSet<String>,
+    instructions: [Instruction; MAX_INSTRUCTIONS],
+}
+
+/// Reads and parses a program, filling in the symbol table of the CPU, and the RAM set by its
+/// `.data` directives.
+fn load_program(path: &Path, cpu: &mut CPUState) -> Result<Program, String> {
+    let lines = read_source_file(path)?;
+    cpu.reset_address_table();
+    let program = preprocessor::assemble(&lines, path, &mut cpu.address_table)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    cpu.load_data(&program.data);
+    Ok(Program {
+        path: path.to_path_buf(),
+        lines: lines.to_vec(),
+        source_map: program.source_map,
+        symbols: program.symbols,
+        expression_symbols: program.expression_symbols,
+        instructions: program.instructions,
+    })
+}
+
+/// Executes up to `cycles` instructions, stopping early if the program counter leaves the ROM, or
+/// a breakpoint is hit. Returns the number of instructions executed.
+fn execute(cpu: &mut CPUState, instructions: &[Instruction; MAX_INSTRUCTIONS], cycles: u64) -> u64 {
+    for cycle in 0..cycles {
+        if cpu.pc as usize >= MAX_INSTRUCTIONS {
+            return cycle;
+        }
+        cpu.interpret(&instructions[cpu.pc as usize]);
+        if cpu.hit_breakpoint() {
+            return cycle + 1;
+        }
+    }
+    cycles
+}
+
+/// Runs a program like [execute], capturing a frame of the screen at the start, every interval of
+/// the recorder, and once the program stops.
+fn execute_recording(
+    cpu: &mut CPUState,
+    instructions: &[Instruction; MAX_INSTRUCTIONS],
+    cycles: u64,
+    recorder: &mut Recorder,
+) -> Result<u64, String> {
+    recorder.capture(cpu)?;
+    let mut executed = 0;
+    while executed < cycles {
+        let interval = recorder.interval.min(cycles - executed);
+        let run = execute(cpu, instructions, interval);
+        executed += run;
+        recorder.capture(cpu)?;
+        if run < interval || cpu.hit_breakpoint() {
+            break;
+        }
+    }
+    Ok(executed)
+}
+
+/// Parses the programs and options given to a command, rejecting the options which do not apply to
+/// it, which are those not in `flags`.
+fn parse_run_options(args: &[String], flags: &[&str]) -> Result<RunOptions, String> {
+    let mut options = RunOptions {
+        programs: vec![],
+        cycles: DEFAULT_CYCLES,
+        ram: vec![],
+        trace: None,
+        trace_filter: TraceFilter::default(),
+        watch: vec![],
+        ram_only: false,
+        profile: false,
+        coverage: None,
+        annotate: None,
+        screenshot: None,
+        record: None,
+        record_interval: DEFAULT_RECORD_INTERVAL,
+        until: None,
+        expected_screen: None,
+        screen_diff: None,
+        port: gdbstub::DEFAULT_PORT,
+    };
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg.starts_with("--") && !flags.contains(&arg) {
+            let mut known = RUN_FLAGS.iter().chain(&DIFF_FLAGS).chain(&GDB_FLAGS);
+            return Err(if known.any(|flag| *flag == arg) {
+                format!("The {arg} option does not apply to this command\n{USAGE}")
+            } else {
+                format!("Unknown option {arg}")
+            });
+        }
+        match arg {
+            "--cycles" => options.cycles = parse_number(take_value(args, &mut i)?)?,
+            "--set" => options
+                .ram
+                .push(parse_assignment(take_value(args, &mut i)?)?),
+            "--trace" => options.trace = Some(PathBuf::from(take_value(args, &mut i)?)),
+            "--trace-pc" => options.trace_filter.pc_range = parse_range(take_value(args, &mut i)?)?,
+            "--trace-addr" => {
+                options.trace_filter.address_range = Some(parse_range(take_value(args, &mut i)?)?)
+            }
+            "--watch" => options.watch.push(parse_range(take_value(args, &mut i)?)?),
+            "--ram-only" => options.ram_only = true,
+            "--profile" => options.profile = true,
+            "--coverage" => options.coverage = Some(PathBuf::from(take_value(args, &mut i)?)),
+            "--port" => options.port = parse_number(take_value(args, &mut i)?)?,
+            "--annotate" => options.annotate = Some(PathBuf::from(take_value(args, &mut i)?)),
+            "--screenshot" => options.screenshot = Some(PathBuf::from(take_value(args, &mut i)?)),
+            "--record" => options.record = Some(PathBuf::from(take_value(args, &mut i)?)),
+            "--record-every" => options.record_interval = parse_number(take_value(args, &mut i)?)?,
+            "--until" => options.until = Some(take_value(args, &mut i)?.to_string()),
+            "--expect-screen" => {
+                options.expected_screen = Some(PathBuf::from(take_value(args, &mut i)?))
+            }
+            "--screen-diff" => options.screen_diff = Some(PathBuf::from(take_value(args, &mut i)?)),
+            path => options.programs.push(PathBuf::from(path)),
+        }
+        i += 1;
+    }
+    Ok(options)
+}
+
+/// Returns the value following the flag at `args[*i]`, and advances past it.
+fn take_value<'a>(args: &'a [String], i: &mut usize) -> Result<&'a str, String> {
+    let flag = &args[*i];
+    *i += 1;
+    args.get(*i)
+        .map(|s| s.as_str())
+        .ok_or(format!("Expected a value after {flag}"))
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse::<T>().map_err(|_| format!("Invalid number {s}"))
+}
+
+/// Parses a range of the form `FROM-TO`, inclusive on both ends.
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (from, to) = s
+        .split_once('-')
+        .ok_or(format!("Expected a range of the form FROM-TO, got {s}"))?;
+    Ok(parse_number(from)?..=parse_number(to)?)
+}
+
+/// Parses a RAM assignment of the form `ADDRESS=VALUE`.
+fn parse_assignment(s: &str) -> Result<(u16, i16), String> {
+    let (address, value) = s
+        .split_once('=')
+        .ok_or(format!("Expected ADDRESS=VALUE, got {s}"))?;
+    let address: u16 = parse_number(address)?;
+    if address as usize >= MAX_RAM {
+        return Err(format!("RAM address {address} is out of range"));
+    }
+    Ok((address, parse_number(value)?))
+}
@@ -1,9 +1,10 @@
 use core::fmt;
 
+use crate::expression::parse_literal;
+
 /// Represents the different kinds of instructions that are run on the CPU.
-/// Label is never constructed, but left for the future, since there is an intention to show the
-/// labels in the emulator
-#[allow(dead_code)]
+/// Labels take up no ROM address, so a [Instruction::Label] is never part of a program, and is only
+/// used for showing the labels in the ROM view of the emulator.
 #[derive(Debug, PartialEq, Eq)]
 pub enum Instruction {
     A(A),
@@ -16,13 +17,47 @@ impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Instruction::A(a) => write!(f, "{}", a),
-            Instruction::C(c) => writeln!(f, "{}", c),
+            Instruction::C(c) => write!(f, "{}", c),
             Instruction::Label(l) => write!(f, "({})", l),
             Instruction::None => write!(f, ""),
         }
     }
 }
 
+/// The machine code used to represent [Instruction::None] (and [Instruction::Label]) in places
+/// where a 16 bit word is needed, such as execution traces. The `100` prefix is not a valid HACK
+/// instruction, so it can never clash with a real one.
+pub const NO_INSTRUCTION: u16 = 0b1000_0000_0000_0000;
+
+impl Instruction {
+    /// Encodes the instruction into its 16 bit HACK machine code. Instructions that do not exist
+    /// in the ROM are encoded as [NO_INSTRUCTION].
+    pub fn machine_code(&self) -> u16 {
+        match self {
+            Instruction::A(a) => a.dest as u16 & 0x7FFF,
+            Instruction::C(c) => c.machine_code(),
+            Instruction::Label(_) | Instruction::None => NO_INSTRUCTION,
+        }
+    }
+
+    /// Decodes a 16 bit HACK machine code word back into an [Instruction]. Words that are not
+    /// valid instructions decode to [Instruction::None].
+    pub fn from_machine_code(code: u16) -> Instruction {
+        if code & 0x8000 == 0 {
+            return Instruction::A(A { dest: code as i16 });
+        }
+        let comp = match Comp::from_bits(code >> 6) {
+            Some(c) => c,
+            None => return Instruction::None,
+        };
+        Instruction::C(C {
+            dest: Destination::from_bits(code >> 3),
+            comp,
+            jump: Jump::from_bits(code),
+        })
+    }
+}
+
 /// Represents an A(ddress) instruction. This sets the A register to some 15 bit value.
 #[derive(Debug, PartialEq, Eq)]
 pub struct A {
@@ -30,12 +65,14 @@ pub struct A {
 }
 impl A {
     /// Create a new [A] instruction from an input string. Useful for building from source files.
-    pub fn new(dest: &str) -> Self {
-        Self {
-            dest: match dest.parse::<i16>() {
-                Ok(d) => d,
-                Err(e) => panic!("Failed to parse the destination of the A instruction: {e}"),
-            },
+    /// The destination may be written in any of the formats of [parse_literal].
+    pub fn new(dest: &str) -> Result<Self, String> {
+        match parse_literal(dest) {
+            Some(d) if (0..=i16::MAX as i32).contains(&d) => Ok(Self { dest: d as i16 }),
+            Some(d) => Err(format!("{d} does not fit in an A instruction")),
+            None => Err(format!(
+                "Failed to parse the destination of the A instruction: {dest}"
+            )),
         }
     }
 }
@@ -60,9 +97,12 @@ pub enum Destination {
 }
 
 impl Destination {
+    /// Every destination mnemonic, as written in source files.
+    pub const MNEMONICS: [&'static str; 7] = ["A", "M", "D", "MD", "AM", "AD", "AMD"];
+
     /// Create a new destination for a [C] instruction.
-    fn new(dest: &str) -> Destination {
-        match dest {
+    fn new(dest: &str) -> Result<Destination, String> {
+        let destination = match dest {
             "" => Destination::None,
             "A" => Destination::A,
             "M" => Destination::M,
@@ -71,9 +111,46 @@ impl Destination {
             "AM" => Destination::AM,
             "AD" => Destination::AD,
             "AMD" => Destination::AMD,
-            _ => panic!("Parse error: {} is not a valid destination", dest),
+            _ => return Err(format!("{} is not a valid destination", dest)),
+        };
+        Ok(destination)
+    }
+
+    /// The `d1 d2 d3` bits of the destination, as they appear in the machine code.
+    fn bits(&self) -> u16 {
+        match self {
+            Destination::None => 0b000,
+            Destination::M => 0b001,
+            Destination::D => 0b010,
+            Destination::MD => 0b011,
+            Destination::A => 0b100,
+            Destination::AM => 0b101,
+            Destination::AD => 0b110,
+            Destination::AMD => 0b111,
         }
     }
+
+    /// Reads the destination from the lowest 3 bits of `bits`.
+    fn from_bits(bits: u16) -> Destination {
+        match bits & 0b111 {
+            0b000 => Destination::None,
+            0b001 => Destination::M,
+            0b010 => Destination::D,
+            0b011 => Destination::MD,
+            0b100 => Destination::A,
+            0b101 => Destination::AM,
+            0b110 => Destination::AD,
+            _ => Destination::AMD,
+        }
+    }
+
+    /// Whether the result of the computation is stored in the RAM.
+    pub fn writes_memory(&self) -> bool {
+        matches!(
+            self,
+            Destination::M | Destination::MD | Destination::AM | Destination::AMD
+        )
+    }
 }
 
 impl fmt::Display for Destination {
@@ -107,9 +184,12 @@ pub enum Jump {
 }
 
 impl Jump {
+    /// Every jump mnemonic, as written in source files.
+    pub const MNEMONICS: [&'static str; 7] = ["JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
     /// Create a new jump location for a [C] instruction
-    fn new(jump: &str) -> Jump {
-        match jump {
+    fn new(jump: &str) -> Result<Jump, String> {
+        let jump = match jump {
             "" => Jump::None,
             "JGT" => Jump::JGT,
             "JEQ" => Jump::JEQ,
@@ -118,7 +198,50 @@ impl Jump {
             "JNE" => Jump::JNE,
             "JLE" => Jump::JLE,
             "JMP" => Jump::JMP,
-            _ => panic!("Parse error: {} is not a valid jump instruction", jump),
+            _ => return Err(format!("{} is not a valid jump instruction", jump)),
+        };
+        Ok(jump)
+    }
+
+    /// Whether the jump is taken, given the value computed by its [C] instruction.
+    pub fn taken(&self, value: i16) -> bool {
+        match self {
+            Jump::None => false,
+            Jump::JGT => value > 0,
+            Jump::JEQ => value == 0,
+            Jump::JGE => value >= 0,
+            Jump::JLT => value < 0,
+            Jump::JNE => value != 0,
+            Jump::JLE => value <= 0,
+            Jump::JMP => true,
+        }
+    }
+
+    /// The `j1 j2 j3` bits of the jump, as they appear in the machine code.
+    fn bits(&self) -> u16 {
+        match self {
+            Jump::None => 0b000,
+            Jump::JGT => 0b001,
+            Jump::JEQ => 0b010,
+            Jump::JGE => 0b011,
+            Jump::JLT => 0b100,
+            Jump::JNE => 0b101,
+            Jump::JLE => 0b110,
+            Jump::JMP => 0b111,
+        }
+    }
+
+    /// Reads the jump from the lowest 3 bits of `bits`.
+    fn from_bits(bits: u16) -> Jump {
+        match bits & 0b111 {
+            0b000 => Jump::None,
+            0b001 => Jump::JGT,
+            0b010 => Jump::JEQ,
+            0b011 => Jump::JGE,
+            0b100 => Jump::JLT,
+            0b101 => Jump::JNE,
+            0b110 => Jump::JLE,
+            _ => Jump::JMP,
         }
     }
 }
@@ -181,9 +304,16 @@ pub enum Comp {
 }
 
 impl Comp {
+    /// Every computation mnemonic, as written in source files.
+    pub const MNEMONICS: [&'static str; 34] = [
+        "0", "1", "-1", "D", "A", "!D", "!A", "-D", "-A", "D+1", "A+1", "D-1", "A-1", "D+A", "D-A",
+        "A-D", "D&A", "D|A", "M", "!M", "-M", "M+1", "M-1", "D+M", "D-M", "M-D", "D&M", "D|M",
+        "A<<", "D<<", "M<<", "A>>", "D>>", "M>>",
+    ];
+
     /// Create a new computation for a [C] instruction.
-    fn new(comp: &str) -> Comp {
-        match comp {
+    fn new(comp: &str) -> Result<Comp, String> {
+        let comp = match comp {
             "0" => Comp::Zero,
             "1" => Comp::One,
             "-1" => Comp::MinusOne,
@@ -221,11 +351,117 @@ impl Comp {
             "D>>" => Comp::RightShiftD,
             "M>>" => Comp::RightShiftM,
 
-            _ => panic!(
-                "Parse error: {} is not a valid comparison instruction",
-                comp
-            ),
-        }
+            _ => return Err(format!("{} is not a valid comparison instruction", comp)),
+        };
+        Ok(comp)
+    }
+
+    /// The upper 10 bits of the machine code of a [C] instruction using this computation. This
+    /// is the `111` prefix followed by the `a c1 c2 c3 c4 c5 c6` bits, except for the shift
+    /// computations, which use the `101` prefix.
+    fn bits(&self) -> u16 {
+        let (prefix, acomp): (u16, u16) = match self {
+            Comp::Zero => (0b111, 0b0101010),
+            Comp::One => (0b111, 0b0111111),
+            Comp::MinusOne => (0b111, 0b0111010),
+            Comp::D => (0b111, 0b0001100),
+            Comp::A => (0b111, 0b0110000),
+            Comp::NotD => (0b111, 0b0001101),
+            Comp::NotA => (0b111, 0b0110001),
+            Comp::MinusD => (0b111, 0b0001111),
+            Comp::MinusA => (0b111, 0b0110011),
+            Comp::DPlusOne => (0b111, 0b0011111),
+            Comp::APlusOne => (0b111, 0b0110111),
+            Comp::DMinusOne => (0b111, 0b0001110),
+            Comp::AMinusOne => (0b111, 0b0110010),
+            Comp::DPlusA => (0b111, 0b0000010),
+            Comp::DMinusA => (0b111, 0b0010011),
+            Comp::AMinusD => (0b111, 0b0000111),
+            Comp::DAndA => (0b111, 0b0000000),
+            Comp::DOrA => (0b111, 0b0010101),
+
+            Comp::M => (0b111, 0b1110000),
+            Comp::NotM => (0b111, 0b1110001),
+            Comp::MinusM => (0b111, 0b1110011),
+            Comp::MPlusOne => (0b111, 0b1110111),
+            Comp::MMinusOne => (0b111, 0b1110010),
+            Comp::DPlusM => (0b111, 0b1000010),
+            Comp::DMinusM => (0b111, 0b1010011),
+            Comp::MMinusD => (0b111, 0b1000111),
+            Comp::DAndM => (0b111, 0b1000000),
+            Comp::DOrM => (0b111, 0b1010101),
+
+            Comp::LeftShiftA => (0b101, 0b0100000),
+            Comp::LeftShiftD => (0b101, 0b0110000),
+            Comp::LeftShiftM => (0b101, 0b1100000),
+            Comp::RightShiftA => (0b101, 0b0000000),
+            Comp::RightShiftD => (0b101, 0b0010000),
+            Comp::RightShiftM => (0b101, 0b1000000),
+        };
+        prefix << 7 | acomp
+    }
+
+    /// Reads the computation from the lowest 10 bits of `bits`, the inverse of [Comp::bits].
+    fn from_bits(bits: u16) -> Option<Comp> {
+        let comp = match bits & 0b11_1111_1111 {
+            0b111_0101010 => Comp::Zero,
+            0b111_0111111 => Comp::One,
+            0b111_0111010 => Comp::MinusOne,
+            0b111_0001100 => Comp::D,
+            0b111_0110000 => Comp::A,
+            0b111_0001101 => Comp::NotD,
+            0b111_0110001 => Comp::NotA,
+            0b111_0001111 => Comp::MinusD,
+            0b111_0110011 => Comp::MinusA,
+            0b111_0011111 => Comp::DPlusOne,
+            0b111_0110111 => Comp::APlusOne,
+            0b111_0001110 => Comp::DMinusOne,
+            0b111_0110010 => Comp::AMinusOne,
+            0b111_0000010 => Comp::DPlusA,
+            0b111_0010011 => Comp::DMinusA,
+            0b111_0000111 => Comp::AMinusD,
+            0b111_0000000 => Comp::DAndA,
+            0b111_0010101 => Comp::DOrA,
+
+            0b111_1110000 => Comp::M,
+            0b111_1110001 => Comp::NotM,
+            0b111_1110011 => Comp::MinusM,
+            0b111_1110111 => Comp::MPlusOne,
+            0b111_1110010 => Comp::MMinusOne,
+            0b111_1000010 => Comp::DPlusM,
+            0b111_1010011 => Comp::DMinusM,
+            0b111_1000111 => Comp::MMinusD,
+            0b111_1000000 => Comp::DAndM,
+            0b111_1010101 => Comp::DOrM,
+
+            0b101_0100000 => Comp::LeftShiftA,
+            0b101_0110000 => Comp::LeftShiftD,
+            0b101_1100000 => Comp::LeftShiftM,
+            0b101_0000000 => Comp::RightShiftA,
+            0b101_0010000 => Comp::RightShiftD,
+            0b101_1000000 => Comp::RightShiftM,
+            _ => return None,
+        };
+        Some(comp)
+    }
+
+    /// Whether the computation reads from the RAM, at the address held in the A register.
+    pub fn reads_memory(&self) -> bool {
+        matches!(
+            self,
+            Comp::M
+                | Comp::NotM
+                | Comp::MinusM
+                | Comp::MPlusOne
+                | Comp::MMinusOne
+                | Comp::DPlusM
+                | Comp::DMinusM
+                | Comp::MMinusD
+                | Comp::DAndM
+                | Comp::DOrM
+                | Comp::LeftShiftM
+                | Comp::RightShiftM
+        )
     }
 }
 
@@ -284,12 +520,17 @@ pub struct C {
 
 impl C {
     /// Create a new [C] instruction based off the inputs from the source file.
-    pub fn new(dest: &str, comp: &str, jump: &str) -> Self {
-        Self {
-            dest: Destination::new(dest),
-            comp: Comp::new(comp),
-            jump: Jump::new(jump),
-        }
+    pub fn new(dest: &str, comp: &str, jump: &str) -> Result<Self, String> {
+        Ok(Self {
+            dest: Destination::new(dest)?,
+            comp: Comp::new(comp)?,
+            jump: Jump::new(jump)?,
+        })
+    }
+
+    /// Encodes the instruction into its 16 bit HACK machine code.
+    pub fn machine_code(&self) -> u16 {
+        self.comp.bits() << 6 | self.dest.bits() << 3 | self.jump.bits()
     }
 }
 
@@ -305,3 +546,58 @@ impl fmt::Display for C {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn c_instructions_round_trip_through_machine_code() {
+        let destinations = [""].into_iter().chain(Destination::MNEMONICS);
+        for dest in destinations {
+            for comp in Comp::MNEMONICS {
+                for jump in [""].into_iter().chain(Jump::MNEMONICS) {
+                    let instruction = Instruction::C(C::new(dest, comp, jump).unwrap());
+                    let decoded = Instruction::from_machine_code(instruction.machine_code());
+                    assert_eq!(decoded, instruction, "{dest}={comp};{jump}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn computations_have_distinct_machine_code() {
+        let mut codes: Vec<u16> = Comp::MNEMONICS
+            .iter()
+            .map(|comp| C::new("", comp, "").unwrap().machine_code())
+            .collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), Comp::MNEMONICS.len());
+    }
+
+    #[test]
+    fn a_instructions_round_trip_through_machine_code() {
+        for dest in [0, 1, 16384, i16::MAX] {
+            let instruction = Instruction::A(A { dest });
+            assert_eq!(instruction.machine_code(), dest as u16);
+            assert_eq!(
+                Instruction::from_machine_code(instruction.machine_code()),
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn missing_instructions_decode_to_none() {
+        assert_eq!(Instruction::None.machine_code(), NO_INSTRUCTION);
+        assert_eq!(
+            Instruction::Label(String::from("LOOP")).machine_code(),
+            NO_INSTRUCTION
+        );
+        assert_eq!(
+            Instruction::from_machine_code(NO_INSTRUCTION),
+            Instruction::None
+        );
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::expression::is_symbol;
+use crate::instructions::{Destination, Instruction, Jump};
+use crate::symbol_table::SymbolTable;
+use crate::MAX_INSTRUCTIONS;
+
+/// The shortest variable that is checked for being a typo, since short names such as `i` or `n1`
+/// are within an edit or two of many other symbols.
+const MIN_TYPO_LENGTH: usize = 4;
+/// A variable is reported as a possible typo of another symbol if it is at most one single
+/// character edit from it for every this many characters of its name.
+const CHARACTERS_PER_TYPO: usize = 3;
+
+/// A likely mistake in a program, found before it runs. The `address` is the ROM address of the
+/// instruction the mistake concerns.
+#[derive(Debug)]
+pub struct Warning {
+    pub address: u16,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ROM[{}]: {}", self.address, self.message)
+    }
+}
+
+/// Looks for common mistakes in a parsed program, which assemble without errors, but rarely do
+/// what was meant. The `symbols` hold the symbol written in each A instruction, by ROM address,
+/// and the `expression_symbols` those used within constant expressions. The warnings are sorted
+/// by address.
+pub fn analyze(
+    instructions: &[Instruction; MAX_INSTRUCTIONS],
+    symbols: &HashMap<u16, String>,
+    expression_symbols: &HashSet<String>,
+    address_table: &SymbolTable,
+) -> Vec<Warning> {
+    let length = instructions
+        .iter()
+        .rposition(|instruction| *instruction != Instruction::None)
+        .map_or(0, |last| last + 1);
+    let program = &instructions[..length];
+    let mut warnings = vec![];
+    memory_at_labels(program, symbols, address_table, &mut warnings);
+    jumps_without_targets(program, &mut warnings);
+    unused_labels(
+        program,
+        symbols,
+        expression_symbols,
+        address_table,
+        &mut warnings,
+    );
+    single_use_variables(symbols, expression_symbols, address_table, &mut warnings);
+    unreachable_instructions(program, address_table, &mut warnings);
+    missing_end_loop(program, &mut warnings);
+    warnings.sort_by_key(|warning| warning.address);
+    warnings
+}
+
+/// Finds `M` used right after an `@` of a label, which refers to the RAM at a ROM address.
+fn memory_at_labels(
+    program: &[Instruction],
+    symbols: &HashMap<u16, String>,
+    address_table: &SymbolTable,
+    warnings: &mut Vec<Warning>,
+) {
+    for (address, pair) in program.windows(2).enumerate() {
+        let Instruction::C(c) = &pair[1] else {
+            continue;
+        };
+        let Some(label) = symbols.get(&(address as u16)) else {
+            continue;
+        };
+        if address_table.labels.contains(label) && (c.comp.reads_memory() || c.dest.writes_memory())
+        {
+            warnings.push(Warning {
+                address: address as u16 + 1,
+                message: format!(
+                    "M is used after @{label}, but {label} is a label, so M is the RAM at a ROM address"
+                ),
+            });
+        }
+    }
+}
+
+/// Finds jumps that are not preceded by an instruction setting A, so they jump to whatever
+/// address happens to be left in A.
+fn jumps_without_targets(program: &[Instruction], warnings: &mut Vec<Warning>) {
+    for (address, instruction) in program.iter().enumerate() {
+        let Instruction::C(c) = instruction else {
+            continue;
+        };
+        if c.jump == Jump::None {
+            continue;
+        }
+        let targeted = match address.checked_sub(1).map(|previous| &program[previous]) {
+            Some(Instruction::A(_)) => true,
+            Some(Instruction::C(previous)) => matches!(
+                previous.dest,
+                Destination::A | Destination::AM | Destination::AD | Destination::AMD
+            ),
+            _ => false,
+        };
+        if !targeted {
+            warnings.push(Warning {
+                address: address as u16,
+                message: String::from(
+                    "The jump is not preceded by an A instruction, so it jumps to whatever is in A",
+                ),
+            });
+        }
+    }
+}
+
+/// Finds labels that are never referenced, neither by name, within an expression, nor by an A
+/// instruction followed by a jump to their address.
+fn unused_labels(
+    program: &[Instruction],
+    symbols: &HashMap<u16, String>,
+    expression_symbols: &HashSet<String>,
+    address_table: &SymbolTable,
+    warnings: &mut Vec<Warning>,
+) {
+    let referenced: HashSet<&String> = symbols.values().chain(expression_symbols).collect();
+    let jump_targets: HashSet<u16> = program
+        .windows(2)
+        .filter_map(|pair| match (&pair[0], &pair[1]) {
+            (Instruction::A(a), Instruction::C(c)) if c.jump != Jump::None => Some(a.dest as u16),
+            _ => None,
+        })
+        .collect();
+    for (address, label) in address_table.labels_by_address() {
+        if !referenced.contains(&label.to_string()) && !jump_targets.contains(&address) {
+            warnings.push(Warning {
+                address,
+                message: format!("The label {label} is never referenced"),
+            });
+        }
+    }
+}
+
+/// Finds variables that are only used once, and are spelled like another symbol, which are usually
+/// a typo of it, since `labels_and_variables` quietly allocates any name it does not know.
+fn single_use_variables(
+    symbols: &HashMap<u16, String>,
+    expression_symbols: &HashSet<String>,
+    address_table: &SymbolTable,
+    warnings: &mut Vec<Warning>,
+) {
+    let predefined = SymbolTable::new();
+    let mut uses: HashMap<&String, Vec<u16>> = HashMap::new();
+    for (address, name) in symbols {
+        uses.entry(name).or_default().push(*address);
+    }
+    for (name, addresses) in uses {
+        let variable = !address_table.labels.contains(name)
+            && !address_table.constants.contains(name)
+            && !predefined.table.contains_key(name);
+        if !variable || addresses.len() != 1 || expression_symbols.contains(name) {
+            continue;
+        }
+        if let Some(similar) = similar_symbol(name, address_table) {
+            warnings.push(Warning {
+                address: addresses[0],
+                message: format!(
+                    "The variable {name} is only used once, it may be a typo of {similar}"
+                ),
+            });
+        }
+    }
+}
+
+/// Finds instructions that directly follow an unconditional jump, and have no label, so nothing
+/// can jump to them.
+fn unreachable_instructions(
+    program: &[Instruction],
+    address_table: &SymbolTable,
+    warnings: &mut Vec<Warning>,
+) {
+    let labelled: HashSet<u16> = address_table
+        .labels_by_address()
+        .iter()
+        .map(|(address, _)| *address)
+        .collect();
+    for (address, pair) in program.windows(2).enumerate() {
+        let address = address as u16 + 1;
+        if let (Instruction::C(c), next) = (&pair[0], &pair[1]) {
+            if c.jump == Jump::JMP && *next != Instruction::None && !labelled.contains(&address) {
+                warnings.push(Warning {
+                    address,
+                    message: String::from(
+                        "The instruction follows an unconditional jump, and has no label, so it can never be reached",
+                    ),
+                });
+            }
+        }
+    }
+}
+
+/// Finds programs that can run past their last instruction, since they do not end with an
+/// unconditional jump, such as the infinite loop `(END) @END 0;JMP`.
+fn missing_end_loop(program: &[Instruction], warnings: &mut Vec<Warning>) {
+    let Some(last) = program.last() else {
+        return;
+    };
+    if !matches!(last, Instruction::C(c) if c.jump == Jump::JMP) {
+        warnings.push(Warning {
+            address: program.len() as u16 - 1,
+            message: String::from(
+                "The program runs off the end of the ROM, end it with an infinite loop such as (END) @END 0;JMP",
+            ),
+        });
+    }
+}
+
+/// Finds the symbol closest to the name, if it is within a few edits of it, allowing more edits
+/// for longer names. Names shorter than [MIN_TYPO_LENGTH] are never typos.
+fn similar_symbol<'a>(name: &str, address_table: &'a SymbolTable) -> Option<&'a String> {
+    let length = name.chars().count();
+    if length < MIN_TYPO_LENGTH {
+        return None;
+    }
+    address_table
+        .table
+        .keys()
+        .filter(|symbol| *symbol != name && is_symbol(symbol))
+        .map(|symbol| (edit_distance(name, symbol), symbol))
+        .filter(|(distance, _)| *distance <= length / CHARACTERS_PER_TYPO)
+        .min()
+        .map(|(_, symbol)| symbol)
+}
+
+/// The number of single character insertions, deletions and substitutions needed to turn one
+/// string into the other.
+fn edit_distance(from: &str, to: &str) -> usize {
+    let to: Vec<char> = to.chars().collect();
+    let mut previous: Vec<usize> = (0..=to.len()).collect();
+    for (i, from_char) in from.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, to_char) in to.iter().enumerate() {
+            let substitution = previous[j] + (from_char != *to_char) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[to.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+    use crate::split_source;
+    use crate::test::on_large_stack;
+
+    /// The warnings of a program, without the warning about its missing end loop unless it is
+    /// what is being tested.
+    fn warnings(source: &str) -> Vec<String> {
+        let mut address_table = SymbolTable::new();
+        let program = parse(split_source(source).unwrap(), &mut address_table).unwrap();
+        analyze(
+            &program.instructions,
+            &program.symbols,
+            &program.expression_symbols,
+            &address_table,
+        )
+        .iter()
+        .map(|warning| warning.to_string())
+        .filter(|warning| !warning.contains("runs off the end"))
+        .collect()
+    }
+
+    /// Ends a program with an infinite loop, so that only the lint being tested warns.
+    fn ended(source: &str) -> String {
+        format!("{source}\n(END)\n@END\n0;JMP")
+    }
+
+    #[test]
+    fn memory_at_labels() {
+        on_large_stack(|| {
+            assert_eq!(
+                warnings(&ended("(LOOP)\n@LOOP\nD=M")),
+                ["ROM[1]: M is used after @LOOP, but LOOP is a label, so M is the RAM at a ROM address"]
+            );
+            assert!(warnings(&ended("(LOOP)\n@LOOP\nD=A")).is_empty());
+            assert!(warnings(&ended("@x\nD=M")).is_empty());
+        });
+    }
+
+    #[test]
+    fn jumps_without_targets() {
+        on_large_stack(|| {
+            assert_eq!(
+                warnings(&ended("D=1\nD;JGT")),
+                ["ROM[1]: The jump is not preceded by an A instruction, so it jumps to whatever is in A"]
+            );
+            assert!(warnings(&ended("@END\nD;JGT")).is_empty());
+            assert!(warnings(&ended("@R0\nA=M\nD;JGT")).is_empty());
+        });
+    }
+
+    #[test]
+    fn unused_labels() {
+        on_large_stack(|| {
+            assert_eq!(
+                warnings(&ended("(UNUSED)\nD=1")),
+                ["ROM[0]: The label UNUSED is never referenced"]
+            );
+            assert!(warnings(&ended("(LOOP)\nD=1\n@LOOP\nD;JGT")).is_empty());
+            // Labels used within expressions and constants are referenced
+            assert!(warnings(&ended("(TABLE)\nD=1\n@TABLE+1\nD=A")).is_empty());
+            assert!(warnings(&ended(".equ NEXT TABLE+1\n(TABLE)\nD=1\n@NEXT\nD=A")).is_empty());
+            assert!(warnings(&ended(".data 100: TABLE\n(TABLE)\nD=1")).is_empty());
+        });
+    }
+
+    #[test]
+    fn single_use_variables() {
+        on_large_stack(|| {
+            assert_eq!(
+                warnings(&ended("@counter\nM=1\n@counter\nM=M+1\n@countr\nM=M+1")),
+                ["ROM[4]: The variable countr is only used once, it may be a typo of counter"]
+            );
+            assert_eq!(
+                warnings(&ended("@SCRREN\nM=1")),
+                ["ROM[0]: The variable SCRREN is only used once, it may be a typo of SCREEN"]
+            );
+            // Short names are within a couple of edits of the predefined symbols
+            assert!(warnings(&ended("@i\nM=1\n@x\nM=1\n@n1\nM=1\n@SPP\nM=1")).is_empty());
+            // Longer names are allowed fewer edits for their length
+            assert!(warnings(&ended("@SCR\nM=1\n@KEYS\nM=1\n@THEM\nM=1")).is_empty());
+            assert!(warnings(&ended("@counter\nM=1\n@counter\nM=1\n@count\nM=1")).is_empty());
+            // Variables used more than once, or within an expression, are not typos
+            assert!(warnings(&ended("@SCRREN\nM=1\n@SCRREN\nM=1")).is_empty());
+            assert!(warnings(&ended("@SCRREN\nM=1\n@SCRREN+1\nM=1")).is_empty());
+        });
+    }
+
+    #[test]
+    fn unreachable_instructions() {
+        on_large_stack(|| {
+            assert_eq!(
+                warnings("(LOOP)\n@LOOP\n0;JMP\nD=1\n@LOOP\n0;JMP"),
+                ["ROM[2]: The instruction follows an unconditional jump, and has no label, so it can never be reached"]
+            );
+            assert!(warnings("(LOOP)\n@NEXT\n0;JMP\n(NEXT)\n@LOOP\n0;JMP").is_empty());
+            assert!(warnings(&ended("@END\nD;JGT\nD=1")).is_empty());
+        });
+    }
+
+    #[test]
+    fn missing_end_loop() {
+        on_large_stack(|| {
+            let mut address_table = SymbolTable::new();
+            let program = parse(split_source("@1\nD=A").unwrap(), &mut address_table).unwrap();
+            let warnings = analyze(
+                &program.instructions,
+                &program.symbols,
+                &program.expression_symbols,
+                &address_table,
+            );
+            assert_eq!(warnings.len(), 1);
+            assert_eq!(warnings[0].address, 1);
+            assert!(warnings[0].message.contains("runs off the end"));
+
+            let program = parse(split_source(&ended("@1\nD=A")).unwrap(), &mut address_table);
+            assert!(program.is_ok_and(|program| analyze(
+                &program.instructions,
+                &program.symbols,
+                &program.expression_symbols,
+                &address_table,
+            )
+            .is_empty()));
+        });
+    }
+}
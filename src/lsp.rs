@@ -0,0 +1,540 @@
+use std::collections::HashMap;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use crate::expression::{is_symbol, is_symbol_char};
+use crate::instructions::{Comp, Destination, Jump};
+use crate::parser::{split_comment, LineParsingError};
+use crate::preprocessor;
+use crate::pseudo;
+use crate::rpc::{read_message, write_message};
+use crate::split_source;
+use crate::symbol_table::SymbolTable;
+
+/// Sent for any request the server does not implement.
+const METHOD_NOT_FOUND: i64 = -32601;
+/// The `TextDocumentSyncKind` in which the whole document is sent on every change.
+const FULL_SYNC: u64 = 1;
+const SEVERITY_ERROR: u64 = 1;
+const KIND_KEYWORD: u64 = 14;
+const KIND_VARIABLE: u64 = 6;
+const KIND_REFERENCE: u64 = 18;
+const KIND_CONSTANT: u64 = 21;
+
+/// A single use or declaration of a symbol in a document.
+struct Occurrence {
+    line: usize,
+    /// The columns of the first char of the symbol, and of the one after it, in UTF-16 code units.
+    start: usize,
+    end: usize,
+    /// Whether this is a label or constant declaration, such as `(LOOP)` or `.equ SIZE 10`.
+    declaration: bool,
+}
+
+/// Serves a single editor over the Language Server Protocol, on the standard input and output.
+/// Documents are kept in memory as the editor sends them, and are checked in full on every
+/// change.
+struct LanguageServer {
+    documents: HashMap<String, String>,
+}
+
+/// Runs the language server until the editor exits.
+pub fn serve() -> io::Result<()> {
+    let mut reader = BufReader::new(io::stdin());
+    let mut server = LanguageServer {
+        documents: HashMap::new(),
+    };
+    while let Some(message) = read_message(&mut reader)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl LanguageServer {
+    /// Handles a single request or notification. Returns false once the editor has asked the
+    /// server to exit.
+    fn handle(self: &mut Self, message: &Value) -> io::Result<bool> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        match method {
+            "initialize" => self.respond(
+                message,
+                json!({
+                    "capabilities": {
+                        "textDocumentSync": FULL_SYNC,
+                        "definitionProvider": true,
+                        "referencesProvider": true,
+                        "hoverProvider": true,
+                        "completionProvider": { "triggerCharacters": ["@", "=", ";"] },
+                    },
+                    "serverInfo": { "name": "cpuemulator" },
+                }),
+            )?,
+            "shutdown" => self.respond(message, Value::Null)?,
+            "exit" => return Ok(false),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                self.update(&document["uri"], &document["text"])?;
+            }
+            "textDocument/didChange" => {
+                // Only full document synchronisation is offered, so the last change holds the
+                // whole document
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .map(|change| &change["text"])
+                    .unwrap_or(&Value::Null);
+                self.update(&params["textDocument"]["uri"], text)?;
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                self.documents.remove(uri);
+                self.publish_diagnostics(uri, vec![])?;
+            }
+            "textDocument/definition" => {
+                let result = self.definition(params);
+                self.respond(message, result)?;
+            }
+            "textDocument/references" => {
+                let result = self.references(params);
+                self.respond(message, result)?;
+            }
+            "textDocument/hover" => {
+                let result = self.hover(params);
+                self.respond(message, result)?;
+            }
+            "textDocument/completion" => {
+                let result = self.completion(params);
+                self.respond(message, result)?;
+            }
+            _ => {
+                // Notifications which are not handled are ignored, requests get an error
+                if !message["id"].is_null() {
+                    self.send(json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": {
+                            "code": METHOD_NOT_FOUND,
+                            "message": format!("Unsupported method {method}"),
+                        },
+                    }))?;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Stores the new text of a document, and reports its errors to the editor.
+    fn update(self: &mut Self, uri: &Value, text: &Value) -> io::Result<()> {
+        let (Some(uri), Some(text)) = (uri.as_str(), text.as_str()) else {
+            return Ok(());
+        };
+        let diagnostics = diagnostics(uri, text);
+        self.documents.insert(uri.to_string(), text.to_string());
+        self.publish_diagnostics(uri, diagnostics)
+    }
+
+    /// Finds the declaration of the label under the cursor. Variables have no declaration, so
+    /// their first use is given instead.
+    fn definition(self: &Self, params: &Value) -> Value {
+        let Some((uri, text, name)) = self.symbol_at(params) else {
+            return Value::Null;
+        };
+        let occurrences = occurrences(text, &name);
+        let definition = occurrences
+            .iter()
+            .find(|occurrence| occurrence.declaration)
+            .or(occurrences.first());
+        match definition {
+            Some(occurrence) => location(uri, occurrence),
+            None => Value::Null,
+        }
+    }
+
+    /// Finds every use of the symbol under the cursor, optionally including its declaration.
+    fn references(self: &Self, params: &Value) -> Value {
+        let Some((uri, text, name)) = self.symbol_at(params) else {
+            return Value::Null;
+        };
+        let include_declaration = params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(true);
+        occurrences(text, &name)
+            .iter()
+            .filter(|occurrence| include_declaration || !occurrence.declaration)
+            .map(|occurrence| location(uri, occurrence))
+            .collect()
+    }
+
+    /// Shows the address that the symbol under the cursor resolves to.
+    fn hover(self: &Self, params: &Value) -> Value {
+        let Some((uri, text, name)) = self.symbol_at(params) else {
+            return Value::Null;
+        };
+        let address_table = symbols(uri, text);
+        let Some(description) = address_table.describe(&name) else {
+            return Value::Null;
+        };
+        let value = if address_table.labels.contains(&name) {
+            format!("`{name}` label: {description}")
+        } else if address_table.constants.contains(&name) {
+            format!("`{name}` constant: {description}")
+        } else {
+            format!("`{name}`: {description}")
+        };
+        json!({ "contents": { "kind": "markdown", "value": value } })
+    }
+
+    /// Offers the symbols of the document after an `@`, and otherwise the mnemonics which are
+    /// valid at the cursor.
+    fn completion(self: &Self, params: &Value) -> Value {
+        let Some((uri, text, line, character)) = self.position(params) else {
+            return Value::Null;
+        };
+        let before_cursor: String = text
+            .lines()
+            .nth(line)
+            .unwrap_or("")
+            .chars()
+            .take(character)
+            .collect();
+        let before_cursor = before_cursor.trim_start();
+
+        let mnemonics = |mnemonics: &[&'static str], detail: &'static str| {
+            mnemonics
+                .iter()
+                .map(
+                    |mnemonic| json!({ "label": mnemonic, "kind": KIND_KEYWORD, "detail": detail }),
+                )
+                .collect::<Vec<Value>>()
+        };
+        let items: Vec<Value> = if before_cursor.starts_with('@') {
+            let address_table = symbols(uri, text);
+            let mut names: Vec<&String> = address_table.table.keys().collect();
+            names.sort();
+            names
+                .into_iter()
+                .map(|name| {
+                    let kind = if address_table.labels.contains(name) {
+                        KIND_REFERENCE
+                    } else if address_table.constants.contains(name) {
+                        KIND_CONSTANT
+                    } else {
+                        KIND_VARIABLE
+                    };
+                    json!({ "label": name, "kind": kind, "detail": address_table.describe(name) })
+                })
+                .collect()
+        } else if before_cursor.contains(';') {
+            mnemonics(&Jump::MNEMONICS, "jump")
+        } else if before_cursor.contains('=') {
+            mnemonics(&Comp::MNEMONICS, "computation")
+        } else {
+            let mut items = mnemonics(&Destination::MNEMONICS, "destination");
+            items.extend(mnemonics(&Comp::MNEMONICS, "computation"));
+            items
+        };
+        Value::Array(items)
+    }
+
+    /// The document and cursor position of a request. The character is counted in chars, rather
+    /// than in the UTF-16 code units of the protocol.
+    fn position<'a>(self: &'a Self, params: &Value) -> Option<(&'a str, &'a str, usize, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let (uri, text) = self.documents.get_key_value(uri)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        let character = char_column(text.lines().nth(line).unwrap_or(""), character);
+        Some((uri, text, line, character))
+    }
+
+    /// The document of a request, along with the symbol under the cursor.
+    fn symbol_at<'a>(self: &'a Self, params: &Value) -> Option<(&'a str, &'a str, String)> {
+        let (uri, text, line, character) = self.position(params)?;
+        let chars: Vec<char> = split_comment(text.lines().nth(line)?).0.chars().collect();
+        let character = character.min(chars.len());
+        let start = chars[..character]
+            .iter()
+            .rposition(|c| !is_symbol_char(*c))
+            .map_or(0, |i| i + 1);
+        let end = chars[character..]
+            .iter()
+            .position(|c| !is_symbol_char(*c))
+            .map_or(chars.len(), |i| character + i);
+        let name: String = chars[start..end].iter().collect();
+        if is_symbol(&name) {
+            Some((uri, text, name))
+        } else {
+            None
+        }
+    }
+
+    fn publish_diagnostics(self: &Self, uri: &str, diagnostics: Vec<Value>) -> io::Result<()> {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn respond(self: &Self, request: &Value, result: Value) -> io::Result<()> {
+        self.send(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+    }
+
+    fn send(self: &Self, message: Value) -> io::Result<()> {
+        write_message(&mut io::stdout().lock(), &message)
+    }
+}
+
+/// Checks a document, producing a diagnostic covering the whole line of every error.
+fn diagnostics(uri: &str, text: &str) -> Vec<Value> {
+    let lines = match split_source(text) {
+        Ok(lines) => lines,
+        Err(e) => return vec![diagnostic(text, 0, &e)],
+    };
+    preprocessor::check(&lines, &uri_path(uri), &mut SymbolTable::new())
+        .iter()
+        .map(|error| match error {
+            LineParsingError::InvalidLine(line_number, _, reason) => {
+                diagnostic(text, *line_number as usize - 1, reason)
+            }
+        })
+        .collect()
+}
+
+fn diagnostic(text: &str, line: usize, message: &str) -> Value {
+    let length = text
+        .lines()
+        .nth(line)
+        .map_or(0, |l| l.encode_utf16().count());
+    json!({
+        "range": {
+            "start": { "line": line, "character": 0 },
+            "end": { "line": line, "character": length },
+        },
+        "severity": SEVERITY_ERROR,
+        "source": "cpuemulator",
+        "message": message,
+    })
+}
+
+/// Resolves the symbols of a document, including the labels and variables it declares.
+fn symbols(uri: &str, text: &str) -> SymbolTable {
+    let mut address_table = SymbolTable::new();
+    if let Ok(lines) = split_source(text) {
+        preprocessor::check(&lines, &uri_path(uri), &mut address_table);
+    }
+    address_table
+}
+
+/// The path of the file behind a document, used to find the files it includes. Documents which
+/// are not files are treated as if they were in the current directory.
+fn uri_path(uri: &str) -> PathBuf {
+    match uri.strip_prefix("file://") {
+        Some(path) => PathBuf::from(percent_decode(path)),
+        None => PathBuf::from(uri.rsplit('/').next().unwrap_or(uri)),
+    }
+}
+
+/// Decodes the `%XX` escapes of a URI, such as `%20` for a space, and `%C3%A9` for an `é`. Anything
+/// which is not a valid escape is left as it is.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match (bytes[i], text.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                u8::from_str_radix(hex, 16).ok()
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Converts a column counted in chars to one counted in UTF-16 code units, as the protocol
+/// expects.
+fn utf16_column(line: &str, column: usize) -> usize {
+    line.chars().take(column).map(char::len_utf16).sum()
+}
+
+/// Converts a column counted in UTF-16 code units to one counted in chars. A column within a
+/// char, or past the end of the line, is moved to the end of that char, or line.
+fn char_column(line: &str, column: usize) -> usize {
+    let mut units = 0;
+    line.chars()
+        .take_while(|c| {
+            let before = units;
+            units += c.len_utf16();
+            before < column
+        })
+        .count()
+}
+
+/// Finds every declaration of the name, as a label or a constant, and every use of it in an A
+/// instruction or a directive, in a document.
+fn occurrences(text: &str, name: &str) -> Vec<Occurrence> {
+    let mut occurrences = vec![];
+    for (line, source) in text.lines().enumerate() {
+        let (code, _) = split_comment(source);
+        let trimmed = code.trim();
+        let label = trimmed.starts_with('(') && trimmed.ends_with(')');
+        let constant = trimmed.starts_with(".equ ");
+        let pseudo_instruction = pseudo::expand(trimmed).is_some();
+        if !label && !trimmed.starts_with('@') && !trimmed.starts_with('.') && !pseudo_instruction {
+            continue;
+        }
+        // A instructions, directives and pseudo-instructions may hold constant expressions, so
+        // every symbol in them is checked, while skipping over character literals. Positions are
+        // counted in chars, and reported in UTF-16 code units.
+        let chars: Vec<char> = code.chars().collect();
+        let mut symbols = 0;
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == '\'' {
+                i += chars[i + 1..]
+                    .iter()
+                    .position(|c| *c == '\'')
+                    .map_or(chars.len(), |end| end + 2);
+                continue;
+            }
+            let length = chars[i..]
+                .iter()
+                .take_while(|c| is_symbol_char(**c))
+                .count();
+            if length == 0 {
+                i += 1;
+                continue;
+            }
+            if chars[i..i + length].iter().copied().eq(name.chars()) {
+                occurrences.push(Occurrence {
+                    line,
+                    start: utf16_column(code, i),
+                    end: utf16_column(code, i + length),
+                    // The name of a constant follows the directive
+                    declaration: label || (constant && symbols == 1),
+                });
+            }
+            symbols += 1;
+            i += length;
+        }
+    }
+    occurrences
+}
+
+fn location(uri: &str, occurrence: &Occurrence) -> Value {
+    json!({
+        "uri": uri,
+        "range": {
+            "start": { "line": occurrence.line, "character": occurrence.start },
+            "end": { "line": occurrence.line, "character": occurrence.end },
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const URI: &'static str = "file:///programs/Main.asm";
+
+    /// The line, columns and kind of each occurrence of the name.
+    fn found(text: &str, name: &str) -> Vec<(usize, usize, usize, bool)> {
+        occurrences(text, name)
+            .iter()
+            .map(|o| (o.line, o.start, o.end, o.declaration))
+            .collect()
+    }
+
+    /// The symbol at a position of a document, with the character in UTF-16 code units.
+    fn symbol_at(text: &str, line: usize, character: usize) -> Option<String> {
+        let server = LanguageServer {
+            documents: HashMap::from([(String::from(URI), String::from(text))]),
+        };
+        let params = json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+        });
+        server.symbol_at(&params).map(|(_, _, name)| name)
+    }
+
+    #[test]
+    fn occurrences_of_labels_and_constants() {
+        let text =
+            "(LOOP)\n@LOOP // LOOP in a comment\n.equ SIZE LOOP+1\n@'L'\nD=A\nJMP LOOP\n@LOOPS";
+        assert_eq!(
+            found(text, "LOOP"),
+            [
+                (0, 1, 5, true),
+                (1, 1, 5, false),
+                (2, 10, 14, false),
+                (5, 4, 8, false)
+            ]
+        );
+        assert_eq!(found(text, "SIZE"), [(2, 5, 9, true)]);
+        assert!(found(text, "L").is_empty());
+    }
+
+    #[test]
+    fn positions_are_in_utf16_code_units() {
+        // The emoji takes up two UTF-16 code units, but is a single char
+        let text = "@'\u{1F600}'+LOOP";
+        assert_eq!(found(text, "LOOP"), [(0, 6, 10, false)]);
+        assert_eq!(symbol_at(text, 0, 7).as_deref(), Some("LOOP"));
+        assert_eq!(diagnostic(text, 0, "")["range"]["end"]["character"], 10);
+        assert_eq!(char_column(text, 3), 3);
+        assert_eq!(char_column(text, 100), 9);
+    }
+
+    #[test]
+    fn symbols_under_the_cursor() {
+        let text = "@LOOP+SIZE // SIZE\n(LOOP)";
+        assert_eq!(symbol_at(text, 0, 3).as_deref(), Some("LOOP"));
+        assert_eq!(symbol_at(text, 0, 5).as_deref(), Some("LOOP"));
+        assert_eq!(symbol_at(text, 0, 7).as_deref(), Some("SIZE"));
+        assert_eq!(symbol_at(text, 1, 1).as_deref(), Some("LOOP"));
+        assert_eq!(symbol_at(text, 0, 0), None);
+        // Comments are not searched
+        assert_eq!(symbol_at(text, 0, 15), None);
+        assert_eq!(symbol_at(text, 2, 0), None);
+    }
+
+    #[test]
+    fn uri_paths_are_decoded() {
+        assert_eq!(
+            uri_path("file:///home/me/My%20Programs/Caf%C3%A9.asm"),
+            PathBuf::from("/home/me/My Programs/Caf\u{e9}.asm")
+        );
+        assert_eq!(
+            uri_path("file:///tmp/100%25%5b1%5D.asm"),
+            PathBuf::from("/tmp/100%[1].asm")
+        );
+        assert_eq!(
+            uri_path("file:///tmp/50%.asm"),
+            PathBuf::from("/tmp/50%.asm")
+        );
+        assert_eq!(
+            uri_path("file:///tmp/%zz.asm"),
+            PathBuf::from("/tmp/%zz.asm")
+        );
+        assert_eq!(
+            uri_path("untitled:Untitled-1"),
+            PathBuf::from("untitled:Untitled-1")
+        );
+    }
+}
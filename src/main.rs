@@ -1,18 +1,46 @@
 use crate::hack_cpu::CPUState;
 use crate::hack_gui::HackGUI;
-use core::panic;
 use instructions::Instruction;
 use parser::MAX_INSTRUCTIONS;
-use std::{env, fs, path::PathBuf, usize};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    usize,
+};
 mod instructions;
 mod parser;
 mod symbol_table;
 use glium::backend::Facade;
 
+mod call_stack;
+mod cfg;
+mod coverage;
+mod dap;
 mod debug;
+mod diff;
+mod editor;
+mod expression;
+mod formatter;
+mod gdbstub;
+mod golden;
 mod hack_cpu;
 mod hack_gui;
+mod headless;
+mod lint;
+mod lsp;
+mod memory_activity;
+mod memory_map;
+mod monitor;
+mod navigation;
+mod number_format;
+mod preprocessor;
+mod profiler;
+mod pseudo;
+mod rpc;
+mod screen_capture;
 mod support;
+mod test_script;
+mod trace;
 
 const ASM_FILE_EXTENSION: &'static str = "asm";
 const SCREEN_WIDTH: usize = 512;
@@ -22,24 +50,33 @@ const SCREEN_LOCATION: usize = 16384;
 const SCREEN_LENGTH: usize = 8192;
 const KBD_LOCATION: usize = 24576;
 const INSTRUCTIONS_PER_REFRESH: usize = 200_000;
+const HEADLESS_STACK_SIZE: usize = 256 * 1024 * 1024;
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 {
+        // The program is parsed into arrays of MAX_INSTRUCTIONS entries, which are too large for
+        // the default stack of the main thread in debug builds.
+        let result = std::thread::Builder::new()
+            .stack_size(HEADLESS_STACK_SIZE)
+            .spawn(move || headless::run(&args[1..]))
+            .expect("Failed to start the headless runner")
+            .join()
+            .expect("The headless runner panicked");
+        if let Err(e) = result {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let state = CPUState::new();
     let instructions = [const { Instruction::None }; MAX_INSTRUCTIONS];
 
-    let num_labels = instructions
-        .iter()
-        .filter(|&x| match x {
-            Instruction::Label(_) => true,
-            _ => false,
-        })
-        .count();
-
     let cpu_display = std::rc::Rc::new(std::cell::RefCell::new(HackGUI::new(
         None,
         state,
         instructions,
-        num_labels,
     )));
     let cpu_display_clone = cpu_display.clone();
 
@@ -57,42 +94,43 @@ fn main() {
     );
 }
 
-fn read_arg_file() -> [String; MAX_INSTRUCTIONS] {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("Invalid usage, please use: cpuemulator <input path>")
+/// Reads the source code of a program from an asm file, one line per array entry.
+fn read_source_file(path: &Path) -> Result<[String; MAX_INSTRUCTIONS], String> {
+    let input_path: PathBuf =
+        fs::canonicalize(path).map_err(|e| format!("Invalid path {}: {e}", path.display()))?;
+    if input_path.is_dir() {
+        return Err(String::from("Directories are not supported"));
     }
-    let argument_path = env::args().nth(1).expect("No path provided");
-    let argument_path = fs::canonicalize(&argument_path).expect("Invalid path provided");
-    let input_path: PathBuf = if argument_path.is_dir() {
-        panic!("Directories are not supported");
-    } else {
-        argument_path
-    };
 
     if let Some(extension) = input_path.extension() {
         if extension.to_str().unwrap_or("").to_lowercase() != ASM_FILE_EXTENSION {
-            panic!("Expected asm file, got {}", extension.to_str().unwrap());
+            return Err(format!(
+                "Expected asm file, got {}",
+                extension.to_str().unwrap_or("")
+            ));
         }
     }
 
-    let input_path = PathBuf::from(input_path);
+    let contents: String = fs::read_to_string(&input_path)
+        .map_err(|e| format!("Failed to read {}: {e}", input_path.display()))?;
+    split_source(&contents)
+}
 
-    let contents: String =
-        fs::read_to_string(input_path).expect("Should have been able to read file");
+/// Splits the contents of an asm file into its lines, one line per array entry.
+fn split_source(contents: &str) -> Result<[String; MAX_INSTRUCTIONS], String> {
     let instructions: Vec<String> = contents.split("\n").map(|s| s.trim().to_string()).collect();
     if instructions.len() > MAX_INSTRUCTIONS {
-        panic!(
+        return Err(format!(
             "Too many instructions, expected a maximum of {}, got {}",
             MAX_INSTRUCTIONS,
             instructions.len()
-        );
+        ));
     }
     let mut ret: [String; MAX_INSTRUCTIONS] = [const { String::new() }; MAX_INSTRUCTIONS];
     for (i, instruction) in instructions.iter().enumerate() {
         ret[i] = instruction.to_string();
     }
-    ret
+    Ok(ret)
 }
 
 #[cfg(test)]
@@ -101,32 +139,62 @@ mod test {
     use crate::hack_gui::hack_to_rgba;
     use std::num::Wrapping;
 
+    /// Runs a test on a thread with the stack of the headless runner, as programs are parsed into
+    /// arrays of MAX_INSTRUCTIONS entries, which are too large for the default stack of a test.
+    pub fn on_large_stack(test: fn()) {
+        std::thread::Builder::new()
+            .stack_size(HEADLESS_STACK_SIZE)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
     #[test]
     fn truth_itself() {
         assert_eq!("Veer Gala woz 'ere", "Veer Gala woz 'ere");
     }
 
+    /// Fills the screen with black, one word at a time, forever.
+    const FILL: &'static str = "(LOOP)
+    @SCREEN
+    D=A
+    @i
+    M=D
+(FILL)
+    @i
+    A=M
+    M=-1
+    @i
+    MD=M+1
+    @KBD
+    D=D-A
+    @FILL
+    D;JLT
+    @LOOP
+    0;JMP";
+
     #[test]
     fn test_speed() {
-        let contents: String =
-            fs::read_to_string("asm/AutoFill.asm").expect("Should have been able to read file");
-        let instructions: Vec<String> =
-            contents.split("\n").map(|s| s.trim().to_string()).collect();
-        let mut s_instructions: [String; MAX_INSTRUCTIONS] =
-            [const { String::new() }; MAX_INSTRUCTIONS];
-        for (i, instruction) in instructions.iter().enumerate() {
-            s_instructions[i] = instruction.to_string();
-        }
-        let mut cpu = CPUState::new();
-        let instructions = parse(s_instructions, &mut cpu.address_table);
+        on_large_stack(|| {
+            let lines = split_source(FILL).unwrap();
+            let mut cpu = CPUState::new();
+            let instructions =
+                preprocessor::assemble(&lines, Path::new("Fill.asm"), &mut cpu.address_table)
+                    .unwrap()
+                    .instructions;
 
-        for _ in 0..1000000000 {
-            cpu.interpret(&instructions[cpu.pc as usize]);
-            hack_to_rgba(&cpu.ram[SCREEN_LOCATION..SCREEN_LOCATION + SCREEN_LENGTH]);
-        }
+            // Filling the screen takes 9 instructions for each of its words
+            for cycle in 0..SCREEN_LENGTH * 10 {
+                cpu.interpret(&instructions[cpu.pc as usize]);
+                if cycle % INSTRUCTIONS_PER_REFRESH == 0 {
+                    hack_to_rgba(&cpu.ram[SCREEN_LOCATION..SCREEN_LOCATION + SCREEN_LENGTH]);
+                }
+            }
 
-        for i in SCREEN_LOCATION..SCREEN_LOCATION + SCREEN_LENGTH {
-            assert_eq!(cpu.ram[i], Wrapping(-1))
-        }
+            for i in SCREEN_LOCATION..SCREEN_LOCATION + SCREEN_LENGTH {
+                assert_eq!(cpu.ram[i], Wrapping(-1))
+            }
+        });
     }
 }
@@ -0,0 +1,51 @@
+use crate::instructions::Instruction;
+use crate::parser::MAX_RAM;
+
+/// Counts how many times each RAM address has been read and written. Counting is opt in, by
+/// placing a [MemoryActivity] into the [crate::hack_cpu::CPUState].
+#[derive(Debug)]
+pub struct MemoryActivity {
+    pub reads: Vec<u64>,
+    pub writes: Vec<u64>,
+}
+
+impl MemoryActivity {
+    pub fn new() -> Self {
+        Self {
+            reads: vec![0; MAX_RAM],
+            writes: vec![0; MAX_RAM],
+        }
+    }
+
+    /// Records the accesses the instruction makes to the RAM at `address`, which is the value of
+    /// A before the instruction runs.
+    pub fn record(self: &mut Self, instruction: &Instruction, address: u16) {
+        let Instruction::C(c) = instruction else {
+            return;
+        };
+        let address = address as usize;
+        if c.comp.reads_memory() {
+            if let Some(count) = self.reads.get_mut(address) {
+                *count += 1;
+            }
+        }
+        if c.dest.writes_memory() {
+            if let Some(count) = self.writes.get_mut(address) {
+                *count += 1;
+            }
+        }
+    }
+
+    /// The number of reads and writes of the address.
+    pub fn accesses(self: &Self, address: usize) -> u64 {
+        self.reads[address] + self.writes[address]
+    }
+
+    /// The highest number of reads and writes of any single address.
+    pub fn max(self: &Self) -> u64 {
+        (0..MAX_RAM)
+            .map(|address| self.accesses(address))
+            .max()
+            .unwrap_or(0)
+    }
+}
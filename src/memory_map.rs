@@ -0,0 +1,125 @@
+use std::num::Wrapping;
+use std::ops::RangeInclusive;
+
+use crate::{KBD_LOCATION, SCREEN_LENGTH, SCREEN_LOCATION};
+
+/// The address of the stack pointer, which holds the address after the top of the stack.
+pub const STACK_POINTER: usize = 0;
+/// The addresses of the pointers to the `local` and `argument` segments of the current function.
+pub const LOCAL_POINTER: usize = 1;
+pub const ARGUMENT_POINTER: usize = 2;
+/// The addresses of the pointers to the `this` and `that` segments, which may point anywhere.
+pub const THIS_POINTER: usize = 3;
+pub const THAT_POINTER: usize = 4;
+/// The address of the bottom of the stack.
+pub const STACK_BASE: usize = 256;
+/// The address of the last word the stack can grow into.
+pub const STACK_END: usize = 2047;
+/// The first of the registers that the VM uses as its `temp` segment.
+const TEMP_BASE: usize = 5;
+const TEMP_LENGTH: usize = 8;
+
+/// A named range of the RAM, as it is laid out by the VM translator of the course.
+pub struct Region {
+    pub name: &'static str,
+    pub addresses: RangeInclusive<usize>,
+}
+
+/// The regions of the RAM, in order of address.
+pub const REGIONS: [Region; 6] = [
+    Region {
+        name: "Registers",
+        addresses: 0..=15,
+    },
+    Region {
+        name: "Static",
+        addresses: 16..=255,
+    },
+    Region {
+        name: "Stack",
+        addresses: STACK_BASE..=STACK_END,
+    },
+    Region {
+        name: "Heap",
+        addresses: STACK_END + 1..=SCREEN_LOCATION - 1,
+    },
+    Region {
+        name: "Screen",
+        addresses: SCREEN_LOCATION..=SCREEN_LOCATION + SCREEN_LENGTH - 1,
+    },
+    Region {
+        name: "Keyboard",
+        addresses: KBD_LOCATION..=KBD_LOCATION,
+    },
+];
+
+/// A segment of the VM, which starts at the address held by its `pointer` register.
+pub struct Segment {
+    /// The name of the segment in VM code, such as `local` in `push local 0`.
+    pub name: &'static str,
+    /// The name of the register holding the base address of the segment.
+    pub pointer_name: &'static str,
+    pub pointer: usize,
+}
+
+/// The segments of the VM that are found through pointers, rather than at fixed addresses.
+pub const SEGMENTS: [Segment; 4] = [
+    Segment {
+        name: "local",
+        pointer_name: "LCL",
+        pointer: LOCAL_POINTER,
+    },
+    Segment {
+        name: "argument",
+        pointer_name: "ARG",
+        pointer: ARGUMENT_POINTER,
+    },
+    Segment {
+        name: "this",
+        pointer_name: "THIS",
+        pointer: THIS_POINTER,
+    },
+    Segment {
+        name: "that",
+        pointer_name: "THAT",
+        pointer: THAT_POINTER,
+    },
+];
+
+/// Finds the region of the RAM holding the address.
+pub fn region(address: usize) -> Option<&'static Region> {
+    REGIONS
+        .iter()
+        .find(|region| region.addresses.contains(&address))
+}
+
+/// Names the register at the address by its use in the VM, such as `SP`, `LCL` or `temp 3`.
+/// Registers without a particular use are named `R13` to `R15`.
+pub fn register_name(address: usize) -> Option<String> {
+    if address == STACK_POINTER {
+        return Some(String::from("SP"));
+    }
+    if let Some(segment) = SEGMENTS.iter().find(|segment| segment.pointer == address) {
+        return Some(String::from(segment.pointer_name));
+    }
+    if (TEMP_BASE..TEMP_BASE + TEMP_LENGTH).contains(&address) {
+        return Some(format!("temp {}", address - TEMP_BASE));
+    }
+    REGIONS[0]
+        .addresses
+        .contains(&address)
+        .then(|| format!("R{address}"))
+}
+
+/// Names the pointer registers, `SP` and those of the [SEGMENTS], that hold the address.
+pub fn pointers_to(ram: &[Wrapping<i16>], address: usize) -> Vec<&'static str> {
+    let stack_pointer = ("SP", STACK_POINTER);
+    let segment_pointers = SEGMENTS
+        .iter()
+        .map(|segment| (segment.pointer_name, segment.pointer));
+    std::iter::once(stack_pointer)
+        .chain(segment_pointers)
+        .filter(|(_, pointer)| ram[*pointer].0 as u16 as usize == address)
+        .map(|(name, _)| name)
+        .collect()
+}
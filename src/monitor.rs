@@ -0,0 +1,449 @@
+use std::fmt::Write;
+use std::num::Wrapping;
+use std::path::Path;
+
+use crate::debug::Breakpoint;
+use crate::expression::{evaluate, is_symbol};
+use crate::hack_cpu::CPUState;
+use crate::instructions::Instruction;
+use crate::parser::{parse_line, MAX_RAM};
+use crate::preprocessor;
+use crate::{read_source_file, MAX_INSTRUCTIONS};
+
+/// The most instructions a single `run` executes, so that a program which never reaches a
+/// breakpoint does not lock up the monitor.
+const RUN_LIMIT: u64 = 10_000_000;
+
+pub const HELP: &'static str = "Commands:
+    step [N]                Execute N instructions (default 1), stopping at breakpoints
+    run [until CONDITION]   Run until a breakpoint, the condition, or the end of the ROM
+    x[/N] LOCATION          Examine N words (default 1) from RAM[ADDRESS] or ROM[ADDRESS]
+    set TARGET VALUE        Set A, D, PC or RAM[ADDRESS] to a value
+    asm INSTRUCTION         Execute an instruction immediately, without advancing the PC
+    break CONDITION         Add a breakpoint
+    delete CONDITION        Remove a breakpoint
+    info registers|symbols|breakpoints
+    load FILE               Load a program, and set the PC back to 0
+    reset                   Set the PC back to 0
+    help                    Show this message
+
+Addresses and values may be numbers, symbols, or constant expressions such as SCREEN+32. A
+condition is one of A=VALUE, D=VALUE, PC=ADDRESS or RAM[ADDRESS]=VALUE, and a bare address
+such as LOOP is short for PC=LOOP.";
+
+/// A part of the machine that can be read or written by monitor commands.
+enum Location {
+    A,
+    D,
+    PC,
+    RAM(u16),
+    ROM(u16),
+}
+
+/// Executes a single monitor command against the CPU and the program in its ROM, returning the
+/// output to show the user.
+pub fn execute(
+    command: &str,
+    cpu: &mut CPUState,
+    instructions: &mut [Instruction; MAX_INSTRUCTIONS],
+) -> Result<String, String> {
+    let command = command.trim();
+    let (name, args) = match command.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (command, ""),
+    };
+    let (name, count) = match name.split_once('/') {
+        Some((name, count)) => (name, Some(count)),
+        None => (name, None),
+    };
+    match name {
+        "" => Ok(String::new()),
+        "step" | "s" => {
+            let steps = match args {
+                "" => 1,
+                steps => parse_count(steps)?,
+            };
+            Ok(step(cpu, instructions, steps))
+        }
+        "run" | "r" | "continue" | "c" => {
+            let condition = match args.strip_prefix("until") {
+                Some(condition) => Some(parse_condition(condition.trim(), cpu)?),
+                None if args.is_empty() => None,
+                None => return Err(format!("Expected run [until CONDITION], got run {args}")),
+            };
+            Ok(run(cpu, instructions, condition))
+        }
+        "x" => {
+            let count = match count {
+                Some(count) => parse_count(count)?,
+                None => 1,
+            };
+            examine(cpu, instructions, parse_location(args, cpu)?, count)
+        }
+        "set" => {
+            let (target, value) = args
+                .split_once(['=', ' '])
+                .ok_or(format!("Expected set TARGET VALUE, got set {args}"))?;
+            let value = parse_value(value.trim().trim_start_matches('=').trim(), cpu)?;
+            match parse_location(target.trim(), cpu)? {
+                Location::A => cpu.a = Wrapping(value),
+                Location::D => cpu.d = Wrapping(value),
+                Location::PC => cpu.pc = value as u16,
+                Location::RAM(address) => cpu.ram[address as usize] = Wrapping(value),
+                Location::ROM(_) => return Err(String::from("The ROM cannot be set")),
+            }
+            Ok(registers(cpu, instructions))
+        }
+        "asm" => {
+            // Parsing against a copy of the symbols keeps unknown names from becoming variables
+            let mut address_table = cpu.address_table.clone();
+            let instruction = parse_line(args, &mut address_table)?;
+            let unknown = address_table.table.keys().find(|name| {
+                is_symbol(name) && !cpu.address_table.table.contains_key(name.as_str())
+            });
+            if let Some(name) = unknown {
+                return Err(format!("Unknown symbol {name}"));
+            }
+            let pc = cpu.pc;
+            // The instruction is not in the ROM, so it is not recorded against the PC, which only
+            // a jump moves
+            if !cpu.execute(&instruction) {
+                cpu.pc = pc;
+            }
+            Ok(registers(cpu, instructions))
+        }
+        "break" | "b" => {
+            let breakpoint = parse_condition(args, cpu)?;
+            cpu.breakpoints.insert(breakpoint);
+            Ok(format!("Breakpoint at {breakpoint}"))
+        }
+        "delete" | "d" => {
+            let breakpoint = parse_condition(args, cpu)?;
+            if cpu.breakpoints.remove(&breakpoint) {
+                Ok(format!("Deleted breakpoint at {breakpoint}"))
+            } else {
+                Err(format!("No breakpoint at {breakpoint}"))
+            }
+        }
+        "info" | "i" => match args {
+            "registers" | "r" => Ok(registers(cpu, instructions)),
+            "symbols" | "s" => Ok(symbols(cpu)),
+            "breakpoints" | "b" => Ok(breakpoints(cpu)),
+            _ => Err(String::from(
+                "Expected info registers, symbols, or breakpoints",
+            )),
+        },
+        "load" => {
+            let lines = read_source_file(Path::new(args))?;
+            cpu.reset_address_table();
+            let program = preprocessor::assemble(&lines, Path::new(args), &mut cpu.address_table)
+                .map_err(|e| e.to_string())?;
+            cpu.load_data(&program.data);
+            *instructions = program.instructions;
+            cpu.pc = 0;
+            Ok(format!("Loaded {args}"))
+        }
+        "reset" => {
+            cpu.pc = 0;
+            Ok(registers(cpu, instructions))
+        }
+        "help" | "h" => Ok(String::from(HELP)),
+        _ => Err(format!("Unknown command {name}, try help")),
+    }
+}
+
+fn halted(cpu: &CPUState) -> bool {
+    cpu.pc as usize >= MAX_INSTRUCTIONS
+}
+
+/// Executes up to `steps` instructions, stopping early at a breakpoint.
+fn step(cpu: &mut CPUState, instructions: &[Instruction; MAX_INSTRUCTIONS], steps: u64) -> String {
+    for _ in 0..steps {
+        if halted(cpu) {
+            break;
+        }
+        cpu.interpret(&instructions[cpu.pc as usize]);
+        if cpu.hit_breakpoint() {
+            return format!("Stopped at a breakpoint\n{}", registers(cpu, instructions));
+        }
+    }
+    registers(cpu, instructions)
+}
+
+/// Runs the program until it hits a breakpoint, meets the condition, or leaves the ROM.
+fn run(
+    cpu: &mut CPUState,
+    instructions: &[Instruction; MAX_INSTRUCTIONS],
+    condition: Option<Breakpoint>,
+) -> String {
+    for cycle in 0..RUN_LIMIT {
+        if halted(cpu) {
+            return format!(
+                "The program left the ROM after {cycle} instructions\n{}",
+                registers(cpu, instructions)
+            );
+        }
+        cpu.interpret(&instructions[cpu.pc as usize]);
+        if condition.is_some_and(|condition| condition.hit(cpu)) || cpu.hit_breakpoint() {
+            return format!(
+                "Stopped after {} instructions\n{}",
+                cycle + 1,
+                registers(cpu, instructions)
+            );
+        }
+    }
+    format!(
+        "Still running after {RUN_LIMIT} instructions\n{}",
+        registers(cpu, instructions)
+    )
+}
+
+/// Lists `count` words of memory, starting from the location.
+fn examine(
+    cpu: &CPUState,
+    instructions: &[Instruction; MAX_INSTRUCTIONS],
+    location: Location,
+    count: u64,
+) -> Result<String, String> {
+    let mut output = String::new();
+    match location {
+        Location::RAM(address) => {
+            for address in (address as usize..MAX_RAM).take(count as usize) {
+                let _ = writeln!(output, "RAM[{address}]: {}", cpu.ram[address]);
+            }
+        }
+        Location::ROM(address) => {
+            let labels = cpu.address_table.labels_by_address();
+            for address in (address as usize..MAX_INSTRUCTIONS).take(count as usize) {
+                for (_, label) in labels.iter().filter(|(a, _)| *a as usize == address) {
+                    let _ = writeln!(output, "({label})");
+                }
+                let _ = writeln!(output, "ROM[{address}]: {}", instructions[address]);
+            }
+        }
+        _ => return Err(String::from("Only RAM and ROM can be examined")),
+    }
+    Ok(output.trim_end().to_string())
+}
+
+/// Describes the registers, along with the next instruction to be executed.
+fn registers(cpu: &CPUState, instructions: &[Instruction; MAX_INSTRUCTIONS]) -> String {
+    let next = match instructions.get(cpu.pc as usize) {
+        Some(instruction) => instruction.to_string(),
+        None => String::from("(halted)"),
+    };
+    format!("PC: {}  A: {}  D: {}  next: {next}", cpu.pc, cpu.a, cpu.d)
+}
+
+/// Lists the symbols of the program, with the labels first, then the constants, then the
+/// variables, each sorted by address.
+fn symbols(cpu: &CPUState) -> String {
+    let address_table = &cpu.address_table;
+    let kind = |name: &String| {
+        if address_table.labels.contains(name) {
+            0
+        } else if address_table.constants.contains(name) {
+            1
+        } else {
+            2
+        }
+    };
+    let mut symbols: Vec<(u8, u16, &String)> = address_table
+        .table
+        .iter()
+        .map(|(name, address)| (kind(name), *address, name))
+        .collect();
+    symbols.sort();
+    symbols
+        .iter()
+        .filter_map(|(_, _, name)| {
+            let description = address_table.describe(name)?;
+            Some(format!("{name:<24}{description}"))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn breakpoints(cpu: &CPUState) -> String {
+    if cpu.breakpoints.is_empty() {
+        return String::from("No breakpoints");
+    }
+    let mut breakpoints: Vec<String> = cpu.breakpoints.iter().map(|b| b.to_string()).collect();
+    breakpoints.sort();
+    breakpoints.join("\n")
+}
+
+/// Parses a condition such as `A=5` or `RAM[i]=0` into a [Breakpoint]. A bare address is a
+/// condition on the PC.
+pub fn parse_condition(condition: &str, cpu: &CPUState) -> Result<Breakpoint, String> {
+    let Some((target, value)) = condition.split_once('=') else {
+        return Ok(Breakpoint::PC(parse_value(condition, cpu)? as u16));
+    };
+    let value = parse_value(value.trim(), cpu)?;
+    match parse_location(target.trim(), cpu)? {
+        Location::A => Ok(Breakpoint::A(value)),
+        Location::D => Ok(Breakpoint::D(value)),
+        Location::PC => Ok(Breakpoint::PC(value as u16)),
+        Location::RAM(address) => Ok(Breakpoint::RAM(address, value)),
+        Location::ROM(_) => Err(String::from("Breakpoints cannot be set on the ROM")),
+    }
+}
+
+/// Parses a register, or a memory location such as `RAM[256]` or `ROM[LOOP]`. A bare address
+/// refers to the RAM.
+fn parse_location(location: &str, cpu: &CPUState) -> Result<Location, String> {
+    let indexed = |prefix: &str| {
+        location
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix('['))
+            .and_then(|rest| rest.strip_suffix(']'))
+    };
+    let location = match location {
+        "A" => Location::A,
+        "D" => Location::D,
+        "PC" => Location::PC,
+        _ => match (indexed("RAM"), indexed("ROM")) {
+            (Some(address), _) => Location::RAM(parse_address(address, cpu, MAX_RAM)?),
+            (_, Some(address)) => Location::ROM(parse_address(address, cpu, MAX_INSTRUCTIONS)?),
+            _ => Location::RAM(parse_address(location, cpu, MAX_RAM)?),
+        },
+    };
+    Ok(location)
+}
+
+fn parse_address(address: &str, cpu: &CPUState, size: usize) -> Result<u16, String> {
+    let value = parse_value(address.trim(), cpu)?;
+    if value < 0 || value as usize >= size {
+        return Err(format!("Address {value} is out of range"));
+    }
+    Ok(value as u16)
+}
+
+/// Evaluates a number, a symbol, or a constant expression of them, such as `SCREEN+32`.
+fn parse_value(value: &str, cpu: &CPUState) -> Result<i16, String> {
+    let value = evaluate(value, &cpu.address_table)?;
+    i16::try_from(value).map_err(|_| format!("{value} does not fit in 16 bits"))
+}
+
+fn parse_count(count: &str) -> Result<u64, String> {
+    count
+        .parse::<u64>()
+        .map_err(|e| format!("Invalid count {count}: {e}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+    use crate::split_source;
+    use crate::test::on_large_stack;
+
+    /// Counts `i` up to 3, then loops at END.
+    const PROGRAM: &'static str =
+        "@3\nD=A\n(LOOP)\n@i\nM=M+1\n@1\nD=D-A\n@LOOP\nD;JGT\n(END)\n@END\n0;JMP";
+
+    fn load() -> (CPUState, Box<[Instruction; MAX_INSTRUCTIONS]>) {
+        let mut cpu = CPUState::new();
+        let lines = split_source(PROGRAM).unwrap();
+        let program = parse(lines, &mut cpu.address_table).unwrap();
+        (cpu, Box::new(program.instructions))
+    }
+
+    #[test]
+    fn step_and_run() {
+        on_large_stack(|| {
+            let (mut cpu, mut instructions) = load();
+            let output = execute("step", &mut cpu, &mut instructions).unwrap();
+            assert_eq!(output, "PC: 1  A: 3  D: 0  next: D=A");
+            execute("s 2", &mut cpu, &mut instructions).unwrap();
+            assert_eq!(cpu.pc, 3);
+
+            let output = execute("run until RAM[i]=3", &mut cpu, &mut instructions).unwrap();
+            assert!(output.starts_with("Stopped after"));
+            assert_eq!((cpu.pc, cpu.ram[16].0), (4, 3));
+            execute("run until END", &mut cpu, &mut instructions).unwrap();
+            assert_eq!(cpu.pc, 8);
+            assert!(execute("run LOOP", &mut cpu, &mut instructions).is_err());
+        });
+    }
+
+    #[test]
+    fn examine_and_set() {
+        on_large_stack(|| {
+            let (mut cpu, mut instructions) = load();
+            let output = execute("x/3 ROM[LOOP]", &mut cpu, &mut instructions).unwrap();
+            assert_eq!(output, "(LOOP)\nROM[2]: @16\nROM[3]: M=M+1\nROM[4]: @1");
+
+            execute("set RAM[i] 9", &mut cpu, &mut instructions).unwrap();
+            execute("set D=-2", &mut cpu, &mut instructions).unwrap();
+            execute("set PC END", &mut cpu, &mut instructions).unwrap();
+            assert_eq!((cpu.ram[16].0, cpu.d.0, cpu.pc), (9, -2, 8));
+            let output = execute("x/2 i", &mut cpu, &mut instructions).unwrap();
+            assert_eq!(output, "RAM[16]: 9\nRAM[17]: 0");
+
+            assert!(execute("set ROM[0] 1", &mut cpu, &mut instructions).is_err());
+            assert!(execute("x D", &mut cpu, &mut instructions).is_err());
+        });
+    }
+
+    #[test]
+    fn breakpoints_stop_the_run() {
+        on_large_stack(|| {
+            let (mut cpu, mut instructions) = load();
+            let output = execute("break RAM[i]=2", &mut cpu, &mut instructions).unwrap();
+            assert_eq!(output, "Breakpoint at RAM[16]: 2");
+            execute("run", &mut cpu, &mut instructions).unwrap();
+            assert_eq!((cpu.pc, cpu.ram[16].0), (4, 2));
+
+            execute("delete RAM[i]=2", &mut cpu, &mut instructions).unwrap();
+            assert!(execute("delete RAM[i]=2", &mut cpu, &mut instructions).is_err());
+            let output = execute("run", &mut cpu, &mut instructions).unwrap();
+            assert!(output.starts_with("Still running"));
+        });
+    }
+
+    #[test]
+    fn conditions() {
+        on_large_stack(|| {
+            let (cpu, _) = load();
+            assert_eq!(parse_condition("A=5", &cpu), Ok(Breakpoint::A(5)));
+            assert_eq!(parse_condition("D = -1", &cpu), Ok(Breakpoint::D(-1)));
+            assert_eq!(parse_condition("END", &cpu), Ok(Breakpoint::PC(8)));
+            assert_eq!(parse_condition("PC=LOOP+1", &cpu), Ok(Breakpoint::PC(3)));
+            assert_eq!(
+                parse_condition("RAM[i]=0", &cpu),
+                Ok(Breakpoint::RAM(16, 0))
+            );
+            assert_eq!(
+                parse_condition("RAM[24577]=0", &cpu),
+                Err(String::from("Address 24577 is out of range"))
+            );
+            assert!(parse_condition("ROM[0]=1", &cpu).is_err());
+            assert!(parse_condition("missing", &cpu).is_err());
+        });
+    }
+
+    #[test]
+    fn asm_executes_without_moving_the_pc() {
+        on_large_stack(|| {
+            let (mut cpu, mut instructions) = load();
+            execute("asm @7", &mut cpu, &mut instructions).unwrap();
+            execute("asm D=A+1", &mut cpu, &mut instructions).unwrap();
+            assert_eq!((cpu.pc, cpu.a.0, cpu.d.0), (0, 7, 8));
+
+            assert_eq!(
+                execute("asm @missing", &mut cpu, &mut instructions),
+                Err(String::from("Unknown symbol missing"))
+            );
+            assert!(!cpu.address_table.table.contains_key("missing"));
+            assert_eq!(cpu.address_table.current_variable, 17);
+
+            // A jump to the next address still moves the PC
+            execute("asm @1", &mut cpu, &mut instructions).unwrap();
+            execute("asm 0;JMP", &mut cpu, &mut instructions).unwrap();
+            assert_eq!(cpu.pc, 1);
+            execute("asm D;JEQ", &mut cpu, &mut instructions).unwrap();
+            assert_eq!(cpu.pc, 1);
+        });
+    }
+}
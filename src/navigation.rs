@@ -0,0 +1,220 @@
+use imgui::Ui;
+
+use crate::debug::RED;
+use crate::expression::evaluate;
+use crate::symbol_table::SymbolTable;
+
+/// Stands for any run of characters in a search pattern.
+const WILDCARD: char = '*';
+const SEARCH_BOX_WIDTH: f32 = 100.0;
+/// How far down the table a row that is gone to is shown, as a fraction of the table's height.
+const SCROLL_RATIO: f32 = 0.3;
+
+/// The navigation of a long table of addresses, such as the ROM or the RAM, which holds the
+/// address gone to, and the matches of the last search.
+#[derive(Default)]
+pub struct TableNavigation {
+    go_to_input: String,
+    search_input: String,
+    error: Option<String>,
+    matches: Vec<u16>,
+    current_match: usize,
+    /// The address last gone to, which is highlighted in the table.
+    pub selected: Option<u16>,
+    /// The address to scroll to, once the height of the rows is known.
+    scroll_target: Option<u16>,
+    /// The frame, row and height in the table of the last row laid out.
+    previous_row: Option<(i32, usize, f32)>,
+}
+
+impl TableNavigation {
+    /// Builds the go to and search boxes, with the buttons that step through the matches. Addresses
+    /// below `size` are gone to by number, symbol or constant expression, and `search` returns the
+    /// addresses matching a pattern. The `id` tells apart the boxes of different tables.
+    pub fn build(
+        self: &mut Self,
+        ui: &Ui,
+        id: &str,
+        address_table: &SymbolTable,
+        size: usize,
+        search: impl FnOnce(&str) -> Vec<u16>,
+    ) {
+        ui.text("Go to:");
+        ui.same_line();
+        ui.set_next_item_width(SEARCH_BOX_WIDTH);
+        if ui
+            .input_text(format!("##go_to_{id}"), &mut self.go_to_input)
+            .enter_returns_true(true)
+            .build()
+        {
+            match parse_address(&self.go_to_input, address_table, size) {
+                Ok(address) => {
+                    self.error = None;
+                    self.go_to(address);
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+        ui.same_line();
+        ui.text("Find:");
+        ui.same_line();
+        ui.set_next_item_width(SEARCH_BOX_WIDTH);
+        if ui
+            .input_text(format!("##search_{id}"), &mut self.search_input)
+            .enter_returns_true(true)
+            .build()
+        {
+            self.error = None;
+            self.matches = search(self.search_input.trim());
+            self.current_match = 0;
+            match self.matches.first() {
+                Some(address) => self.go_to(*address),
+                None => self.error = Some(String::from("No matches")),
+            }
+        }
+        if !self.matches.is_empty() {
+            ui.same_line();
+            if ui.arrow_button(format!("##previous_{id}"), imgui::Direction::Up) {
+                self.step(self.matches.len() - 1);
+            }
+            ui.same_line();
+            if ui.arrow_button(format!("##next_{id}"), imgui::Direction::Down) {
+                self.step(1);
+            }
+            ui.same_line();
+            ui.text(format!("{}/{}", self.current_match + 1, self.matches.len()));
+        }
+        if let Some(e) = &self.error {
+            ui.text_colored(RED, e);
+        }
+    }
+
+    /// Selects the address, and scrolls the table to it.
+    pub fn go_to(self: &mut Self, address: u16) {
+        self.selected = Some(address);
+        self.scroll_target = Some(address);
+    }
+
+    /// Called at the start of each row of the table that is laid out, since rows far from view are
+    /// skipped by the [imgui::ListClipper]. Once two consecutive rows have been laid out, their
+    /// height gives the position of any other row, and the table is scrolled to the row of the
+    /// address gone to, found by `row_of`.
+    pub fn lay_out_row(self: &mut Self, ui: &Ui, row: usize, row_of: impl FnOnce(u16) -> usize) {
+        let frame = ui.frame_count();
+        let y = ui.cursor_pos()[1];
+        if let (Some(address), Some((previous_frame, previous_row, previous_y))) =
+            (self.scroll_target, self.previous_row)
+        {
+            if previous_frame == frame && previous_row + 1 == row {
+                let height = y - previous_y;
+                let target_y = y + (row_of(address) as f32 - row as f32) * height;
+                ui.set_scroll_y(target_y - ui.window_size()[1] * SCROLL_RATIO);
+                self.scroll_target = None;
+            }
+        }
+        self.previous_row = Some((frame, row, y));
+    }
+
+    /// Moves through the matches by `offset`, wrapping around at the end.
+    fn step(self: &mut Self, offset: usize) {
+        self.current_match = (self.current_match + offset) % self.matches.len();
+        self.go_to(self.matches[self.current_match]);
+    }
+}
+
+/// Evaluates the address to go to, which must be within a table of `size` addresses.
+fn parse_address(input: &str, address_table: &SymbolTable, size: usize) -> Result<u16, String> {
+    let address = evaluate(input.trim(), address_table)?;
+    match u16::try_from(address) {
+        Ok(address) if (address as usize) < size => Ok(address),
+        _ => Err(format!(
+            "Address {address} is out of range, expected 0 to {}",
+            size - 1
+        )),
+    }
+}
+
+/// Whether the text matches the search pattern as a whole, where a `*` in the pattern matches any
+/// run of characters. Spaces are ignored, so that `M = D` matches `M=D`.
+pub fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern: String = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut parts = pattern.split(WILDCARD);
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // There is no wildcard, so the whole text must match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn patterns_without_wildcards_match_whole_text() {
+        assert!(matches_pattern("M=D", "M=D"));
+        assert!(!matches_pattern("M=D", "AM=D"));
+        assert!(!matches_pattern("M=D", "M=D+1"));
+        assert!(matches_pattern("", ""));
+        assert!(!matches_pattern("", "M=D"));
+    }
+
+    #[test]
+    fn wildcards_match_any_run() {
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("*", "@LOOP"));
+        assert!(matches_pattern("*;JMP", "0;JMP"));
+        assert!(matches_pattern("*;JMP", ";JMP"));
+        assert!(!matches_pattern("*;JMP", "0;JMP2"));
+        assert!(matches_pattern("@LOOP*", "@LOOP_END"));
+        assert!(!matches_pattern("@LOOP*", "@END_LOOP"));
+        assert!(matches_pattern("*D*", "AM=D+1"));
+        assert!(matches_pattern("M=*+*", "M=D+1"));
+        assert!(!matches_pattern("M=*+*", "M=D-1"));
+    }
+
+    #[test]
+    fn patterns_longer_than_the_text_do_not_match() {
+        assert!(!matches_pattern("@LOOP", "@LOO"));
+        assert!(!matches_pattern("@LOOP*", "@LOO"));
+        assert!(!matches_pattern("*@LOOP", "LOOP"));
+        // The prefix and suffix may not share the characters of the text
+        assert!(!matches_pattern("AB*BC", "ABC"));
+        assert!(matches_pattern("AB*BC", "ABBC"));
+    }
+
+    #[test]
+    fn spaces_are_ignored() {
+        assert!(matches_pattern("M = D", "M=D"));
+        assert!(matches_pattern("M=D", " M = D "));
+        assert!(matches_pattern(" * ; JMP ", "0;JMP"));
+        assert!(matches_pattern("' '", "''"));
+    }
+
+    #[test]
+    fn addresses_must_be_within_the_table() {
+        let address_table = SymbolTable::new();
+        assert_eq!(parse_address(" 10 ", &address_table, 16), Ok(10));
+        assert_eq!(parse_address("15", &address_table, 16), Ok(15));
+        assert_eq!(parse_address("SCREEN", &address_table, 24577), Ok(16384));
+        assert_eq!(parse_address("KBD", &address_table, 24577), Ok(24576));
+        let out_of_range = Err(String::from("Address 16 is out of range, expected 0 to 15"));
+        assert_eq!(parse_address("16", &address_table, 16), out_of_range);
+        let negative = Err(String::from("Address -1 is out of range, expected 0 to 15"));
+        assert_eq!(parse_address("0-1", &address_table, 16), negative);
+        assert!(parse_address("70000", &address_table, 24577).is_err());
+        assert!(parse_address("NOWHERE", &address_table, 16).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::expression::parse_literal;
+
+const HEX_PREFIX: &'static str = "0x";
+const BINARY_PREFIX: &'static str = "0b";
+/// The number of binary digits between the spaces that make binary words easier to read.
+const BINARY_GROUP: usize = 4;
+
+/// How a 16 bit word is displayed in the emulator, and how edits to it are read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NumberFormat {
+    /// Signed decimal, as the CPU does arithmetic.
+    Decimal,
+    Unsigned,
+    Hex,
+    /// Grouped into nibbles, which suits screen bitmaps and bitwise logic.
+    Binary,
+    /// The character with the code of the word, or the code itself if it is not printable.
+    Char,
+}
+
+impl NumberFormat {
+    /// Every format, in the order they are offered in the emulator.
+    pub const ALL: [NumberFormat; 5] = [
+        NumberFormat::Decimal,
+        NumberFormat::Unsigned,
+        NumberFormat::Hex,
+        NumberFormat::Binary,
+        NumberFormat::Char,
+    ];
+    /// The names of the formats in [NumberFormat::ALL].
+    pub const NAMES: [&'static str; 5] = ["Decimal", "Unsigned", "Hex", "Binary", "Char"];
+
+    /// Writes the word in this format.
+    pub fn format(self: &Self, value: i16) -> String {
+        let word = value as u16;
+        match self {
+            NumberFormat::Decimal => value.to_string(),
+            NumberFormat::Unsigned => word.to_string(),
+            NumberFormat::Hex => format!("{HEX_PREFIX}{word:04X}"),
+            NumberFormat::Binary => {
+                let digits = format!("{word:016b}");
+                let groups: Vec<&str> = (0..digits.len())
+                    .step_by(BINARY_GROUP)
+                    .map(|i| &digits[i..i + BINARY_GROUP])
+                    .collect();
+                groups.join(" ")
+            }
+            NumberFormat::Char => match char::from_u32(word as u32) {
+                Some(c) if c.is_ascii_graphic() || c == ' ' => format!("'{c}'"),
+                _ => word.to_string(),
+            },
+        }
+    }
+
+    /// Reads a word written in this format. The prefixes of hexadecimal and binary numbers may be
+    /// left out in those formats, and in the char format, a lone character other than a digit
+    /// stands for its code. Any other literal accepted by [parse_literal] is read as well, as long
+    /// as it fits in 16 bits, either signed or unsigned.
+    pub fn parse(self: &Self, text: &str) -> Option<i16> {
+        let text = text.trim();
+        let value = match self {
+            NumberFormat::Hex => {
+                let digits = text.strip_prefix(HEX_PREFIX).unwrap_or(text);
+                u16::from_str_radix(digits, 16).ok().map(i32::from)
+            }
+            NumberFormat::Binary => {
+                let digits = text.strip_prefix(BINARY_PREFIX).unwrap_or(text);
+                u16::from_str_radix(&digits.replace([' ', '_'], ""), 2)
+                    .ok()
+                    .map(i32::from)
+            }
+            NumberFormat::Char if text.chars().count() == 1 => text
+                .chars()
+                .next()
+                .filter(|c| !c.is_ascii_digit())
+                .map(|c| c as i32),
+            _ => None,
+        };
+        let value = value.or_else(|| parse_literal(text))?;
+        (i16::MIN as i32..=u16::MAX as i32)
+            .contains(&value)
+            .then_some(value as i16)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let format = |format: NumberFormat| {
+            [0, 1, -1, 65, i16::MAX, i16::MIN].map(|value| format.format(value))
+        };
+        assert_eq!(
+            format(NumberFormat::Decimal),
+            ["0", "1", "-1", "65", "32767", "-32768"]
+        );
+        assert_eq!(
+            format(NumberFormat::Unsigned),
+            ["0", "1", "65535", "65", "32767", "32768"]
+        );
+        assert_eq!(
+            format(NumberFormat::Hex),
+            ["0x0000", "0x0001", "0xFFFF", "0x0041", "0x7FFF", "0x8000"]
+        );
+        assert_eq!(NumberFormat::Binary.format(0), "0000 0000 0000 0000");
+        assert_eq!(NumberFormat::Binary.format(-1), "1111 1111 1111 1111");
+        assert_eq!(NumberFormat::Binary.format(0x1234), "0001 0010 0011 0100");
+    }
+
+    #[test]
+    fn unprintable_chars_are_shown_as_codes() {
+        assert_eq!(NumberFormat::Char.format(65), "'A'");
+        assert_eq!(NumberFormat::Char.format(32), "' '");
+        assert_eq!(NumberFormat::Char.format(126), "'~'");
+        assert_eq!(NumberFormat::Char.format(10), "10");
+        assert_eq!(NumberFormat::Char.format(127), "127");
+        assert_eq!(NumberFormat::Char.format(233), "233");
+        assert_eq!(NumberFormat::Char.format(-1), "65535");
+    }
+
+    #[test]
+    fn prefixes_are_optional_in_their_own_format() {
+        assert_eq!(NumberFormat::Hex.parse("0x4000"), Some(16384));
+        assert_eq!(NumberFormat::Hex.parse("4000"), Some(16384));
+        assert_eq!(NumberFormat::Hex.parse("ffff"), Some(-1));
+        assert_eq!(NumberFormat::Hex.parse("0xG"), None);
+        assert_eq!(NumberFormat::Binary.parse("0b101"), Some(5));
+        assert_eq!(NumberFormat::Binary.parse("101"), Some(5));
+        assert_eq!(
+            NumberFormat::Binary.parse("1111 0000_1111 0000"),
+            Some(-3856)
+        );
+        assert_eq!(NumberFormat::Binary.parse("12"), Some(12));
+        // Other formats need the prefixes, and read the digits alone as decimal
+        assert_eq!(NumberFormat::Decimal.parse("0x10"), Some(16));
+        assert_eq!(NumberFormat::Decimal.parse("0b10"), Some(2));
+        assert_eq!(NumberFormat::Decimal.parse("10"), Some(10));
+        assert_eq!(NumberFormat::Unsigned.parse("ff"), None);
+    }
+
+    #[test]
+    fn lone_characters_are_read_as_codes() {
+        assert_eq!(NumberFormat::Char.parse("A"), Some(65));
+        assert_eq!(NumberFormat::Char.parse(" A "), Some(65));
+        assert_eq!(NumberFormat::Char.parse("'A'"), Some(65));
+        assert_eq!(NumberFormat::Char.parse("7"), Some(7));
+        assert_eq!(NumberFormat::Char.parse("65"), Some(65));
+        assert_eq!(NumberFormat::Char.parse("AB"), None);
+        assert_eq!(NumberFormat::Char.parse("é"), Some(233));
+        assert_eq!(NumberFormat::Char.parse("😀"), None);
+        assert_eq!(NumberFormat::Decimal.parse("A"), None);
+    }
+
+    #[test]
+    fn words_fit_in_16_bits_signed_or_unsigned() {
+        for format in NumberFormat::ALL {
+            assert_eq!(format.parse("-32768"), Some(i16::MIN));
+            assert_eq!(format.parse("65535"), Some(-1));
+            assert_eq!(format.parse("-32769"), None);
+            assert_eq!(format.parse("65536"), None);
+            assert_eq!(format.parse(""), None);
+        }
+        assert_eq!(NumberFormat::Decimal.parse("32768"), Some(i16::MIN));
+        assert_eq!(NumberFormat::Hex.parse("0x10000"), None);
+        assert_eq!(NumberFormat::Binary.parse("1 0000 0000 0000 0000"), None);
+    }
+
+    #[test]
+    fn formatted_words_are_read_back() {
+        for format in NumberFormat::ALL {
+            for value in [0, 1, -1, 32, 65, 1234, i16::MAX, i16::MIN] {
+                assert_eq!(format.parse(&format.format(value)), Some(value));
+            }
+        }
+    }
+}
@@ -1,115 +1,389 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
 
 use regex::Regex;
 
+use crate::expression::{evaluate, is_symbol, symbols_in};
 use crate::instructions::{Instruction, A, C};
 use crate::symbol_table::SymbolTable;
 
-const COMMENT_BEGIN: &'static str = "//";
+pub const COMMENT_BEGIN: &'static str = "//";
 const LABEL_BEGIN: char = '(';
 const LABEL_END: char = ')';
 const VARIABLE_DECLARATION: char = '@';
+const CHARACTER_QUOTE: char = '\'';
+const DIRECTIVE_BEGIN: char = '.';
+const CONSTANT_DIRECTIVE: &'static str = ".equ";
+const DATA_DIRECTIVE: &'static str = ".data";
 
 pub const MAX_INSTRUCTIONS: usize = i16::MAX as usize;
 pub const MAX_RAM: usize = 24577;
 
-/// Represents an invalid line in the source code. Used for showing the user the error.
+/// Represents an invalid line in the source code. Used for showing the user the error. Holds the
+/// line number (starting from 1), the offending line, and the reason it is invalid.
 #[derive(Debug)]
 pub enum LineParsingError {
-    InvalidLine(u16, String),
+    InvalidLine(u16, String, String),
 }
 impl fmt::Display for LineParsingError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Invalid line")
+        match self {
+            LineParsingError::InvalidLine(line_number, line, reason) => {
+                write!(
+                    f,
+                    "Error in program at line {}: {} ({})",
+                    line_number, line, reason
+                )
+            }
+        }
     }
 }
 
+/// A program parsed from its source code. The `data` holds the RAM addresses and values set by
+/// the `.data` directives, which are written to the RAM when the program is loaded, and the
+/// `source_map` holds the source line of each instruction (see [source_map]). The `expansions`
+/// hold the ROM addresses that each pseudo-instruction expanded to, see [crate::pseudo], and the
+/// `symbols` hold the symbol each A instruction was written with, by ROM address. The
+/// `expression_symbols` are those used within constant expressions, such as `LOOP` in `@LOOP+1`,
+/// and `included` holds the files read by the `#include`s of the program, see
+/// [crate::preprocessor].
+#[derive(Debug)]
+pub struct ParsedProgram {
+    pub instructions: [Instruction; MAX_INSTRUCTIONS],
+    pub data: Vec<(u16, i16)>,
+    pub source_map: Vec<usize>,
+    pub expansions: Vec<(Range<usize>, String)>,
+    pub symbols: HashMap<u16, String>,
+    pub expression_symbols: HashSet<String>,
+    pub included: Vec<PathBuf>,
+}
+
+/// A single line of the source code, other than a label, once parsed.
+enum Statement {
+    Instruction(Instruction),
+    Data(Vec<(u16, i16)>),
+    /// An `.equ` directive, which only adds to the [SymbolTable].
+    Constant,
+}
+
 /// Parses a series of lines that make up the source code for the program to be run.
 pub fn parse(
     lines: [String; MAX_INSTRUCTIONS],
     address_table: &mut SymbolTable,
-) -> Result<[Instruction; MAX_INSTRUCTIONS], LineParsingError> {
-    let whitespace_cleaned_lines = clear_whitespace(lines);
+) -> Result<ParsedProgram, LineParsingError> {
+    let source_map = source_map(&lines);
+    let (whitespace_cleaned_lines, line_numbers) = clear_whitespace(lines);
     labels_and_variables(&whitespace_cleaned_lines, address_table);
-    let mut parsed_lines: [Instruction; MAX_INSTRUCTIONS] =
-        [const { Instruction::None }; MAX_INSTRUCTIONS];
-    let mut offset = 0;
-    for (i, line) in whitespace_cleaned_lines.iter().enumerate() {
-        if line.is_empty() {
-            continue;
+    let mut program = ParsedProgram {
+        instructions: [const { Instruction::None }; MAX_INSTRUCTIONS],
+        data: vec![],
+        source_map,
+        expansions: vec![],
+        symbols: symbols(&whitespace_cleaned_lines),
+        expression_symbols: expression_symbols(&whitespace_cleaned_lines),
+        included: vec![],
+    };
+    let mut address = 0;
+    for statement in parse_lines(&whitespace_cleaned_lines, &line_numbers, address_table) {
+        match statement? {
+            Statement::Instruction(instruction) => {
+                program.instructions[address] = instruction;
+                address += 1;
+            }
+            Statement::Data(words) => program.data.extend(words),
+            Statement::Constant => {}
         }
+    }
+    Ok(program)
+}
 
-        // A instruction
-        if line.starts_with(VARIABLE_DECLARATION) {
-            // Unchecked unwrap is acceptable, since all the destinations are put into the address
-            // table in labels_and_variables
-            parsed_lines[i - offset] = Instruction::A(A::new(
-                &address_table.table.get(&line[1..]).unwrap().to_string(),
-            ));
-        } else if line.starts_with(LABEL_BEGIN) && line.ends_with(LABEL_END) {
-            offset += 1;
-            // parsed_lines[i] = Instruction::Label(line[1..line.len() - 1].to_string());
-        }
-        // C instruction
-        else {
-            let temp_line = split_line(&line);
-            let instruction;
-            if temp_line.len() == 2 {
-                if line.contains(';') {
-                    instruction = Instruction::C(C::new("", temp_line[0], temp_line[1]));
-                } else {
-                    instruction = Instruction::C(C::new(temp_line[0], temp_line[1], ""));
+/// Checks every line of the source code, returning all of the errors, rather than stopping at the
+/// first one like [parse].
+pub fn check(
+    lines: [String; MAX_INSTRUCTIONS],
+    address_table: &mut SymbolTable,
+) -> Vec<LineParsingError> {
+    let (whitespace_cleaned_lines, line_numbers) = clear_whitespace(lines);
+    labels_and_variables(&whitespace_cleaned_lines, address_table);
+    parse_lines(&whitespace_cleaned_lines, &line_numbers, address_table)
+        .filter_map(|statement| statement.err())
+        .collect()
+}
+
+/// Parses the instructions and directives of the cleaned source code, skipping over the labels.
+/// The `line_numbers` hold the index of the source line each cleaned line came from.
+fn parse_lines<'a>(
+    whitespace_cleaned_lines: &'a [String; MAX_INSTRUCTIONS],
+    line_numbers: &'a [usize],
+    address_table: &'a SymbolTable,
+) -> impl Iterator<Item = Result<Statement, LineParsingError>> + 'a {
+    whitespace_cleaned_lines
+        .iter()
+        .zip(line_numbers)
+        .filter(|(line, _)| !is_label(line))
+        .map(|(line, line_number)| {
+            parse_statement(line, address_table).map_err(|reason| {
+                LineParsingError::InvalidLine(*line_number as u16 + 1, line.to_owned(), reason)
+            })
+        })
+}
+
+/// Parses a single directive, or A or C instruction, from a line cleaned by [clean_line].
+fn parse_statement(line: &str, address_table: &SymbolTable) -> Result<Statement, String> {
+    if !is_directive(line) {
+        return Ok(Statement::Instruction(parse_instruction(
+            line,
+            address_table,
+        )?));
+    }
+    let (directive, args) = line.split_once(' ').unwrap_or((line, ""));
+    match directive {
+        CONSTANT_DIRECTIVE => {
+            // The constants are put into the address table in labels_and_variables, so this only
+            // checks that the constant was not defined differently elsewhere
+            let (name, value) = parse_constant(args, address_table)?;
+            match address_table.table.get(&name) {
+                Some(v) if *v == value && address_table.constants.contains(&name) => {
+                    Ok(Statement::Constant)
                 }
-            } else {
-                let dest = match address_table.table.get(temp_line[0]) {
-                    Some(d) => d,
-                    None => return Err(LineParsingError::InvalidLine(i as u16, line.to_owned())),
-                };
-                instruction = Instruction::C(C::new(&dest.to_string(), temp_line[1], temp_line[2]));
+                Some(_) => Err(format!("{name} is already defined")),
+                None => Err(String::from(
+                    "Constants may only refer to numbers, labels and other constants",
+                )),
             }
-            parsed_lines[i - offset] = instruction;
         }
+        DATA_DIRECTIVE => Ok(Statement::Data(parse_data(args, address_table)?)),
+        _ => Err(format!("Unknown directive {directive}")),
     }
-    Ok(parsed_lines)
+}
+
+/// Parses the arguments of an `.equ NAME VALUE` directive, where the value may be a constant
+/// expression.
+fn parse_constant(args: &str, address_table: &SymbolTable) -> Result<(String, u16), String> {
+    let (name, value) = args
+        .split_once(' ')
+        .ok_or(format!("Expected {CONSTANT_DIRECTIVE} NAME VALUE"))?;
+    if !is_symbol(name) {
+        return Err(format!("{name} is not a valid name"));
+    }
+    let value = evaluate(value, address_table)?;
+    let value = u16::try_from(value).map_err(|_| {
+        format!(
+            "{value} is not a valid constant, expected 0 to {}",
+            u16::MAX
+        )
+    })?;
+    Ok((name.to_string(), value))
+}
+
+/// Parses the arguments of a `.data ADDRESS: VALUE, VALUE, ...` directive into the RAM addresses
+/// and the values they hold. Values may be given as signed or unsigned 16 bit numbers.
+fn parse_data(args: &str, address_table: &SymbolTable) -> Result<Vec<(u16, i16)>, String> {
+    let (address, values) = args
+        .split_once(':')
+        .ok_or(format!("Expected {DATA_DIRECTIVE} ADDRESS: VALUE, ..."))?;
+    let address = evaluate(address.trim(), address_table)?;
+    let mut words = vec![];
+    for (i, value) in values.split(',').enumerate() {
+        let value = evaluate(value.trim(), address_table)?;
+        let value = i16::try_from(value)
+            .or(u16::try_from(value).map(|v| v as i16))
+            .map_err(|_| format!("{value} does not fit in 16 bits"))?;
+        let target = address + i as i32;
+        if !(0..MAX_RAM as i32).contains(&target) {
+            return Err(format!("RAM[{target}] is out of range"));
+        }
+        words.push((target as u16, value));
+    }
+    Ok(words)
+}
+
+/// Parses a single line of source code outside of a program, such as one typed into the monitor.
+/// New variables are added to the [SymbolTable], but labels cannot be declared.
+pub fn parse_line(line: &str, address_table: &mut SymbolTable) -> Result<Instruction, String> {
+    let line = clean_line(line);
+    if line.is_empty() || is_label(&line) || is_directive(&line) {
+        return Err(String::from("Expected an A or C instruction"));
+    }
+    if let Some(symbol) = line.strip_prefix(VARIABLE_DECLARATION) {
+        declare_variable(symbol.to_string(), address_table);
+    }
+    parse_instruction(&line, address_table)
+}
+
+/// Parses a single A or C instruction, from a line cleaned by [clean_line].
+fn parse_instruction(line: &str, address_table: &SymbolTable) -> Result<Instruction, String> {
+    // A instruction
+    if let Some(symbol) = line.strip_prefix(VARIABLE_DECLARATION) {
+        // All the symbols are put into the address table in labels_and_variables, so anything
+        // else is a literal, or a constant expression
+        let address = match address_table.table.get(symbol) {
+            Some(address) => *address as i32,
+            None if symbol.is_empty() => {
+                return Err(String::from("Missing the destination of the A instruction"))
+            }
+            None => evaluate(symbol, address_table)?,
+        };
+        return Ok(Instruction::A(A::new(&address.to_string())?));
+    }
+
+    // C instruction
+    let temp_line = split_line(line);
+    let instruction = match temp_line[..] {
+        [comp] => C::new("", comp, "")?,
+        [comp, jump] if line.contains(';') => C::new("", comp, jump)?,
+        [dest, comp] => C::new(dest, comp, "")?,
+        [dest, comp, jump] => C::new(dest, comp, jump)?,
+        _ => return Err(String::from("Not a valid instruction")),
+    };
+    Ok(Instruction::C(instruction))
 }
 
 /// Given a line which appears to be a C instruction, it splits the line on the chars that
 /// delineate the parts of C instructions.
-fn split_line(line: &String) -> Vec<&str> {
+fn split_line(line: &str) -> Vec<&str> {
     let re = Regex::new(r"[ ,=;]").unwrap();
     re.split(line).collect()
 }
 
 /// Clears whitespace out of provided source code. Whitespace includes empty lines, and comments.
-fn clear_whitespace(lines: [String; MAX_INSTRUCTIONS]) -> [String; MAX_INSTRUCTIONS] {
+/// Additionally returns the index of the source line that each of the remaining lines came from.
+fn clear_whitespace(lines: [String; MAX_INSTRUCTIONS]) -> ([String; MAX_INSTRUCTIONS], Vec<usize>) {
     let mut whitespace_cleaned_lines: [String; MAX_INSTRUCTIONS] =
         [const { String::new() }; MAX_INSTRUCTIONS];
-    let mut count = 0;
+    let mut line_numbers = vec![];
     for (i, line) in lines.iter().enumerate() {
-        if line.is_empty() || line.starts_with(COMMENT_BEGIN) {
-            count += 1;
-        } else if let Some(comment_index) = line.find(COMMENT_BEGIN) {
-            let trimmed = &line[..comment_index].trim();
-            whitespace_cleaned_lines[i - count] = trimmed.replace(' ', "").to_string();
-        } else {
-            whitespace_cleaned_lines[i - count] = line.replace(' ', "").to_string();
+        let cleaned = clean_line(line);
+        if !cleaned.is_empty() {
+            whitespace_cleaned_lines[line_numbers.len()] = cleaned;
+            line_numbers.push(i);
         }
     }
-    whitespace_cleaned_lines
+    (whitespace_cleaned_lines, line_numbers)
+}
+
+/// Splits a line of source code into its code, and its comment starting with [COMMENT_BEGIN],
+/// which is empty if the line has no comment.
+pub fn split_comment(line: &str) -> (&str, &str) {
+    match line.find(COMMENT_BEGIN) {
+        Some(comment_index) => line.split_at(comment_index),
+        None => (line, ""),
+    }
+}
+
+/// Removes the comment and the spaces from a single line of source code. Spaces within character
+/// literals, such as `@' '`, are kept, and the arguments of directives stay separated by a single
+/// space.
+fn clean_line(line: &str) -> String {
+    let (code, _) = split_comment(line);
+    if is_directive(code.trim()) {
+        return code.split_whitespace().collect::<Vec<&str>>().join(" ");
+    }
+    let mut in_literal = false;
+    code.trim()
+        .chars()
+        .filter(|c| {
+            if *c == CHARACTER_QUOTE {
+                in_literal = !in_literal;
+            }
+            in_literal || !c.is_whitespace()
+        })
+        .collect()
+}
+
+/// Whether a line, cleaned by [clean_line], is a label declaration.
+fn is_label(line: &str) -> bool {
+    line.starts_with(LABEL_BEGIN) && line.ends_with(LABEL_END)
+}
+
+/// Whether a line, cleaned by [clean_line], is a directive such as `.equ`.
+fn is_directive(line: &str) -> bool {
+    line.starts_with(DIRECTIVE_BEGIN)
+}
+
+/// Maps every ROM address of the program to the index of the source line its instruction was
+/// parsed from.
+pub fn source_map(lines: &[String; MAX_INSTRUCTIONS]) -> Vec<usize> {
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| {
+            let cleaned = clean_line(line);
+            !cleaned.is_empty() && !is_label(&cleaned) && !is_directive(&cleaned)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Finds the A instructions written with a single symbol, such as `@LOOP`, and maps their ROM
+/// addresses to the symbol. The lines must already be cleaned by [clear_whitespace].
+fn symbols(lines: &[String; MAX_INSTRUCTIONS]) -> HashMap<u16, String> {
+    lines
+        .iter()
+        .filter(|line| !line.is_empty() && !is_label(line) && !is_directive(line))
+        .enumerate()
+        .filter_map(|(address, line)| {
+            let name = line.strip_prefix(VARIABLE_DECLARATION)?;
+            is_symbol(name).then(|| (address as u16, name.to_string()))
+        })
+        .collect()
 }
 
-/// Given the source code, this scans it for labels and variables, and stores them, and their
-/// representative addresses in the [SymbolTable].
+/// Finds the symbols used within the constant expressions of A instructions, such as `@LOOP+1`,
+/// and of directives, other than the names the `.equ` directives define. The lines must already be
+/// cleaned by [clear_whitespace].
+fn expression_symbols(lines: &[String; MAX_INSTRUCTIONS]) -> HashSet<String> {
+    lines
+        .iter()
+        .filter_map(|line| match line.strip_prefix(VARIABLE_DECLARATION) {
+            Some(expression) => (!is_symbol(expression)).then_some(expression),
+            None if is_directive(line) => match line.split_once(' ') {
+                Some((CONSTANT_DIRECTIVE, args)) => args.split_once(' ').map(|(_, value)| value),
+                Some((_, args)) => Some(args),
+                None => None,
+            },
+            None => None,
+        })
+        .flat_map(symbols_in)
+        .map(String::from)
+        .collect()
+}
+
+/// Given the source code, this scans it for labels, constants and variables, and stores them, and
+/// their representative addresses or values in the [SymbolTable].
 fn labels_and_variables(lines: &[String; MAX_INSTRUCTIONS], address_table: &mut SymbolTable) {
-    let mut labels_count: u16 = 0;
     // Add labels to address_table
-    for (i, line) in lines.iter().enumerate() {
-        if line.starts_with(LABEL_BEGIN) && line.ends_with(LABEL_END) {
+    let mut address: u16 = 0;
+    for line in lines {
+        if is_label(line) {
             let label_name: String = line[1..line.len() - 1].to_string();
-            address_table
-                .table
-                .insert(label_name, (i - labels_count as usize) as u16);
-            labels_count += 1;
+            address_table.table.insert(label_name.clone(), address);
+            address_table.labels.insert(label_name);
+        } else if !line.is_empty() && !is_directive(line) {
+            address += 1;
+        }
+    }
+
+    // Add constants to address_table. Constants may refer to those defined later in the
+    // program, so this repeats until no more can be added.
+    loop {
+        let mut added = false;
+        for line in lines {
+            let Some((CONSTANT_DIRECTIVE, args)) = line.split_once(' ') else {
+                continue;
+            };
+            if let Ok((name, value)) = parse_constant(args, address_table) {
+                if !address_table.table.contains_key(&name) {
+                    address_table.table.insert(name.clone(), value);
+                    address_table.constants.insert(name);
+                    added = true;
+                }
+            }
+        }
+        if !added {
+            break;
         }
     }
 
@@ -119,21 +393,126 @@ fn labels_and_variables(lines: &[String; MAX_INSTRUCTIONS], address_table: &mut
             continue;
         }
         let potential_var = line[1..].to_string();
-        if line.starts_with(VARIABLE_DECLARATION)
-            && !address_table.table.contains_key(&potential_var)
-        {
-            let pv = potential_var.parse::<u16>();
-            match pv {
-                Ok(r) => {
-                    address_table.table.insert(potential_var, r);
-                }
-                Err(_) => {
-                    address_table
-                        .table
-                        .insert(potential_var, address_table.current_variable);
-                    address_table.current_variable += 1;
-                }
-            }
+        if line.starts_with(VARIABLE_DECLARATION) {
+            declare_variable(potential_var, address_table);
+        }
+    }
+}
+
+/// Adds the destination of an A instruction to the [SymbolTable], if it is not there already.
+/// Numbers refer to themselves, and new names are given the next free variable address. Other
+/// literals and constant expressions are left to be evaluated when parsing.
+fn declare_variable(potential_var: String, address_table: &mut SymbolTable) {
+    if potential_var.is_empty() || address_table.table.contains_key(&potential_var) {
+        return;
+    }
+    let pv = potential_var.parse::<u16>();
+    match pv {
+        Ok(r) => {
+            address_table.table.insert(potential_var, r);
+        }
+        Err(_) if is_symbol(&potential_var) => {
+            address_table
+                .table
+                .insert(potential_var, address_table.current_variable);
+            address_table.current_variable += 1;
         }
+        Err(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::split_source;
+    use crate::test::on_large_stack;
+
+    fn parse_source(source: &str) -> Result<ParsedProgram, LineParsingError> {
+        parse(split_source(source).unwrap(), &mut SymbolTable::new())
+    }
+
+    fn error(source: &str) -> (u16, String) {
+        let LineParsingError::InvalidLine(line_number, _, reason) =
+            parse_source(source).unwrap_err();
+        (line_number, reason)
+    }
+
+    #[test]
+    fn constants_may_refer_forward() {
+        on_large_stack(|| {
+            let program = parse_source(
+                ".equ LAST END-1\n.equ END START+3\n.equ START 10\n@LAST\n(LOOP)\n@LOOP_END\n(LOOP_END)\n.equ AFTER LOOP_END+1\n@AFTER",
+            )
+            .unwrap();
+            assert_eq!(program.instructions[0], Instruction::A(A { dest: 12 }));
+            assert_eq!(program.instructions[1], Instruction::A(A { dest: 2 }));
+            assert_eq!(program.instructions[2], Instruction::A(A { dest: 3 }));
+        });
+    }
+
+    #[test]
+    fn constant_cycles_and_undefined_names_are_errors() {
+        on_large_stack(|| {
+            assert_eq!(
+                error(".equ ONE TWO\n.equ TWO ONE\n@ONE"),
+                (1, String::from("Unknown symbol TWO"))
+            );
+            assert_eq!(
+                error("@0\n.equ SIZE MISSING*2"),
+                (2, String::from("Unknown symbol MISSING"))
+            );
+            assert_eq!(
+                error("@counter\n.equ SIZE counter+1"),
+                (
+                    2,
+                    String::from("Constants may only refer to numbers, labels and other constants")
+                )
+            );
+            assert_eq!(
+                error(".equ SIZE 1\n.equ SIZE 2"),
+                (2, String::from("SIZE is already defined"))
+            );
+            assert_eq!(
+                error("(LOOP)\n.equ LOOP 0"),
+                (2, String::from("LOOP is already defined"))
+            );
+            assert_eq!(
+                error(".equ BIG 65536"),
+                (
+                    1,
+                    String::from("65536 is not a valid constant, expected 0 to 65535")
+                )
+            );
+        });
+    }
+
+    #[test]
+    fn data_is_kept_within_the_ram() {
+        on_large_stack(|| {
+            let program =
+                parse_source(".equ TABLE 100\n.data TABLE: 1, -1, 65535\n.data 24576: 'a'")
+                    .unwrap();
+            assert_eq!(
+                program.data,
+                [(100, 1), (101, -1), (102, -1), (24576, 'a' as i16)]
+            );
+
+            assert_eq!(
+                error(".data 24575: 1, 2, 3"),
+                (1, String::from("RAM[24577] is out of range"))
+            );
+            assert_eq!(
+                error(".data -1: 0"),
+                (1, String::from("RAM[-1] is out of range"))
+            );
+            assert_eq!(
+                error("@0\n.data 0: 65536"),
+                (2, String::from("65536 does not fit in 16 bits"))
+            );
+            assert_eq!(
+                error(".data 0 1"),
+                (1, String::from("Expected .data ADDRESS: VALUE, ..."))
+            );
+        });
     }
 }
@@ -0,0 +1,688 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::expression::is_symbol_char;
+use crate::parser::{self, split_comment, LineParsingError, ParsedProgram};
+use crate::pseudo;
+use crate::symbol_table::SymbolTable;
+use crate::MAX_INSTRUCTIONS;
+
+const INCLUDE_DIRECTIVE: &'static str = "#include";
+const MACRO_DIRECTIVE: &'static str = "#macro";
+const END_MACRO_DIRECTIVE: &'static str = "#endmacro";
+/// Enables the pseudo-instructions, such as `LD D, 42`, for the rest of the program.
+const EXTENDED_DIRECTIVE: &'static str = "#extended";
+const PARAMETER_BEGIN: char = '{';
+const PARAMETER_END: char = '}';
+/// The most macros that may be expanded within each other, which stops recursive macros.
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// Where a line of the preprocessed source code was written.
+#[derive(Debug, Clone)]
+pub struct Origin {
+    pub path: Rc<PathBuf>,
+    /// The index of the line within the file, starting from 0.
+    pub line: usize,
+    /// The `#include` or macro use which brought the line into the program, if any.
+    pub expanded_from: Option<Rc<Expansion>>,
+}
+
+/// An `#include`, or a use of a macro, along with where it was written.
+#[derive(Debug)]
+pub struct Expansion {
+    pub origin: Origin,
+    /// The name of the macro, or [None] for an `#include`.
+    pub macro_name: Option<String>,
+}
+
+impl Origin {
+    /// The line of the opened file that this line was ultimately expanded from.
+    pub fn root_line(self: &Self) -> usize {
+        match &self.expanded_from {
+            Some(expansion) => expansion.origin.root_line(),
+            None => self.line,
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.path.display(), self.line + 1)?;
+        match &self.expanded_from {
+            Some(expansion) => match &expansion.macro_name {
+                Some(name) => write!(f, ", in macro {name} used at {}", expansion.origin),
+                None => write!(f, ", included from {}", expansion.origin),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// A macro defined with `#macro NAME PARAMETER, ...`, and ended with `#endmacro`.
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<(String, Origin)>,
+    /// The labels declared within the body, which are renamed in each expansion, so that every
+    /// use of the macro has its own.
+    labels: HashSet<String>,
+}
+
+/// The source code of a program after its includes and macros have been expanded, along with
+/// where each of the lines came from. The `pseudo_instructions` hold the range of lines each
+/// pseudo-instruction expanded to, and `included` holds every file read by an `#include`.
+#[derive(Debug)]
+pub struct Preprocessed {
+    pub lines: [String; MAX_INSTRUCTIONS],
+    pub origins: Vec<Origin>,
+    pub pseudo_instructions: Vec<(Range<usize>, String)>,
+    pub included: Vec<PathBuf>,
+}
+
+impl Preprocessed {
+    /// Moves an error found in the preprocessed source code back to the opened file, noting the
+    /// expansion chain when the error is within an include or a macro.
+    pub fn locate(self: &Self, error: LineParsingError) -> LineParsingError {
+        let LineParsingError::InvalidLine(line_number, line, reason) = error;
+        match self.origins.get(line_number as usize - 1) {
+            Some(origin) => origin_error(origin, &line, reason),
+            None => LineParsingError::InvalidLine(line_number, line, reason),
+        }
+    }
+}
+
+/// Expands the `#include`s, macros and pseudo-instructions of a program, which was read from
+/// `path`. Included files are found relative to the file including them.
+pub fn preprocess(
+    lines: &[String; MAX_INSTRUCTIONS],
+    path: &Path,
+) -> Result<Preprocessed, LineParsingError> {
+    let mut preprocessor = Preprocessor {
+        macros: HashMap::new(),
+        lines: vec![],
+        origins: vec![],
+        includes: vec![fs::canonicalize(path).unwrap_or(path.to_path_buf())],
+        expansions: 0,
+        extended: false,
+        pseudo_instructions: vec![],
+        included: vec![],
+    };
+    let path = Rc::new(path.to_path_buf());
+    let last_line = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(0, |l| l + 1);
+    preprocessor.process(file_lines(&lines[..last_line], path, None), 0)?;
+
+    let mut preprocessed = Preprocessed {
+        lines: [const { String::new() }; MAX_INSTRUCTIONS],
+        origins: preprocessor.origins,
+        pseudo_instructions: preprocessor.pseudo_instructions,
+        included: preprocessor.included,
+    };
+    if preprocessor.lines.len() > MAX_INSTRUCTIONS {
+        let origin = &preprocessed.origins[MAX_INSTRUCTIONS];
+        return Err(origin_error(
+            origin,
+            &preprocessor.lines[MAX_INSTRUCTIONS],
+            format!("Too many lines after expansion, expected a maximum of {MAX_INSTRUCTIONS}"),
+        ));
+    }
+    for (i, line) in preprocessor.lines.into_iter().enumerate() {
+        preprocessed.lines[i] = line;
+    }
+    Ok(preprocessed)
+}
+
+/// Preprocesses and parses a program, which was read from `path`. Errors, and the source map of
+/// the program, refer to the lines of the opened file, and the expansions of the program refer to
+/// the ROM addresses of its pseudo-instructions.
+pub fn assemble(
+    lines: &[String; MAX_INSTRUCTIONS],
+    path: &Path,
+    address_table: &mut SymbolTable,
+) -> Result<ParsedProgram, LineParsingError> {
+    assemble_with_origins(lines, path, address_table).map(|(program, _)| program)
+}
+
+/// Assembles a program like [assemble], also returning the [Origin] of each instruction, by ROM
+/// address, which tells the included file or macro it was written in.
+pub fn assemble_with_origins(
+    lines: &[String; MAX_INSTRUCTIONS],
+    path: &Path,
+    address_table: &mut SymbolTable,
+) -> Result<(ParsedProgram, Vec<Origin>), LineParsingError> {
+    let preprocessed = preprocess(lines, path)?;
+    let mut program = parser::parse(preprocessed.lines.clone(), address_table)
+        .map_err(|error| preprocessed.locate(error))?;
+    for (lines, pseudo_instruction) in preprocessed.pseudo_instructions {
+        let start = program.source_map.partition_point(|&l| l < lines.start);
+        let end = program.source_map.partition_point(|&l| l < lines.end);
+        program.expansions.push((start..end, pseudo_instruction));
+    }
+    program.included = preprocessed.included;
+    let origins: Vec<Origin> = program
+        .source_map
+        .iter()
+        .map(|line| preprocessed.origins[*line].clone())
+        .collect();
+    for (line, origin) in program.source_map.iter_mut().zip(&origins) {
+        *line = origin.root_line();
+    }
+    Ok((program, origins))
+}
+
+/// Preprocesses and checks a program, returning all of the errors. See [parser::check].
+pub fn check(
+    lines: &[String; MAX_INSTRUCTIONS],
+    path: &Path,
+    address_table: &mut SymbolTable,
+) -> Vec<LineParsingError> {
+    match preprocess(lines, path) {
+        Ok(preprocessed) => parser::check(preprocessed.lines.clone(), address_table)
+            .into_iter()
+            .map(|error| preprocessed.locate(error))
+            .collect(),
+        Err(error) => vec![error],
+    }
+}
+
+struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    lines: Vec<String>,
+    origins: Vec<Origin>,
+    /// The files currently being included, to find files which include themselves.
+    includes: Vec<PathBuf>,
+    /// The number of macros expanded so far, used to make the labels of each expansion unique.
+    expansions: usize,
+    /// Whether the pseudo-instructions have been enabled by `#extended`.
+    extended: bool,
+    pseudo_instructions: Vec<(Range<usize>, String)>,
+    /// Every file read by an `#include` so far, once each.
+    included: Vec<PathBuf>,
+}
+
+impl Preprocessor {
+    /// Processes a series of lines, adding them to the output, or expanding them.
+    fn process(
+        self: &mut Self,
+        lines: Vec<(String, Origin)>,
+        depth: usize,
+    ) -> Result<(), LineParsingError> {
+        let mut definition: Option<(String, Macro, Origin)> = None;
+        for (line, origin) in lines {
+            let code = code(&line);
+            let (word, args) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+            let args = args.trim();
+
+            if let Some((name, mut new_macro, start)) = definition.take() {
+                match word {
+                    END_MACRO_DIRECTIVE => {
+                        self.macros.insert(name, new_macro);
+                    }
+                    MACRO_DIRECTIVE => {
+                        return Err(origin_error(
+                            &origin,
+                            &line,
+                            String::from("Macros cannot be defined within macros"),
+                        ))
+                    }
+                    _ => {
+                        let trimmed = code.trim();
+                        if trimmed.starts_with('(') && trimmed.ends_with(')') {
+                            new_macro
+                                .labels
+                                .insert(trimmed[1..trimmed.len() - 1].trim().to_string());
+                        }
+                        new_macro.body.push((line.clone(), origin));
+                        definition = Some((name, new_macro, start));
+                    }
+                }
+                continue;
+            }
+
+            match word {
+                INCLUDE_DIRECTIVE => self.include(args, &line, origin, depth)?,
+                EXTENDED_DIRECTIVE => self.extended = true,
+                MACRO_DIRECTIVE => {
+                    let (name, parameters) =
+                        args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                    if name.is_empty() || !name.chars().all(is_symbol_char) {
+                        return Err(origin_error(
+                            &origin,
+                            &line,
+                            format!("{name} is not a valid macro name"),
+                        ));
+                    }
+                    let new_macro = Macro {
+                        parameters: split_arguments(parameters),
+                        body: vec![],
+                        labels: HashSet::new(),
+                    };
+                    definition = Some((name.to_string(), new_macro, origin));
+                }
+                END_MACRO_DIRECTIVE => {
+                    return Err(origin_error(
+                        &origin,
+                        &line,
+                        format!("{END_MACRO_DIRECTIVE} without {MACRO_DIRECTIVE}"),
+                    ))
+                }
+                _ if word.starts_with('#') => {
+                    return Err(origin_error(
+                        &origin,
+                        &line,
+                        format!("Unknown preprocessor directive {word}"),
+                    ))
+                }
+                _ if self.macros.contains_key(word) => {
+                    self.expand(word, args, &line, origin, depth)?;
+                }
+                _ => match self.extended.then(|| pseudo::expand(code)).flatten() {
+                    Some(expansion) => {
+                        let expansion = expansion.map_err(|e| origin_error(&origin, &line, e))?;
+                        let start = self.lines.len();
+                        for expanded_line in expansion {
+                            self.lines.push(expanded_line);
+                            self.origins.push(origin.clone());
+                        }
+                        self.pseudo_instructions
+                            .push((start..self.lines.len(), code.to_string()));
+                    }
+                    None => {
+                        self.lines.push(line);
+                        self.origins.push(origin);
+                    }
+                },
+            }
+        }
+        match definition {
+            Some((name, _, start)) => Err(origin_error(
+                &start,
+                &format!("{MACRO_DIRECTIVE} {name}"),
+                format!("Missing {END_MACRO_DIRECTIVE}"),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Processes the lines of an included file, given as a quoted path relative to the file
+    /// including it.
+    fn include(
+        self: &mut Self,
+        args: &str,
+        line: &str,
+        origin: Origin,
+        depth: usize,
+    ) -> Result<(), LineParsingError> {
+        let Some(relative_path) = args
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+        else {
+            return Err(origin_error(
+                &origin,
+                line,
+                format!("Expected {INCLUDE_DIRECTIVE} \"FILE\""),
+            ));
+        };
+        let path = origin
+            .path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(relative_path);
+        let canonical_path = fs::canonicalize(&path).map_err(|e| {
+            origin_error(&origin, line, format!("Invalid path {relative_path}: {e}"))
+        })?;
+        if self.includes.contains(&canonical_path) {
+            return Err(origin_error(
+                &origin,
+                line,
+                format!("{relative_path} is already being included"),
+            ));
+        }
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            origin_error(
+                &origin,
+                line,
+                format!("Failed to read {relative_path}: {e}"),
+            )
+        })?;
+        let lines: Vec<String> = contents.split("\n").map(|s| s.trim().to_string()).collect();
+        if !self.included.contains(&path) {
+            self.included.push(path.clone());
+        }
+
+        let expansion = Rc::new(Expansion {
+            origin,
+            macro_name: None,
+        });
+        self.includes.push(canonical_path);
+        self.process(file_lines(&lines, Rc::new(path), Some(expansion)), depth)?;
+        self.includes.pop();
+        Ok(())
+    }
+
+    /// Expands a use of a macro, substituting the arguments for its parameters, and giving its
+    /// labels unique names.
+    fn expand(
+        self: &mut Self,
+        name: &str,
+        args: &str,
+        line: &str,
+        origin: Origin,
+        depth: usize,
+    ) -> Result<(), LineParsingError> {
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(origin_error(
+                &origin,
+                line,
+                format!(
+                    "Macros expanded more than {MAX_EXPANSION_DEPTH} deep, {name} may use itself"
+                ),
+            ));
+        }
+        let called = &self.macros[name];
+        let arguments = split_arguments(args);
+        if arguments.len() != called.parameters.len() {
+            return Err(origin_error(
+                &origin,
+                line,
+                format!(
+                    "{name} expects {} arguments, got {}",
+                    called.parameters.len(),
+                    arguments.len()
+                ),
+            ));
+        }
+
+        self.expansions += 1;
+        let expansion = Rc::new(Expansion {
+            origin,
+            macro_name: Some(name.to_string()),
+        });
+        let mut body = vec![];
+        for (body_line, body_origin) in &called.body {
+            let mut expanded = body_line.clone();
+            for (parameter, argument) in called.parameters.iter().zip(&arguments) {
+                expanded = expanded.replace(
+                    &format!("{PARAMETER_BEGIN}{parameter}{PARAMETER_END}"),
+                    argument,
+                );
+            }
+            let expanded = rename_symbols(&expanded, |symbol| {
+                called
+                    .labels
+                    .contains(symbol)
+                    .then(|| format!("{symbol}${name}${}", self.expansions))
+            });
+            let body_origin = Origin {
+                expanded_from: Some(expansion.clone()),
+                ..body_origin.clone()
+            };
+            body.push((expanded, body_origin));
+        }
+        self.process(body, depth + 1)
+    }
+}
+
+/// Pairs the lines of a file with their [Origin].
+fn file_lines(
+    lines: &[String],
+    path: Rc<PathBuf>,
+    expanded_from: Option<Rc<Expansion>>,
+) -> Vec<(String, Origin)> {
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let origin = Origin {
+                path: path.clone(),
+                line: i,
+                expanded_from: expanded_from.clone(),
+            };
+            (line.clone(), origin)
+        })
+        .collect()
+}
+
+/// Creates an error for a line, reported at the line of the opened file it was expanded from.
+fn origin_error(origin: &Origin, line: &str, reason: String) -> LineParsingError {
+    let reason = match origin.expanded_from {
+        Some(_) => format!("{reason}, at {origin}"),
+        None => reason,
+    };
+    LineParsingError::InvalidLine(origin.root_line() as u16 + 1, line.to_string(), reason)
+}
+
+/// Splits the comma separated parameters or arguments of a macro.
+fn split_arguments(args: &str) -> Vec<String> {
+    if args.trim().is_empty() {
+        return vec![];
+    }
+    args.split(',').map(|arg| arg.trim().to_string()).collect()
+}
+
+/// Replaces the symbols in a line of code for which `rename` gives a new name. Comments and
+/// character literals are left untouched.
+fn rename_symbols<F: Fn(&str) -> Option<String>>(line: &str, rename: F) -> String {
+    let (code, comment) = split_comment(line);
+    let mut renamed = String::new();
+    let mut symbol = String::new();
+    let mut in_literal = false;
+    for c in code.chars() {
+        if !in_literal && is_symbol_char(c) {
+            symbol.push(c);
+            continue;
+        }
+        renamed.push_str(&rename(&symbol).unwrap_or(symbol.clone()));
+        symbol.clear();
+        if c == '\'' {
+            in_literal = !in_literal;
+        }
+        renamed.push(c);
+    }
+    renamed.push_str(&rename(&symbol).unwrap_or(symbol));
+    renamed.push_str(comment);
+    renamed
+}
+
+/// The part of a line of source code before its comment.
+fn code(line: &str) -> &str {
+    split_comment(line).0.trim()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::on_large_stack;
+
+    fn program<S: AsRef<str>>(source: &[S]) -> [String; MAX_INSTRUCTIONS] {
+        let mut lines = [const { String::new() }; MAX_INSTRUCTIONS];
+        for (i, line) in source.iter().enumerate() {
+            lines[i] = line.as_ref().to_string();
+        }
+        lines
+    }
+
+    /// Writes files to a directory of their own, returning the path of the first.
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("hack_preprocessor_{name}"));
+        fs::create_dir_all(&directory).unwrap();
+        for (file, contents) in files {
+            fs::write(directory.join(file), contents).unwrap();
+        }
+        directory.join(files[0].0)
+    }
+
+    fn located(error: LineParsingError) -> (u16, String) {
+        let LineParsingError::InvalidLine(line_number, _, reason) = error;
+        (line_number, reason)
+    }
+
+    #[test]
+    fn includes_are_expanded() {
+        on_large_stack(|| {
+            let path = write_files(
+                "includes",
+                &[("Main.asm", ""), ("Lib.asm", "@1\nD=A // from Lib")],
+            );
+            let preprocessed =
+                preprocess(&program(&["@0", "#include \"Lib.asm\"", "0;JMP"]), &path).unwrap();
+            assert_eq!(
+                preprocessed.lines[..4],
+                ["@0", "@1", "D=A // from Lib", "0;JMP"]
+            );
+            assert_eq!(preprocessed.origins[2].line, 1);
+            assert_eq!(preprocessed.origins[2].root_line(), 1);
+            assert_eq!(preprocessed.origins[3].root_line(), 2);
+            assert_eq!(preprocessed.included, [path.with_file_name("Lib.asm")]);
+        });
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        on_large_stack(|| {
+            let path = write_files(
+                "cycles",
+                &[
+                    ("A.asm", "#include \"B.asm\""),
+                    ("B.asm", "@1\n#include \"A.asm\""),
+                ],
+            );
+            let error = preprocess(&program(&["#include \"B.asm\""]), &path).unwrap_err();
+            let (line_number, reason) = located(error);
+            assert_eq!(line_number, 1);
+            let included = path.with_file_name("B.asm");
+            assert_eq!(
+                reason,
+                format!(
+                    "A.asm is already being included, at {}:2, included from {}:1",
+                    included.display(),
+                    path.display()
+                )
+            );
+
+            let error = preprocess(&program(&["#include \"A.asm\""]), &path).unwrap_err();
+            assert_eq!(located(error).1, "A.asm is already being included");
+        });
+    }
+
+    #[test]
+    fn recursive_macros_are_errors() {
+        on_large_stack(|| {
+            let lines = program(&["#macro LOOP", "LOOP", "#endmacro", "@0", "LOOP"]);
+            let error = preprocess(&lines, Path::new("Main.asm")).unwrap_err();
+            let (line_number, reason) = located(error);
+            assert_eq!(line_number, 5);
+            assert!(reason.starts_with(&format!(
+                "Macros expanded more than {MAX_EXPANSION_DEPTH} deep, LOOP may use itself"
+            )));
+        });
+    }
+
+    #[test]
+    fn macros_may_be_nested_up_to_the_limit() {
+        on_large_stack(|| {
+            let mut source = vec![
+                String::from("#macro M0"),
+                String::from("D=D+1"),
+                String::from("#endmacro"),
+            ];
+            for i in 1..MAX_EXPANSION_DEPTH {
+                source.push(format!("#macro M{i}"));
+                source.push(format!("M{}", i - 1));
+                source.push(String::from("#endmacro"));
+            }
+            source.push(format!("M{}", MAX_EXPANSION_DEPTH - 1));
+            let preprocessed = preprocess(&program(&source), Path::new("Main.asm")).unwrap();
+            assert_eq!(preprocessed.lines[0], "D=D+1");
+            assert_eq!(preprocessed.lines[1], "");
+
+            source.push(String::from("#macro TOO_DEEP"));
+            source.push(format!("M{}", MAX_EXPANSION_DEPTH - 1));
+            source.push(String::from("#endmacro"));
+            source.push(String::from("TOO_DEEP"));
+            assert!(preprocess(&program(&source), Path::new("Main.asm")).is_err());
+        });
+    }
+
+    #[test]
+    fn macro_labels_are_renamed_in_each_expansion() {
+        on_large_stack(|| {
+            let lines = program(&[
+                "#macro WAIT COUNT",
+                "@{COUNT}",
+                "D=A",
+                "(LOOP)",
+                "D=D-1 // LOOP until zero",
+                "@LOOP",
+                "D;JGT",
+                "#endmacro",
+                "WAIT 10",
+                "WAIT 20",
+                "(LOOP)",
+            ]);
+            let preprocessed = preprocess(&lines, Path::new("Main.asm")).unwrap();
+            assert_eq!(
+                preprocessed.lines[..13],
+                [
+                    "@10",
+                    "D=A",
+                    "(LOOP$WAIT$1)",
+                    "D=D-1 // LOOP until zero",
+                    "@LOOP$WAIT$1",
+                    "D;JGT",
+                    "@20",
+                    "D=A",
+                    "(LOOP$WAIT$2)",
+                    "D=D-1 // LOOP until zero",
+                    "@LOOP$WAIT$2",
+                    "D;JGT",
+                    "(LOOP)",
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn renaming_skips_comments_and_characters() {
+        let rename = |symbol: &str| (symbol == "A").then(|| String::from("B"));
+        assert_eq!(rename_symbols("@A", rename), "@B");
+        assert_eq!(rename_symbols("A=A+1", rename), "B=B+1");
+        assert_eq!(rename_symbols("@AA", rename), "@AA");
+        assert_eq!(rename_symbols("@'A'", rename), "@'A'");
+        assert_eq!(rename_symbols("@A // A", rename), "@B // A");
+        assert_eq!(rename_symbols("", rename), "");
+    }
+
+    #[test]
+    fn errors_are_located_in_the_opened_file() {
+        on_large_stack(|| {
+            let lines = program(&["@0", "#macro BAD", "D=D+2", "#endmacro", "D=A", "BAD"]);
+            let mut address_table = SymbolTable::new();
+            let error = assemble(&lines, Path::new("Main.asm"), &mut address_table).unwrap_err();
+            let LineParsingError::InvalidLine(line_number, line, reason) = error;
+            assert_eq!(line_number, 6);
+            assert_eq!(line, "D=D+2");
+            assert!(reason.ends_with(", at Main.asm:3, in macro BAD used at Main.asm:6"));
+
+            let plain = preprocess(&program(&["@0", "D=D+2"]), Path::new("Main.asm")).unwrap();
+            let error =
+                LineParsingError::InvalidLine(2, String::from("D=D+2"), String::from("Bad"));
+            assert_eq!(located(plain.locate(error)), (2, String::from("Bad")));
+        });
+    }
+
+    #[test]
+    fn source_maps_refer_to_the_opened_file() {
+        on_large_stack(|| {
+            let lines = program(&["#macro TWICE", "D=D+1", "D=D+1", "#endmacro", "@0", "TWICE"]);
+            let mut address_table = SymbolTable::new();
+            let program = assemble(&lines, Path::new("Main.asm"), &mut address_table).unwrap();
+            assert_eq!(program.source_map[..3], [4, 5, 5]);
+        });
+    }
+}
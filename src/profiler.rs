@@ -0,0 +1,177 @@
+use std::fmt::Write;
+
+use crate::instructions::Instruction;
+use crate::symbol_table::SymbolTable;
+use crate::MAX_INSTRUCTIONS;
+
+/// The name given to the instructions that come before the first label of the program.
+const NO_LABEL: &'static str = "(start)";
+/// The number of individual addresses listed in the [Profiler::report].
+const REPORT_HOTTEST: usize = 20;
+
+/// Counts how many times each ROM address has been executed. Profiling is opt in, by placing a
+/// [Profiler] into the [crate::hack_cpu::CPUState].
+#[derive(Debug)]
+pub struct Profiler {
+    pub counts: Vec<u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; MAX_INSTRUCTIONS],
+        }
+    }
+
+    /// Records a single execution of the instruction at `pc`.
+    pub fn record(self: &mut Self, pc: u16) {
+        if let Some(count) = self.counts.get_mut(pc as usize) {
+            *count += 1;
+        }
+    }
+
+    /// The total number of instructions executed.
+    pub fn total(self: &Self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The highest count of any single address.
+    pub fn max(self: &Self) -> u64 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Combines the counts into totals per label, where each address belongs to the closest label
+    /// at or before it. The totals are sorted from the most to the least executed.
+    pub fn label_totals(self: &Self, address_table: &SymbolTable) -> Vec<(String, u64)> {
+        let labels = address_table.labels_by_address();
+        let mut totals: Vec<(String, u64)> = vec![];
+        let mut current = (NO_LABEL, 0);
+        let mut next_label = labels.iter().peekable();
+        for (address, count) in self.counts.iter().enumerate() {
+            while let Some((label_address, label)) = next_label.peek() {
+                if *label_address as usize > address {
+                    break;
+                }
+                if current.1 > 0 {
+                    totals.push((current.0.to_string(), current.1));
+                }
+                current = (*label, 0);
+                next_label.next();
+            }
+            current.1 += count;
+        }
+        if current.1 > 0 {
+            totals.push((current.0.to_string(), current.1));
+        }
+        totals.sort_by(|a, b| b.1.cmp(&a.1));
+        totals
+    }
+
+    /// Produces a report of the per label totals, followed by the most executed addresses.
+    pub fn report(
+        self: &Self,
+        address_table: &SymbolTable,
+        instructions: &[Instruction; MAX_INSTRUCTIONS],
+    ) -> String {
+        let total = self.total().max(1) as f64;
+        let mut report = String::new();
+        let _ = writeln!(report, "Profile of {} instructions", self.total());
+        let _ = writeln!(report, "{:<24}{:>14}{:>9}", "Label", "Count", "%");
+        for (label, count) in self.label_totals(address_table) {
+            let _ = writeln!(
+                report,
+                "{:<24}{:>14}{:>8.2}%",
+                label,
+                count,
+                count as f64 * 100.0 / total
+            );
+        }
+
+        let mut hottest: Vec<(usize, u64)> = self
+            .counts
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        hottest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(report);
+        let _ = writeln!(
+            report,
+            "{:<9}{:<15}{:>14}{:>9}",
+            "Address", "Instruction", "Count", "%"
+        );
+        for (address, count) in hottest.into_iter().take(REPORT_HOTTEST) {
+            let _ = writeln!(
+                report,
+                "{:<9}{:<15}{:>14}{:>8.2}%",
+                address,
+                instructions[address].to_string(),
+                count,
+                count as f64 * 100.0 / total
+            );
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn labelled(labels: &[(&str, u16)]) -> SymbolTable {
+        let mut address_table = SymbolTable::new();
+        for (label, address) in labels {
+            address_table.table.insert(label.to_string(), *address);
+            address_table.labels.insert(label.to_string());
+        }
+        address_table
+    }
+
+    fn totals(counts: &[(u16, u64)], labels: &[(&str, u16)]) -> Vec<(String, u64)> {
+        let mut profiler = Profiler::new();
+        for (address, count) in counts {
+            profiler.counts[*address as usize] = *count;
+        }
+        profiler.label_totals(&labelled(labels))
+    }
+
+    fn named(totals: &[(&str, u64)]) -> Vec<(String, u64)> {
+        totals.iter().map(|(l, c)| (l.to_string(), *c)).collect()
+    }
+
+    #[test]
+    fn addresses_belong_to_the_closest_label_before_them() {
+        let counts = [(0, 1), (1, 1), (2, 5), (4, 10), (5, 10), (9, 3)];
+        let labels = [("LOOP", 2), ("END", 9)];
+        assert_eq!(
+            totals(&counts, &labels),
+            named(&[("LOOP", 25), ("END", 3), ("(start)", 2)])
+        );
+    }
+
+    #[test]
+    fn unexecuted_labels_are_left_out() {
+        // SKIPPED is never executed, and FIRST shares its address with SECOND
+        let counts = [(3, 4), (7, 4)];
+        let labels = [("FIRST", 3), ("SECOND", 3), ("SKIPPED", 5), ("LAST", 7)];
+        assert_eq!(
+            totals(&counts, &labels),
+            named(&[("SECOND", 4), ("LAST", 4)])
+        );
+        assert!(totals(&[], &labels).is_empty());
+    }
+
+    #[test]
+    fn counts_are_recorded_within_the_rom() {
+        let mut profiler = Profiler::new();
+        profiler.record(0);
+        profiler.record(0);
+        profiler.record(MAX_INSTRUCTIONS as u16);
+        assert_eq!((profiler.total(), profiler.max()), (2, 2));
+        assert_eq!(
+            profiler.label_totals(&SymbolTable::new()),
+            named(&[("(start)", 2)])
+        );
+    }
+}
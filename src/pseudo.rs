@@ -0,0 +1,203 @@
+use crate::instructions::Jump;
+
+/// The memory operand of a pseudo-instruction, such as `RAM[5]` or `RAM[i]`.
+const RAM_BEGIN: &'static str = "RAM[";
+const RAM_END: char = ']';
+/// The registers that can be used as operands. `M` refers to the RAM at the address in `A`.
+const REGISTERS: [&'static str; 3] = ["A", "D", "M"];
+/// The values a C instruction can write without first loading them into `A`.
+const DIRECT_VALUES: [&'static str; 3] = ["0", "1", "-1"];
+const STACK_POINTER: &'static str = "SP";
+
+/// An operand of a pseudo-instruction.
+enum Operand<'a> {
+    Register(&'a str),
+    Ram(&'a str),
+    Value(&'a str),
+}
+
+impl<'a> Operand<'a> {
+    fn new(operand: &'a str) -> Self {
+        if REGISTERS.contains(&operand) {
+            Operand::Register(operand)
+        } else if let Some(address) = operand
+            .strip_prefix(RAM_BEGIN)
+            .and_then(|rest| rest.strip_suffix(RAM_END))
+        {
+            Operand::Ram(address.trim())
+        } else {
+            Operand::Value(operand)
+        }
+    }
+}
+
+/// Expands a pseudo-instruction, such as `LD D, 42`, into the lines of Hack assembly that perform
+/// it. Returns [None] if the line is not a pseudo-instruction.
+pub fn expand(line: &str) -> Option<Result<Vec<String>, String>> {
+    let (mnemonic, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let operands: Vec<Operand> = match operands.trim() {
+        "" => vec![],
+        operands => operands
+            .split(',')
+            .map(|o| Operand::new(o.trim()))
+            .collect(),
+    };
+    let expansion = match (mnemonic, operands.as_slice()) {
+        ("LD", [target, source]) => load(target, source),
+        ("INC", [target]) => increment(target, "+"),
+        ("DEC", [target]) => increment(target, "-"),
+        ("JMP", [Operand::Value(label)]) => Ok(vec![format!("@{label}"), String::from("0;JMP")]),
+        (jump, [Operand::Value(label)]) if Jump::MNEMONICS.contains(&jump) => {
+            Ok(vec![format!("@{label}"), format!("D;{jump}")])
+        }
+        ("PUSH", [Operand::Register("D")]) => Ok(push("D")),
+        ("PUSH", [Operand::Value(value)]) if DIRECT_VALUES.contains(value) => Ok(push(value)),
+        ("POP", [Operand::Register(register @ ("A" | "D"))]) => Ok(vec![
+            format!("@{STACK_POINTER}"),
+            String::from("AM=M-1"),
+            format!("{register}=M"),
+        ]),
+        ("LD", _) => Err(String::from("Expected LD TARGET, SOURCE")),
+        ("INC" | "DEC", _) => Err(format!("Expected {mnemonic} TARGET")),
+        (jump, _) if Jump::MNEMONICS.contains(&jump) => Err(format!("Expected {jump} LABEL")),
+        ("PUSH", _) => Err(String::from("Only D, 0, 1 and -1 can be pushed")),
+        ("POP", _) => Err(String::from("Only A and D can be popped into")),
+        _ => return None,
+    };
+    Some(expansion)
+}
+
+fn load(target: &Operand, source: &Operand) -> Result<Vec<String>, String> {
+    match (target, source) {
+        (Operand::Register("A"), Operand::Value(value)) => Ok(vec![format!("@{value}")]),
+        (Operand::Register("D"), Operand::Value(value)) => {
+            Ok(vec![format!("@{value}"), String::from("D=A")])
+        }
+        (Operand::Register(register), Operand::Value(value)) if DIRECT_VALUES.contains(value) => {
+            Ok(vec![format!("{register}={value}")])
+        }
+        (Operand::Register(register), Operand::Register(source)) => {
+            Ok(vec![format!("{register}={source}")])
+        }
+        (Operand::Register(register @ ("A" | "D")), Operand::Ram(address)) => {
+            Ok(vec![format!("@{address}"), format!("{register}=M")])
+        }
+        (Operand::Ram(address), Operand::Register("D")) => {
+            Ok(vec![format!("@{address}"), String::from("M=D")])
+        }
+        (Operand::Ram(address), Operand::Value(value)) if DIRECT_VALUES.contains(value) => {
+            Ok(vec![format!("@{address}"), format!("M={value}")])
+        }
+        (Operand::Ram(_), _) | (Operand::Register("M"), _) => Err(String::from(
+            "Only D, 0, 1 and -1 can be stored in the RAM, load other values into D first",
+        )),
+        (Operand::Value(value), _) | (Operand::Register(value), _) => {
+            Err(format!("{value} cannot be loaded into"))
+        }
+    }
+}
+
+/// Adds or subtracts 1, according to the `operator`.
+fn increment(target: &Operand, operator: &str) -> Result<Vec<String>, String> {
+    match target {
+        Operand::Register(register) => Ok(vec![format!("{register}={register}{operator}1")]),
+        Operand::Ram(address) => Ok(vec![format!("@{address}"), format!("M=M{operator}1")]),
+        Operand::Value(value) => Err(format!("{value} cannot be changed")),
+    }
+}
+
+/// Pushes a value that a C instruction can compute without using `A`.
+fn push(value: &str) -> Vec<String> {
+    vec![
+        format!("@{STACK_POINTER}"),
+        String::from("A=M"),
+        format!("M={value}"),
+        format!("@{STACK_POINTER}"),
+        String::from("M=M+1"),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn expanded(line: &str) -> Result<Vec<String>, String> {
+        expand(line).unwrap()
+    }
+
+    /// The rows of the table of pseudo-instructions in the README.
+    #[test]
+    fn expansions_match_the_readme() {
+        let rows: [(&str, &[&str]); 20] = [
+            ("LD D, 42", &["@42", "D=A"]),
+            ("LD A, 42", &["@42"]),
+            ("LD D, RAM[i]", &["@i", "D=M"]),
+            ("LD A, RAM[ 5 ]", &["@5", "A=M"]),
+            ("LD RAM[i], D", &["@i", "M=D"]),
+            ("LD RAM[i], 0", &["@i", "M=0"]),
+            ("LD RAM[i], -1", &["@i", "M=-1"]),
+            ("LD D, A", &["D=A"]),
+            ("LD M, D", &["M=D"]),
+            ("LD M, 1", &["M=1"]),
+            ("INC RAM[i]", &["@i", "M=M+1"]),
+            ("DEC D", &["D=D-1"]),
+            ("INC A", &["A=A+1"]),
+            ("JMP LOOP", &["@LOOP", "0;JMP"]),
+            ("JEQ LOOP", &["@LOOP", "D;JEQ"]),
+            ("JGT END", &["@END", "D;JGT"]),
+            ("PUSH D", &["@SP", "A=M", "M=D", "@SP", "M=M+1"]),
+            ("PUSH -1", &["@SP", "A=M", "M=-1", "@SP", "M=M+1"]),
+            ("POP D", &["@SP", "AM=M-1", "D=M"]),
+            ("POP A", &["@SP", "AM=M-1", "A=M"]),
+        ];
+        for (line, instructions) in rows {
+            assert_eq!(expanded(line).unwrap(), instructions, "{line}");
+        }
+    }
+
+    #[test]
+    fn other_lines_are_not_expanded() {
+        assert!(expand("D=A").is_none());
+        assert!(expand("@LOOP").is_none());
+        assert!(expand("0;JMP").is_none());
+        assert!(expand("LOAD D, 1").is_none());
+    }
+
+    #[test]
+    fn arity_errors() {
+        let errors = [
+            ("LD D", "Expected LD TARGET, SOURCE"),
+            ("LD D, 1, 2", "Expected LD TARGET, SOURCE"),
+            ("INC", "Expected INC TARGET"),
+            ("DEC D, A", "Expected DEC TARGET"),
+            ("JMP", "Expected JMP LABEL"),
+            ("JNE A, B", "Expected JNE LABEL"),
+            ("JLT D", "Expected JLT LABEL"),
+            ("PUSH", "Only D, 0, 1 and -1 can be pushed"),
+            ("POP", "Only A and D can be popped into"),
+        ];
+        for (line, error) in errors {
+            assert_eq!(expanded(line), Err(String::from(error)), "{line}");
+        }
+    }
+
+    #[test]
+    fn operand_errors() {
+        let stored = "Only D, 0, 1 and -1 can be stored in the RAM, load other values into D first";
+        let errors = [
+            ("LD RAM[i], 42", stored),
+            ("LD RAM[i], RAM[j]", stored),
+            ("LD RAM[i], A", stored),
+            ("LD M, 42", stored),
+            ("LD 42, D", "42 cannot be loaded into"),
+            ("INC 5", "5 cannot be changed"),
+            ("PUSH A", "Only D, 0, 1 and -1 can be pushed"),
+            ("PUSH 2", "Only D, 0, 1 and -1 can be pushed"),
+            ("POP M", "Only A and D can be popped into"),
+            ("POP RAM[i]", "Only A and D can be popped into"),
+        ];
+        for (line, error) in errors {
+            assert_eq!(expanded(line), Err(String::from(error)), "{line}");
+        }
+    }
+}
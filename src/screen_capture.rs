@@ -0,0 +1,169 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, ImageFormat, RgbImage};
+
+use crate::hack_cpu::CPUState;
+use crate::hack_gui::hack_to_rgba;
+use crate::{SCREEN_HEIGHT, SCREEN_LENGTH, SCREEN_LOCATION, SCREEN_WIDTH};
+
+pub const PNG_FILE_EXTENSION: &'static str = "png";
+pub const GIF_FILE_EXTENSION: &'static str = "gif";
+/// The number of cycles between the frames of a recording, unless another is chosen.
+pub const DEFAULT_RECORD_INTERVAL: u64 = 100_000;
+/// How long each frame of an animated GIF is shown for.
+const FRAME_DELAY_MS: u32 = 40;
+/// The most frames merged into one while the screen does not change, which keeps the delay of the
+/// merged frame within what a GIF can hold.
+const MAX_MERGED_FRAMES: u32 = 1000;
+/// How hard the GIF encoder works to reduce the colours of a frame. Screens only ever have black
+/// and white, so the fastest setting loses nothing.
+const GIF_SPEED: i32 = 30;
+
+/// Returns the image shown on the screen, from the `SCREEN` region of the RAM.
+pub fn screen_image(cpu: &CPUState) -> RgbImage {
+    let framebuffer = hack_to_rgba(&cpu.ram[SCREEN_LOCATION..SCREEN_LOCATION + SCREEN_LENGTH]);
+    RgbImage::from_raw(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, framebuffer)
+        .expect("The framebuffer should have the size of the screen")
+}
+
+/// Saves the image shown on the screen to a PNG file.
+pub fn save_screenshot(cpu: &CPUState, path: &Path) -> Result<(), String> {
+    screen_image(cpu)
+        .save_with_format(path, ImageFormat::Png)
+        .map_err(|e| format!("Failed to save screenshot {}: {e}", path.display()))
+}
+
+/// Where the frames of a [Recorder] are written.
+enum Output {
+    /// An animated GIF, along with the last frame captured and how many times in a row it was
+    /// captured, since frames are only written once the screen changes.
+    Gif {
+        encoder: GifEncoder<BufWriter<File>>,
+        pending: Option<(RgbImage, u32)>,
+    },
+    /// Numbered PNG files, named after the stem of the path, such as `frame_000001.png` for
+    /// `frame.png`.
+    Png { directory: PathBuf, stem: String },
+}
+
+/// Records the screen to an animated GIF, or to a sequence of numbered PNG files, capturing a
+/// frame every `interval` cycles.
+pub struct Recorder {
+    pub path: PathBuf,
+    pub interval: u64,
+    output: Output,
+    /// The cycles run since the last frame was captured.
+    cycles: u64,
+    pub frames: u64,
+}
+
+impl Recorder {
+    /// Starts a recording to the path, which is an animated GIF if it ends in `.gif`, or numbered
+    /// PNG files otherwise.
+    pub fn new(path: &Path, interval: u64) -> Result<Self, String> {
+        if interval == 0 {
+            return Err(String::from(
+                "The recording interval must be at least 1 cycle",
+            ));
+        }
+        let output = if path.extension().is_some_and(|e| e == GIF_FILE_EXTENSION) {
+            let file = File::create(path)
+                .map_err(|e| format!("Failed to create recording {}: {e}", path.display()))?;
+            let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), GIF_SPEED);
+            encoder
+                .set_repeat(Repeat::Infinite)
+                .map_err(|e| format!("Failed to write recording {}: {e}", path.display()))?;
+            Output::Gif {
+                encoder,
+                pending: None,
+            }
+        } else {
+            let stem = path
+                .file_stem()
+                .map_or(String::from("frame"), |s| s.to_string_lossy().into_owned());
+            let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+            if !directory.as_os_str().is_empty() {
+                fs::create_dir_all(&directory).map_err(|e| {
+                    format!("Failed to create directory {}: {e}", directory.display())
+                })?;
+            }
+            Output::Png { directory, stem }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            interval,
+            output,
+            cycles: 0,
+            frames: 0,
+        })
+    }
+
+    /// Called after every cycle, capturing a frame once `interval` cycles have been run since the
+    /// last one.
+    pub fn tick(self: &mut Self, cpu: &CPUState) -> Result<(), String> {
+        self.cycles += 1;
+        if self.cycles < self.interval {
+            return Ok(());
+        }
+        self.capture(cpu)
+    }
+
+    /// Captures a frame of the screen now.
+    pub fn capture(self: &mut Self, cpu: &CPUState) -> Result<(), String> {
+        self.cycles = 0;
+        self.frames += 1;
+        let image = screen_image(cpu);
+        match &mut self.output {
+            Output::Gif { encoder, pending } => match pending {
+                Some((previous, repeats)) if *previous == image && *repeats < MAX_MERGED_FRAMES => {
+                    *repeats += 1;
+                    Ok(())
+                }
+                _ => {
+                    let result = match pending.take() {
+                        Some((previous, repeats)) => write_gif_frame(encoder, previous, repeats),
+                        None => Ok(()),
+                    };
+                    *pending = Some((image, 1));
+                    result.map_err(|e| {
+                        format!("Failed to write recording {}: {e}", self.path.display())
+                    })
+                }
+            },
+            Output::Png { directory, stem } => {
+                let path =
+                    directory.join(format!("{stem}_{:06}.{PNG_FILE_EXTENSION}", self.frames));
+                image
+                    .save_with_format(&path, ImageFormat::Png)
+                    .map_err(|e| format!("Failed to save frame {}: {e}", path.display()))
+            }
+        }
+    }
+
+    /// Ends the recording, writing the frames that are still pending.
+    pub fn finish(self: Self) -> Result<(), String> {
+        if let Output::Gif {
+            mut encoder,
+            pending: Some((image, repeats)),
+        } = self.output
+        {
+            write_gif_frame(&mut encoder, image, repeats)
+                .map_err(|e| format!("Failed to write recording {}: {e}", self.path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a frame to a GIF, shown for as long as the number of frames it stands for.
+fn write_gif_frame(
+    encoder: &mut GifEncoder<BufWriter<File>>,
+    image: RgbImage,
+    repeats: u32,
+) -> Result<(), image::ImageError> {
+    let delay = Delay::from_numer_denom_ms(FRAME_DELAY_MS * repeats, 1);
+    let image = DynamicImage::ImageRgb8(image).into_rgba8();
+    encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))
+}
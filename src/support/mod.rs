@@ -23,6 +23,8 @@ use std::time::Instant;
 mod clipboard;
 
 pub const FONT_SIZE: f32 = 13.0;
+/// The range of arrow characters, ended by a zero as imgui expects.
+const ARROW_GLYPHS: [u32; 3] = [0x2190, 0x21FF, 0];
 
 #[allow(dead_code)] // annoyingly, RA yells that this is unusued
 pub fn simple_init<F: FnMut(&mut bool, &mut Ui, &mut Renderer, &Option<Key>) + 'static>(
@@ -229,6 +231,17 @@ pub fn create_context() -> imgui::Context {
                 ..FontConfig::default()
             }),
         },
+        FontSource::TtfData {
+            data: include_bytes!("../../resources/mplus-1p-regular.ttf"),
+            size_pixels: FONT_SIZE,
+            config: Some(FontConfig {
+                oversample_h: 4,
+                oversample_v: 4,
+                // Arrows, which mark jump targets in the ROM view
+                glyph_ranges: FontGlyphRanges::from_slice(&ARROW_GLYPHS),
+                ..FontConfig::default()
+            }),
+        },
     ]);
     imgui.set_ini_filename(None);
 
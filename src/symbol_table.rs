@@ -1,11 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Represents the symbol table used for translating A instructions from names to locations in the
-/// RAM.
-#[derive(Debug)]
+/// RAM. The names in `labels` are those which refer to locations in the ROM instead, and the names
+/// in `constants` are plain values, defined by the `.equ` directive.
+#[derive(Debug, Clone)]
 pub struct SymbolTable {
     pub table: HashMap<String, u16>,
     pub current_variable: u16,
+    pub labels: HashSet<String>,
+    pub constants: HashSet<String>,
 }
 
 impl SymbolTable {
@@ -45,6 +48,32 @@ impl SymbolTable {
         Self {
             table: t,
             current_variable: 16,
+            labels: HashSet::new(),
+            constants: HashSet::new(),
         }
     }
+
+    /// Describes what a symbol refers to, such as `ROM[4]` for a label, `RAM[16]` for a variable,
+    /// or the value of a constant.
+    pub fn describe(self: &Self, name: &str) -> Option<String> {
+        let value = self.table.get(name)?;
+        Some(if self.labels.contains(name) {
+            format!("ROM[{value}]")
+        } else if self.constants.contains(name) {
+            value.to_string()
+        } else {
+            format!("RAM[{value}]")
+        })
+    }
+
+    /// Returns the labels of the program, along with their ROM addresses, sorted by address.
+    pub fn labels_by_address(self: &Self) -> Vec<(u16, &str)> {
+        let mut labels: Vec<(u16, &str)> = self
+            .labels
+            .iter()
+            .map(|label| (self.table[label], label.as_str()))
+            .collect();
+        labels.sort();
+        labels
+    }
 }
@@ -0,0 +1,289 @@
+use std::fs;
+use std::path::Path;
+
+use crate::golden;
+use crate::hack_cpu::CPUState;
+use crate::instructions::Instruction;
+use crate::monitor;
+use crate::parser::split_comment;
+use crate::MAX_INSTRUCTIONS;
+
+/// The most times the commands of a `repeat` block run, counting the blocks around it, so that a
+/// typo cannot lock up the runner.
+const MAX_REPEATS: u64 = 100_000_000;
+
+pub const HELP: &'static str =
+    "Test scripts are commands separated by ; or , in the style of the .tst scripts of
+the course, with // comments. Besides the monitor commands, such as set RAM[0] 2, break END and
+run, a script may use:
+    load FILE               Load a program, relative to the script
+    ticktock                Execute one instruction
+    repeat N { ... }        Run the commands within the braces N times
+    compare-screen FILE [DIFF]
+                            Fail unless the screen matches the reference PNG image, relative to
+                            the script, saving an image of the differing pixels to DIFF (default
+                            FILE.diff.png beside the reference)";
+
+/// A command of a test script.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Single(String),
+    Repeat(u64, Vec<Command>),
+}
+
+/// The outcome of a test script that ran to the end.
+pub struct Report {
+    /// The number of commands run, counting each repetition.
+    pub commands: u64,
+    /// The number of screen comparisons that passed.
+    pub screens: u64,
+}
+
+/// Runs a test script, returning an error that names the failing command if any command fails, or
+/// the screen does not match a reference image.
+pub fn run(path: &Path) -> Result<Report, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read script {}: {e}", path.display()))?;
+    let source: Vec<&str> = source.lines().map(|line| split_comment(line).0).collect();
+    let text = source.join("\n");
+    let commands = parse_block(&mut text.as_str(), None)?;
+    let mut runner = Runner {
+        directory: path.parent().unwrap_or(Path::new("")),
+        cpu: CPUState::new(),
+        instructions: [const { Instruction::None }; MAX_INSTRUCTIONS],
+        report: Report {
+            commands: 0,
+            screens: 0,
+        },
+    };
+    runner.run_all(&commands)?;
+    Ok(runner.report)
+}
+
+/// Parses the commands up to the end of the text, or for a nested block, up to its closing brace.
+/// The `repeats` of a nested block are the times it runs, which is the product of its count and
+/// those of the blocks around it. The text is advanced past the commands parsed.
+fn parse_block(text: &mut &str, repeats: Option<u64>) -> Result<Vec<Command>, String> {
+    let nested = repeats.is_some();
+    let mut commands = vec![];
+    loop {
+        let Some(index) = text.find([';', ',', '{', '}']) else {
+            if nested {
+                return Err(String::from("Expected } at the end of a repeat block"));
+            }
+            push_command(&mut commands, text);
+            return Ok(commands);
+        };
+        let (statement, rest) = text.split_at(index);
+        let separator = rest.as_bytes()[0];
+        *text = &rest[1..];
+        match separator {
+            b'{' => {
+                let count = statement
+                    .trim()
+                    .strip_prefix("repeat")
+                    .ok_or(format!(
+                        "Expected repeat N before {{, got {}",
+                        statement.trim()
+                    ))?
+                    .trim();
+                let count: u64 = count
+                    .parse()
+                    .map_err(|_| format!("Invalid repeat count {count}"))?;
+                let repeats = repeats
+                    .unwrap_or(1)
+                    .checked_mul(count)
+                    .filter(|repeats| *repeats <= MAX_REPEATS)
+                    .ok_or(format!(
+                        "A block may be repeated at most {MAX_REPEATS} times, counting the \
+                         blocks around it"
+                    ))?;
+                commands.push(Command::Repeat(count, parse_block(text, Some(repeats))?));
+            }
+            b'}' => {
+                if !nested {
+                    return Err(String::from("Unexpected } outside of a repeat block"));
+                }
+                push_command(&mut commands, statement);
+                return Ok(commands);
+            }
+            _ => push_command(&mut commands, statement),
+        }
+    }
+}
+
+fn push_command(commands: &mut Vec<Command>, statement: &str) {
+    let statement = statement
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if !statement.is_empty() {
+        commands.push(Command::Single(statement));
+    }
+}
+
+/// The machine a test script runs against, with paths found relative to the script.
+struct Runner<'a> {
+    directory: &'a Path,
+    cpu: CPUState,
+    instructions: [Instruction; MAX_INSTRUCTIONS],
+    report: Report,
+}
+
+impl Runner<'_> {
+    fn run_all(self: &mut Self, commands: &[Command]) -> Result<(), String> {
+        for command in commands {
+            match command {
+                Command::Single(command) => self
+                    .run_command(command)
+                    .map_err(|e| format!("{command}: {e}"))?,
+                Command::Repeat(count, body) => {
+                    for _ in 0..*count {
+                        self.run_all(body)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run_command(self: &mut Self, command: &str) -> Result<(), String> {
+        self.report.commands += 1;
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        match name {
+            "ticktock" => self.monitor("step"),
+            "load" => {
+                let path = self.directory.join(args);
+                self.monitor(&format!("load {}", path.display()))
+            }
+            "compare-screen" => {
+                let mut args = args.split(' ').filter(|arg| !arg.is_empty());
+                let reference = self
+                    .directory
+                    .join(args.next().ok_or("Expected compare-screen FILE [DIFF]")?);
+                let diff = match args.next() {
+                    Some(diff) => self.directory.join(diff),
+                    None => golden::default_diff_path(&reference),
+                };
+                match golden::compare_screen(&self.cpu, &reference, &diff)? {
+                    None => {
+                        self.report.screens += 1;
+                        Ok(())
+                    }
+                    Some(mismatch) => Err(mismatch.to_string()),
+                }
+            }
+            _ => self.monitor(command),
+        }
+    }
+
+    /// Runs a command of the monitor, discarding its output.
+    fn monitor(self: &mut Self, command: &str) -> Result<(), String> {
+        monitor::execute(command, &mut self.cpu, &mut self.instructions).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Vec<Command>, String> {
+        parse_block(&mut &*text, None)
+    }
+
+    fn single(command: &str) -> Command {
+        Command::Single(String::from(command))
+    }
+
+    #[test]
+    fn commands_are_separated_by_commas_and_semicolons() {
+        assert_eq!(
+            parse("load  Fill.asm,\nset RAM[0] 2;\n\nticktock ,, ; run"),
+            Ok(vec![
+                single("load Fill.asm"),
+                single("set RAM[0] 2"),
+                single("ticktock"),
+                single("run"),
+            ])
+        );
+        assert_eq!(parse(""), Ok(vec![]));
+        assert_eq!(parse(" ;\n, "), Ok(vec![]));
+    }
+
+    #[test]
+    fn repeat_blocks_nest() {
+        assert_eq!(
+            parse("repeat 2 { ticktock; repeat 3 { step, step } } run"),
+            Ok(vec![
+                Command::Repeat(
+                    2,
+                    vec![
+                        single("ticktock"),
+                        Command::Repeat(3, vec![single("step"), single("step")]),
+                    ]
+                ),
+                single("run"),
+            ])
+        );
+        assert_eq!(parse("repeat 0 {}"), Ok(vec![Command::Repeat(0, vec![])]));
+    }
+
+    #[test]
+    fn braces_must_be_balanced() {
+        assert_eq!(
+            parse("ticktock; }"),
+            Err(String::from("Unexpected } outside of a repeat block"))
+        );
+        assert_eq!(
+            parse("repeat 2 { ticktock } }"),
+            Err(String::from("Unexpected } outside of a repeat block"))
+        );
+        let missing = Err(String::from("Expected } at the end of a repeat block"));
+        assert_eq!(parse("repeat 2 { ticktock"), missing);
+        assert_eq!(parse("repeat 2 { repeat 2 { ticktock }"), missing);
+    }
+
+    #[test]
+    fn blocks_must_start_with_a_count() {
+        assert_eq!(
+            parse("{ ticktock }"),
+            Err(String::from("Expected repeat N before {, got "))
+        );
+        assert_eq!(
+            parse("ticktock { }"),
+            Err(String::from("Expected repeat N before {, got ticktock"))
+        );
+        assert_eq!(
+            parse("repeat -1 { }"),
+            Err(String::from("Invalid repeat count -1"))
+        );
+    }
+
+    #[test]
+    fn nested_repeats_are_bounded_together() {
+        let too_many = Err(format!(
+            "A block may be repeated at most {MAX_REPEATS} times, counting the blocks around it"
+        ));
+        assert!(parse(&format!("repeat {MAX_REPEATS} {{ ticktock }}")).is_ok());
+        assert_eq!(
+            parse(&format!("repeat {} {{ ticktock }}", MAX_REPEATS + 1)),
+            too_many
+        );
+        assert!(parse("repeat 10000 { repeat 10000 { ticktock } }").is_ok());
+        assert_eq!(
+            parse("repeat 10000 { repeat 10001 { ticktock } }"),
+            too_many
+        );
+        assert_eq!(
+            parse("repeat 1000 { repeat 1000 { repeat 1000 { ticktock } } }"),
+            too_many
+        );
+        // Blocks that follow each other are bounded on their own
+        assert!(parse("repeat 10000 { } repeat 10000 { repeat 10000 { } }").is_ok());
+        // Multiplying the counts cannot overflow
+        assert_eq!(
+            parse("repeat 100000000 { repeat 18446744073709551615 { } }"),
+            too_many
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::instructions::Instruction;
+
+/// Identifies the binary trace format, and its version.
+const TRACE_MAGIC: &[u8; 8] = b"HACKTRC1";
+/// The size in bytes of a single [TraceEntry] in the binary format.
+const ENTRY_SIZE: usize = 21;
+/// The number of entries after which the [Tracer] stops recording, so that leaving the tracer on
+/// while running a program indefinitely does not eat all of the memory.
+pub const MAX_TRACE_ENTRIES: usize = 10_000_000;
+
+pub const TRACE_FILE_EXTENSION: &'static str = "trace";
+pub const CSV_FILE_EXTENSION: &'static str = "csv";
+
+const READ_FLAG: u8 = 0b01;
+const WRITE_FLAG: u8 = 0b10;
+
+/// A single access to the RAM, either a read or a write, made by an instruction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MemoryAccess {
+    pub address: u16,
+    pub value: i16,
+}
+
+/// Records a single executed instruction, along with the state of the registers before and
+/// after it ran, and the RAM accesses it made. The instruction is stored as machine code, see
+/// [Instruction::machine_code].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub instruction: u16,
+    pub a_before: i16,
+    pub d_before: i16,
+    pub a_after: i16,
+    pub d_after: i16,
+    pub read: Option<MemoryAccess>,
+    pub write: Option<MemoryAccess>,
+}
+
+impl TraceEntry {
+    /// Whether this entry read from, or wrote to, an address within `range`.
+    fn touches(&self, range: &RangeInclusive<u16>) -> bool {
+        [self.read, self.write]
+            .iter()
+            .flatten()
+            .any(|access| range.contains(&access.address))
+    }
+
+    /// Writes the entry in the compact binary trace format.
+    fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut flags = 0;
+        if self.read.is_some() {
+            flags |= READ_FLAG;
+        }
+        if self.write.is_some() {
+            flags |= WRITE_FLAG;
+        }
+        let read = self.read.unwrap_or(MemoryAccess {
+            address: 0,
+            value: 0,
+        });
+        let write = self.write.unwrap_or(MemoryAccess {
+            address: 0,
+            value: 0,
+        });
+
+        let mut buffer = [0u8; ENTRY_SIZE];
+        buffer[0..2].copy_from_slice(&self.pc.to_le_bytes());
+        buffer[2..4].copy_from_slice(&self.instruction.to_le_bytes());
+        buffer[4..6].copy_from_slice(&self.a_before.to_le_bytes());
+        buffer[6..8].copy_from_slice(&self.d_before.to_le_bytes());
+        buffer[8..10].copy_from_slice(&self.a_after.to_le_bytes());
+        buffer[10..12].copy_from_slice(&self.d_after.to_le_bytes());
+        buffer[12] = flags;
+        buffer[13..15].copy_from_slice(&read.address.to_le_bytes());
+        buffer[15..17].copy_from_slice(&read.value.to_le_bytes());
+        buffer[17..19].copy_from_slice(&write.address.to_le_bytes());
+        buffer[19..21].copy_from_slice(&write.value.to_le_bytes());
+        writer.write_all(&buffer)
+    }
+
+    /// Reads an entry written by [TraceEntry::write_binary].
+    fn from_binary(buffer: &[u8; ENTRY_SIZE]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([buffer[i], buffer[i + 1]]);
+        let i16_at = |i: usize| i16::from_le_bytes([buffer[i], buffer[i + 1]]);
+        let flags = buffer[12];
+        Self {
+            pc: u16_at(0),
+            instruction: u16_at(2),
+            a_before: i16_at(4),
+            d_before: i16_at(6),
+            a_after: i16_at(8),
+            d_after: i16_at(10),
+            read: (flags & READ_FLAG != 0).then(|| MemoryAccess {
+                address: u16_at(13),
+                value: i16_at(15),
+            }),
+            write: (flags & WRITE_FLAG != 0).then(|| MemoryAccess {
+                address: u16_at(17),
+                value: i16_at(19),
+            }),
+        }
+    }
+
+    /// Writes the entry as a single CSV row, matching the header written by [Tracer::write_csv].
+    fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let access = |a: Option<MemoryAccess>| match a {
+            Some(a) => format!("{},{}", a.address, a.value),
+            None => String::from(","),
+        };
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{}",
+            self.pc,
+            Instruction::from_machine_code(self.instruction),
+            self.a_before,
+            self.d_before,
+            self.a_after,
+            self.d_after,
+            access(self.read),
+            access(self.write),
+        )
+    }
+}
+
+/// Decides which executed instructions are kept by the [Tracer]. An instruction is recorded when
+/// its PC is within `pc_range`, and, if an `address_range` is given, it accessed the RAM within
+/// that range.
+#[derive(Debug, Clone)]
+pub struct TraceFilter {
+    pub pc_range: RangeInclusive<u16>,
+    pub address_range: Option<RangeInclusive<u16>>,
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        Self {
+            pc_range: 0..=u16::MAX,
+            address_range: None,
+        }
+    }
+}
+
+impl TraceFilter {
+    /// Whether the entry passes the filter.
+    fn accepts(&self, entry: &TraceEntry) -> bool {
+        self.pc_range.contains(&entry.pc)
+            && match &self.address_range {
+                Some(range) => entry.touches(range),
+                None => true,
+            }
+    }
+}
+
+/// Records the instructions executed by the [crate::hack_cpu::CPUState], for later offline
+/// analysis. Tracing is opt in, by placing a [Tracer] into the CPU.
+#[derive(Debug)]
+pub struct Tracer {
+    pub entries: Vec<TraceEntry>,
+    pub filter: TraceFilter,
+}
+
+impl Tracer {
+    /// Creates an empty tracer, that only keeps the entries accepted by `filter`.
+    pub fn new(filter: TraceFilter) -> Self {
+        Self {
+            entries: vec![],
+            filter,
+        }
+    }
+
+    /// Records an executed instruction, unless it is rejected by the filter, or the tracer is
+    /// full.
+    pub fn record(self: &mut Self, entry: TraceEntry) {
+        if self.entries.len() < MAX_TRACE_ENTRIES && self.filter.accepts(&entry) {
+            self.entries.push(entry);
+        }
+    }
+
+    /// Whether the tracer has reached [MAX_TRACE_ENTRIES], and is no longer recording.
+    pub fn is_full(self: &Self) -> bool {
+        self.entries.len() >= MAX_TRACE_ENTRIES
+    }
+
+    /// Writes the recorded entries in the compact binary trace format.
+    pub fn write_binary<W: Write>(self: &Self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(TRACE_MAGIC)?;
+        for entry in &self.entries {
+            entry.write_binary(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the recorded entries as CSV, with a header row.
+    pub fn write_csv<W: Write>(self: &Self, writer: &mut W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "pc,instruction,a_before,d_before,a_after,d_after,read_address,read_value,write_address,write_value"
+        )?;
+        for entry in &self.entries {
+            entry.write_csv(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Saves the trace to `path`. Files with the [CSV_FILE_EXTENSION] are written as CSV, and all
+    /// others in the binary format.
+    pub fn save(self: &Self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let is_csv = path
+            .extension()
+            .is_some_and(|e| e.to_str().unwrap_or("").to_lowercase() == CSV_FILE_EXTENSION);
+        if is_csv {
+            self.write_csv(&mut writer)?;
+        } else {
+            self.write_binary(&mut writer)?;
+        }
+        writer.flush()
+    }
+}
+
+/// Reads a trace written by [Tracer::write_binary].
+pub fn read_binary<R: Read>(reader: &mut R) -> io::Result<Vec<TraceEntry>> {
+    let mut magic = [0u8; TRACE_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != TRACE_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a HACK trace file",
+        ));
+    }
+
+    let mut entries = vec![];
+    let mut buffer = [0u8; ENTRY_SIZE];
+    loop {
+        match reader.read_exact(&mut buffer) {
+            Ok(()) => entries.push(TraceEntry::from_binary(&buffer)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads a binary trace file from `path`.
+pub fn load(path: &Path) -> io::Result<Vec<TraceEntry>> {
+    read_binary(&mut BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instructions::C;
+
+    fn entry(pc: u16, read: Option<MemoryAccess>, write: Option<MemoryAccess>) -> TraceEntry {
+        TraceEntry {
+            pc,
+            instruction: Instruction::C(C::new("M", "M+1", "").unwrap()).machine_code(),
+            a_before: 17,
+            d_before: -3,
+            a_after: 17,
+            d_after: i16::MIN,
+            read,
+            write,
+        }
+    }
+
+    fn access(address: u16, value: i16) -> Option<MemoryAccess> {
+        Some(MemoryAccess { address, value })
+    }
+
+    fn tracer(filter: TraceFilter) -> Tracer {
+        let mut tracer = Tracer::new(filter);
+        tracer.record(entry(0, None, None));
+        tracer.record(entry(1, access(16, 5), None));
+        tracer.record(entry(2, None, access(24576, -1)));
+        tracer.record(entry(
+            u16::MAX,
+            access(100, i16::MIN),
+            access(101, i16::MAX),
+        ));
+        tracer
+    }
+
+    #[test]
+    fn binary_trace_round_trips() {
+        let tracer = tracer(TraceFilter::default());
+        let mut bytes = vec![];
+        tracer.write_binary(&mut bytes).unwrap();
+        assert_eq!(&bytes[..TRACE_MAGIC.len()], b"HACKTRC1");
+        assert_eq!(bytes.len(), TRACE_MAGIC.len() + 4 * ENTRY_SIZE);
+        assert_eq!(ENTRY_SIZE, 21);
+        assert_eq!(read_binary(&mut bytes.as_slice()).unwrap(), tracer.entries);
+    }
+
+    #[test]
+    fn binary_trace_ignores_a_truncated_entry() {
+        let mut bytes = vec![];
+        tracer(TraceFilter::default())
+            .write_binary(&mut bytes)
+            .unwrap();
+        bytes.pop();
+        assert_eq!(read_binary(&mut bytes.as_slice()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn binary_trace_rejects_other_files() {
+        let error = read_binary(&mut b"HACKTRC0".as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn csv_trace_has_a_row_per_entry() {
+        let mut csv = vec![];
+        tracer(TraceFilter::default()).write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            rows,
+            [
+                "pc,instruction,a_before,d_before,a_after,d_after,read_address,read_value,write_address,write_value",
+                "0,M=M+1,17,-3,17,-32768,,,,",
+                "1,M=M+1,17,-3,17,-32768,16,5,,",
+                "2,M=M+1,17,-3,17,-32768,,,24576,-1",
+                "65535,M=M+1,17,-3,17,-32768,100,-32768,101,32767",
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_keeps_entries_within_the_pc_range() {
+        let tracer = tracer(TraceFilter {
+            pc_range: 1..=2,
+            address_range: None,
+        });
+        let pcs: Vec<u16> = tracer.entries.iter().map(|e| e.pc).collect();
+        assert_eq!(pcs, [1, 2]);
+    }
+
+    #[test]
+    fn filter_keeps_entries_reading_or_writing_the_address_range() {
+        let tracer = tracer(TraceFilter {
+            pc_range: 0..=u16::MAX,
+            address_range: Some(16..=101),
+        });
+        let pcs: Vec<u16> = tracer.entries.iter().map(|e| e.pc).collect();
+        assert_eq!(pcs, [1, u16::MAX]);
+    }
+}